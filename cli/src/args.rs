@@ -1,3 +1,6 @@
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
 use clap::{Parser, ValueEnum};
 use clap_num::maybe_hex;
 
@@ -17,6 +20,52 @@ pub enum FileFormat {
     // Mos, // MOS object file format https://en.wikipedia.org/wiki/MOS_Technology_file_format
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum CpuKind {
+    /// NMOS 6502, documented opcodes only
+    Mos6502,
+    /// NMOS 6502 including the undocumented ("illegal") opcodes
+    Mos6502Undocumented,
+    /// CMOS 65C02 (WDC), including the bit manipulation instructions
+    Wdc65c02,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum IllegalOpcodes {
+    /// Stop with an error at the illegal opcode
+    Error,
+    /// Skip the opcode, with the length and cycles it has on the NMOS 6502
+    Nop,
+    /// Halt the CPU like the JAM opcodes
+    Jam,
+    /// Execute a BRK instead
+    Brk,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum Brk {
+    /// Stop execution after a BRK
+    Stop,
+    /// Run the BRK handler via the IRQ vector at 0xFFFE, like the real CPU
+    Interrupt,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ClockSpeed {
+    /// As fast as the host allows
+    Max,
+    MHz(f64),
+}
+
+impl ClockSpeed {
+    pub fn as_hz(&self) -> Option<f64> {
+        match self {
+            ClockSpeed::Max => None,
+            ClockSpeed::MHz(mhz) => Some(mhz * 1_000_000.0),
+        }
+    }
+}
+
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct CliArgs {
@@ -32,7 +81,8 @@ pub struct CliArgs {
     pub format: Option<FileFormat>,
 
     #[arg(short, long, required = false, value_parser = maybe_hex::<u16>)]
-    /// Load address (u16) for binary to be loaded to (inferred for .prg); if no start_addr it is also used as start address
+    /// Load address (u16) for binary to be loaded to (inferred for .prg); if no start_addr it is also used as start address,
+    /// unless the binary covers the reset vector at 0xFFFC
     pub load_address: Option<u16>,
 
     #[arg(short, long, required = false, value_parser = maybe_hex::<u16>)]
@@ -42,4 +92,100 @@ pub struct CliArgs {
     #[arg(short, long)]
     /// loaded binary is read-only in memory (simulate ROM)
     pub read_only: bool,
+
+    #[arg(short = 'B', long = "break", value_name = "ADDR", value_parser = maybe_hex::<u16>)]
+    /// Set a breakpoint at address (u16), e.g. 0x1234; can be repeated
+    pub breakpoints: Vec<u16>,
+
+    #[arg(value_enum, ignore_case = true, long, default_value = "mos6502")]
+    /// CPU variant to emulate
+    pub cpu: CpuKind,
+
+    #[arg(value_enum, ignore_case = true, long, default_value = "brk")]
+    /// How to execute opcodes the CPU variant does not define, e.g. after a jump into data
+    pub illegal_opcodes: IllegalOpcodes,
+
+    #[arg(value_enum, ignore_case = true, long, default_value = "stop")]
+    /// What a BRK instruction does; 'interrupt' is for programs with a BRK handler, e.g. monitors
+    pub brk: Brk,
+
+    #[arg(long, value_name = "MHZ", default_value = "max", value_parser = parse_clock)]
+    /// Target clock speed in MHz for runs, e.g. 1.023; 'max' runs as fast as the host allows
+    pub clock: ClockSpeed,
+
+    #[arg(long, value_name = "CYCLES")]
+    /// Stop a run after this many cycles
+    pub max_cycles: Option<u64>,
+
+    #[arg(long, value_name = "INSTRUCTIONS")]
+    /// Stop a run after this many instructions
+    pub max_instructions: Option<u64>,
+
+    #[arg(long, value_name = "SECONDS", value_parser = parse_timeout)]
+    /// Stop a run after this many seconds (wall-clock), e.g. 2.5
+    pub timeout: Option<Duration>,
+
+    #[arg(long)]
+    /// Stop a run at an endless loop, e.g. JMP * or a branch to itself
+    pub stop_on_loop: bool,
+
+    #[arg(long, value_name = "ADDR", value_parser = maybe_hex::<u16>)]
+    /// Address of the endless loop that signals success, as in functional test ROMs; a run ending
+    /// in a loop elsewhere fails. Implies --stop-on-loop
+    pub success_address: Option<u16>,
+
+    #[arg(long, value_name = "FILE")]
+    /// Write a trace line per executed instruction to a file, in the column layout of nestest.log
+    pub trace: Option<String>,
+
+    #[arg(long = "trace-range", value_name = "RANGE", requires = "trace", value_parser = parse_address_range)]
+    /// Only trace instructions in an address range, e.g. 0x0200..0x0300 or 0x0200..=0x02FF;
+    /// can be repeated
+    pub trace_ranges: Vec<RangeInclusive<u16>>,
+
+    #[arg(long, value_name = "FILE")]
+    /// Restore machine state from a file saved with --save-state or the debugger's save command;
+    /// unless a start address is given, execution continues at the restored PC
+    pub load_state: Option<String>,
+
+    #[arg(long, value_name = "FILE")]
+    /// Save machine state to a file when the program finishes or the debugger quits
+    pub save_state: Option<String>,
+}
+
+fn parse_clock(arg: &str) -> Result<ClockSpeed, String> {
+    if arg.eq_ignore_ascii_case("max") {
+        return Ok(ClockSpeed::Max);
+    }
+    let mhz: f64 = arg.parse().map_err(|e| format!("{}", e))?;
+    if !mhz.is_finite() || mhz <= 0.0 {
+        return Err("clock speed must be a positive number of MHz, or 'max'".to_string());
+    }
+    Ok(ClockSpeed::MHz(mhz))
+}
+
+fn parse_address_range(arg: &str) -> Result<RangeInclusive<u16>, String> {
+    let (start, end, is_exclusive) = match arg.split_once("..") {
+        Some((start, end)) => match end.strip_prefix('=') {
+            Some(end) => (start, end, false),
+            None => (start, end, true),
+        },
+        None => return Err("expected a range like 0x0200..0x0300".to_string()),
+    };
+    let start = maybe_hex::<u16>(start)?;
+    let end = maybe_hex::<u16>(end)?;
+    let end = if is_exclusive {
+        end.checked_sub(1)
+    } else {
+        Some(end)
+    };
+    match end {
+        Some(end) if end >= start => Ok(start..=end),
+        _ => Err("address range is empty".to_string()),
+    }
+}
+
+fn parse_timeout(arg: &str) -> Result<Duration, String> {
+    let seconds: f64 = arg.parse().map_err(|e| format!("{}", e))?;
+    Duration::try_from_secs_f64(seconds).map_err(|e| e.to_string())
 }
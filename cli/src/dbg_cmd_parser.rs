@@ -1,19 +1,50 @@
 use std::num::ParseIntError;
+use std::ops::RangeInclusive;
+use std::sync::LazyLock;
 
 use pest::Parser;
-use pest::iterators::Pair;
+use pest::iterators::{Pair, Pairs};
+use pest::pratt_parser::{Assoc, Op, PrattParser};
 use pest_derive::Parser;
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+use mos6502_emulator::{BinaryOp, Expr, FlagName, RegisterName, UnaryOp, WatchKind, Watchpoint};
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum DebugCommand {
+    Back,
+    // at the current PC if no address is given:
+    Break {
+        address: Option<u16>,
+        condition: Option<BreakCondition>,
+        ignore_count: u32,
+    },
     Continue,
+    // breakpoint commands apply to all breakpoints if no number is given:
+    Delete(Option<usize>),
+    Disable(Option<usize>),
     Disassemble(AddressRange),
+    Enable(Option<usize>),
     Help,
+    InfoBreakpoints,
     Invalid,
     Memory(AddressRange),
     Quit,
     Repeat,
+    Restore(String),
+    ReverseContinue,
+    Save(String),
     Step,
+    // traces instructions in the address ranges, or all if there are none:
+    TraceOn(String, Vec<RangeInclusive<u16>>),
+    TraceOff,
+    Watch(Watchpoint),
+}
+
+// the condition as typed by the user is kept for listing the breakpoint
+#[derive(Debug, PartialEq, Clone)]
+pub struct BreakCondition {
+    pub text: String,
+    pub expr: Expr,
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -52,6 +83,23 @@ impl From<ParseIntError> for DebugCmdError {
 #[grammar = "dbg_cmd.pest"]
 struct DbgCmdParser;
 
+// lowest precedence first; like in Rust, bit operators bind tighter than comparisons
+static EXPR_PARSER: LazyLock<PrattParser<Rule>> = LazyLock::new(|| {
+    PrattParser::new()
+        .op(Op::infix(Rule::op_or, Assoc::Left))
+        .op(Op::infix(Rule::op_and, Assoc::Left))
+        .op(Op::infix(Rule::op_eq, Assoc::Left)
+            | Op::infix(Rule::op_ne, Assoc::Left)
+            | Op::infix(Rule::op_lt, Assoc::Left)
+            | Op::infix(Rule::op_le, Assoc::Left)
+            | Op::infix(Rule::op_gt, Assoc::Left)
+            | Op::infix(Rule::op_ge, Assoc::Left))
+        .op(Op::infix(Rule::op_bit_or, Assoc::Left))
+        .op(Op::infix(Rule::op_bit_and, Assoc::Left))
+        .op(Op::infix(Rule::op_add, Assoc::Left) | Op::infix(Rule::op_sub, Assoc::Left))
+        .op(Op::prefix(Rule::op_not))
+});
+
 pub fn parse_cmd(input: &str) -> Result<DebugCommand, DebugCmdError> {
     if input.is_empty() {
         return Ok(DebugCommand::Repeat);
@@ -67,12 +115,23 @@ pub fn parse_cmd(input: &str) -> Result<DebugCommand, DebugCmdError> {
     let mut dbg_cmd = DebugCommand::Invalid;
     for verb in parsed_cmd.next().unwrap().into_inner() {
         match verb.as_rule() {
+            Rule::back_verb => dbg_cmd = DebugCommand::Back,
+            Rule::break_point => dbg_cmd = process_break(verb)?,
             Rule::continue_run => dbg_cmd = DebugCommand::Continue,
+            Rule::delete => dbg_cmd = DebugCommand::Delete(process_bp_number(verb)?),
+            Rule::disable => dbg_cmd = DebugCommand::Disable(process_bp_number(verb)?),
             Rule::disassemble => dbg_cmd = DebugCommand::Disassemble(process_addr_range(verb)?),
+            Rule::enable => dbg_cmd = DebugCommand::Enable(process_bp_number(verb)?),
             Rule::help_verb => dbg_cmd = DebugCommand::Help,
+            Rule::info_breakpoints => dbg_cmd = DebugCommand::InfoBreakpoints,
             Rule::memory => dbg_cmd = DebugCommand::Memory(process_addr_range(verb)?),
             Rule::quit_verb => dbg_cmd = DebugCommand::Quit,
+            Rule::reverse_continue => dbg_cmd = DebugCommand::ReverseContinue,
+            Rule::restore => dbg_cmd = DebugCommand::Restore(process_file_name(verb)),
+            Rule::save => dbg_cmd = DebugCommand::Save(process_file_name(verb)),
             Rule::step_verb => dbg_cmd = DebugCommand::Step,
+            Rule::trace => dbg_cmd = process_trace(verb)?,
+            Rule::watch => dbg_cmd = DebugCommand::Watch(process_watch(verb)?),
             Rule::EOI => {}
             _ => unreachable!(),
         };
@@ -95,6 +154,196 @@ fn process_addr_range(pair: Pair<Rule>) -> Result<AddressRange, DebugCmdError> {
     Ok(b.build())
 }
 
+fn process_break(pair: Pair<Rule>) -> Result<DebugCommand, DebugCmdError> {
+    let mut address = None;
+    let mut condition = None;
+    let mut ignore_count = 0;
+    for inner_pair in pair.into_inner() {
+        match inner_pair.as_rule() {
+            Rule::dec_address => address = Some(inner_pair.as_str().parse::<u16>()?),
+            Rule::hex_address => address = Some(u16::from_str_radix(inner_pair.as_str(), 16)?),
+            Rule::expr => {
+                condition = Some(BreakCondition {
+                    text: inner_pair.as_str().trim().to_string(),
+                    expr: process_expr(inner_pair.into_inner())?,
+                })
+            }
+            Rule::ignore_count => {
+                ignore_count = inner_pair.as_str().parse::<u32>().map_err(|_| {
+                    DebugCmdError::InvalidCommand(format!(
+                        "invalid ignore count: {}",
+                        inner_pair.as_str()
+                    ))
+                })?
+            }
+            _ => unreachable!(),
+        };
+    }
+    Ok(DebugCommand::Break {
+        address,
+        condition,
+        ignore_count,
+    })
+}
+
+fn process_expr(pairs: Pairs<Rule>) -> Result<Expr, DebugCmdError> {
+    EXPR_PARSER
+        .map_primary(|primary| match primary.as_rule() {
+            Rule::number => Ok(Expr::Number(process_number(primary)?)),
+            Rule::register => Ok(Expr::Register(
+                match primary.as_str().to_ascii_lowercase().as_str() {
+                    "a" => RegisterName::Accumulator,
+                    "x" => RegisterName::IndexX,
+                    "y" => RegisterName::IndexY,
+                    "sp" => RegisterName::StackPointer,
+                    _ => RegisterName::ProgramCounter,
+                },
+            )),
+            Rule::flag => Ok(Expr::Flag(
+                match primary.as_str().to_ascii_lowercase().as_str() {
+                    "n" => FlagName::Negative,
+                    "v" => FlagName::Overflow,
+                    "d" => FlagName::Decimal,
+                    "i" => FlagName::InterruptDisable,
+                    "z" => FlagName::Zero,
+                    _ => FlagName::Carry,
+                },
+            )),
+            Rule::mem_byte => Ok(Expr::Byte(Box::new(process_inner_expr(primary)?))),
+            Rule::mem_word => Ok(Expr::Word(Box::new(process_inner_expr(primary)?))),
+            Rule::expr => process_expr(primary.into_inner()),
+            _ => unreachable!(),
+        })
+        .map_prefix(|_not, operand| Ok(Expr::Unary(UnaryOp::Not, Box::new(operand?))))
+        .map_infix(|left, op, right| {
+            let op = match op.as_rule() {
+                Rule::op_or => BinaryOp::Or,
+                Rule::op_and => BinaryOp::And,
+                Rule::op_eq => BinaryOp::Eq,
+                Rule::op_ne => BinaryOp::Ne,
+                Rule::op_lt => BinaryOp::Lt,
+                Rule::op_le => BinaryOp::Le,
+                Rule::op_gt => BinaryOp::Gt,
+                Rule::op_ge => BinaryOp::Ge,
+                Rule::op_add => BinaryOp::Add,
+                Rule::op_sub => BinaryOp::Sub,
+                Rule::op_bit_and => BinaryOp::BitAnd,
+                Rule::op_bit_or => BinaryOp::BitOr,
+                _ => unreachable!(),
+            };
+            Ok(Expr::Binary(op, Box::new(left?), Box::new(right?)))
+        })
+        .parse(pairs)
+}
+
+// the address expression inside mem[...] or word[...]
+fn process_inner_expr(pair: Pair<Rule>) -> Result<Expr, DebugCmdError> {
+    process_expr(pair.into_inner().next().unwrap().into_inner())
+}
+
+fn process_number(pair: Pair<Rule>) -> Result<u16, DebugCmdError> {
+    let digits = pair.into_inner().next().unwrap();
+    match digits.as_rule() {
+        Rule::hex_address => Ok(u16::from_str_radix(digits.as_str(), 16)?),
+        _ => Ok(digits.as_str().parse::<u16>()?),
+    }
+}
+
+fn process_bp_number(pair: Pair<Rule>) -> Result<Option<usize>, DebugCmdError> {
+    match pair.into_inner().next() {
+        Some(number) => number.as_str().parse::<usize>().map(Some).map_err(|_| {
+            DebugCmdError::InvalidCommand(format!("invalid breakpoint number: {}", number.as_str()))
+        }),
+        None => Ok(None),
+    }
+}
+
+fn process_watch(pair: Pair<Rule>) -> Result<Watchpoint, DebugCmdError> {
+    let mut kind = WatchKind::Write;
+    let mut range = 0..=0;
+    let mut value = None;
+    let mut stop_before = false;
+    for inner_pair in pair.into_inner() {
+        match inner_pair.as_rule() {
+            Rule::watch_verb => kind = WatchKind::Write,
+            Rule::rwatch_verb => kind = WatchKind::Read,
+            Rule::awatch_verb => kind = WatchKind::Access,
+            Rule::plain_range => range = process_plain_range(inner_pair, "watched")?,
+            Rule::watch_value => value = Some(process_byte(inner_pair)?),
+            Rule::watch_before => stop_before = true,
+            Rule::watch_after => stop_before = false,
+            _ => unreachable!(),
+        };
+    }
+    Ok(Watchpoint {
+        range,
+        kind,
+        value,
+        stop_before,
+    })
+}
+
+fn process_trace(pair: Pair<Rule>) -> Result<DebugCommand, DebugCmdError> {
+    let trace = pair.into_inner().next().unwrap();
+    if trace.as_rule() == Rule::trace_off {
+        return Ok(DebugCommand::TraceOff);
+    }
+    let mut inner_pairs = trace.into_inner();
+    let file_name = inner_pairs.next().unwrap().as_str().to_string();
+    let ranges = inner_pairs
+        .map(|range| process_plain_range(range, "traced"))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(DebugCommand::TraceOn(file_name, ranges))
+}
+
+// a single address, or an exclusive or inclusive range of addresses
+fn process_plain_range(
+    pair: Pair<Rule>,
+    purpose: &str,
+) -> Result<RangeInclusive<u16>, DebugCmdError> {
+    let mut addresses: Vec<u16> = vec![];
+    let mut is_exclusive = true;
+    for inner_pair in pair.into_inner() {
+        match inner_pair.as_rule() {
+            Rule::dec_address => addresses.push(inner_pair.as_str().parse::<u16>()?),
+            Rule::hex_address => addresses.push(u16::from_str_radix(inner_pair.as_str(), 16)?),
+            Rule::inclusive => is_exclusive = false,
+            Rule::exclusive => is_exclusive = true,
+            _ => unreachable!(),
+        };
+    }
+    let start = addresses[0];
+    let end = match addresses.get(1) {
+        Some(end) if is_exclusive => {
+            if *end <= start {
+                return Err(DebugCmdError::InvalidCommand(format!(
+                    "{} range is empty",
+                    purpose
+                )));
+            }
+            end - 1
+        }
+        Some(end) => *end,
+        None => start,
+    };
+    Ok(start..=end)
+}
+
+fn process_byte(pair: Pair<Rule>) -> Result<u8, DebugCmdError> {
+    let digits = pair.into_inner().next().unwrap();
+    let value = match digits.as_rule() {
+        Rule::hex_address => u8::from_str_radix(digits.as_str(), 16),
+        _ => digits.as_str().parse::<u8>(),
+    };
+    value.map_err(|_| {
+        DebugCmdError::InvalidCommand(format!("value must be a byte: {}", digits.as_str()))
+    })
+}
+
+fn process_file_name(pair: Pair<Rule>) -> String {
+    pair.into_inner().next().unwrap().as_str().to_string()
+}
+
 struct AddressRangeBuilder {
     start_addr: Option<u16>,
     end_addr: Option<u16>,
@@ -135,8 +384,8 @@ impl AddressRangeBuilder {
         if self.start_addr.is_none() {
             return AddressRange::Default;
         }
-        if self.line_cnt.is_some() {
-            return AddressRange::StartLines((self.start_addr.unwrap(), self.line_cnt.unwrap()));
+        if let Some(line_cnt) = self.line_cnt {
+            return AddressRange::StartLines((self.start_addr.unwrap(), line_cnt));
         }
         if self.end_addr.is_none() {
             return AddressRange::StartEnd((
@@ -349,6 +598,24 @@ mod tests {
     }
 
     // ======== simple commands
+    #[test]
+    fn parse_back() -> Result<(), DebugCmdError> {
+        assert_eq!(DebugCommand::Back, parse_cmd("back")?);
+        assert_eq!(DebugCommand::Back, parse_cmd(" Reverse-Step ")?);
+        assert_eq!(DebugCommand::Back, parse_cmd("rs")?);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_reverse_continue() -> Result<(), DebugCmdError> {
+        assert_eq!(
+            DebugCommand::ReverseContinue,
+            parse_cmd("reverse-continue")?
+        );
+        assert_eq!(DebugCommand::ReverseContinue, parse_cmd("rc")?);
+        Ok(())
+    }
+
     #[test]
     fn parse_continue() -> Result<(), DebugCmdError> {
         let cmd = parse_cmd("  c ")?;
@@ -356,6 +623,174 @@ mod tests {
         Ok(())
     }
 
+    fn plain_break(address: Option<u16>) -> DebugCommand {
+        DebugCommand::Break {
+            address,
+            condition: None,
+            ignore_count: 0,
+        }
+    }
+
+    fn binary(op: BinaryOp, left: Expr, right: Expr) -> Expr {
+        Expr::Binary(op, Box::new(left), Box::new(right))
+    }
+
+    fn condition_of(input: &str) -> Result<Expr, DebugCmdError> {
+        match parse_cmd(input)? {
+            DebugCommand::Break {
+                condition: Some(condition),
+                ..
+            } => Ok(condition.expr),
+            cmd => panic!("unexpected command: {:?}", cmd),
+        }
+    }
+
+    #[test]
+    fn parse_break() -> Result<(), DebugCmdError> {
+        assert_eq!(plain_break(Some(0x0300)), parse_cmd("break $0300")?);
+        assert_eq!(plain_break(Some(768)), parse_cmd("b 768")?);
+        assert_eq!(plain_break(None), parse_cmd(" B ")?);
+        assert!(parse_cmd("break sp").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_break_condition_and_ignore_count() -> Result<(), DebugCmdError> {
+        let expected = DebugCommand::Break {
+            address: Some(0x0205),
+            condition: Some(BreakCondition {
+                text: "A == $12 && mem[$40] > 3".to_string(),
+                expr: binary(
+                    BinaryOp::And,
+                    binary(
+                        BinaryOp::Eq,
+                        Expr::Register(RegisterName::Accumulator),
+                        Expr::Number(0x12),
+                    ),
+                    binary(
+                        BinaryOp::Gt,
+                        Expr::Byte(Box::new(Expr::Number(0x40))),
+                        Expr::Number(3),
+                    ),
+                ),
+            }),
+            ignore_count: 0,
+        };
+        assert_eq!(
+            expected,
+            parse_cmd("break $0205 if A == $12 && mem[$40] > 3")?
+        );
+
+        let expected = DebugCommand::Break {
+            address: Some(0x0300),
+            condition: None,
+            ignore_count: 100,
+        };
+        assert_eq!(expected, parse_cmd("break $0300 ignore 100")?);
+
+        let cmd = parse_cmd("b IF !c Ignore 2")?;
+        let DebugCommand::Break {
+            address: None,
+            condition: Some(condition),
+            ignore_count: 2,
+        } = cmd
+        else {
+            panic!("unexpected command: {:?}", cmd);
+        };
+        assert_eq!("!c", condition.text);
+        assert_eq!(
+            Expr::Unary(UnaryOp::Not, Box::new(Expr::Flag(FlagName::Carry))),
+            condition.expr
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_break_condition_precedence() -> Result<(), DebugCmdError> {
+        let x = || Expr::Register(RegisterName::IndexX);
+        let n = Expr::Number;
+        // bit operators bind tighter than comparisons, like in Rust:
+        assert_eq!(
+            binary(
+                BinaryOp::Eq,
+                binary(BinaryOp::BitAnd, Expr::Word(Box::new(n(0xFE))), n(0x80)),
+                n(0x80)
+            ),
+            condition_of("b if word[$fe] & $80 == $80")?
+        );
+        assert_eq!(
+            binary(
+                BinaryOp::Or,
+                binary(BinaryOp::Lt, x(), n(2)),
+                binary(
+                    BinaryOp::And,
+                    Expr::Flag(FlagName::Zero),
+                    binary(
+                        BinaryOp::Ge,
+                        Expr::Register(RegisterName::StackPointer),
+                        n(0xF0)
+                    )
+                )
+            ),
+            condition_of("b if x < 2 || z && SP >= 0xF0")?
+        );
+        assert_eq!(
+            binary(
+                BinaryOp::Sub,
+                binary(
+                    BinaryOp::Sub,
+                    Expr::Register(RegisterName::ProgramCounter),
+                    n(1)
+                ),
+                n(2)
+            ),
+            condition_of("b if pc - 1 - 2")?
+        );
+        assert_eq!(
+            Expr::Byte(Box::new(binary(
+                BinaryOp::Add,
+                n(0x40),
+                binary(BinaryOp::BitOr, x(), Expr::Register(RegisterName::IndexY))
+            ))),
+            condition_of("b if MEM[$40 + (x | y)]")?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_break_condition_invalid() {
+        assert!(parse_cmd("break $0300 if").is_err());
+        assert!(parse_cmd("break $0300 if A ==").is_err());
+        assert!(parse_cmd("break $0300 if q == 1").is_err());
+        assert!(parse_cmd("break $0300 if mem[$40").is_err());
+        assert!(parse_cmd("break $0300 ignore").is_err());
+        assert!(parse_cmd("break $0300 if a == 70000").is_err());
+    }
+
+    #[test]
+    fn parse_breakpoint_management() -> Result<(), DebugCmdError> {
+        assert_eq!(DebugCommand::Delete(Some(2)), parse_cmd("delete 2")?);
+        assert_eq!(DebugCommand::Delete(None), parse_cmd("d")?);
+        assert_eq!(DebugCommand::Disable(Some(1)), parse_cmd("disable 1")?);
+        assert_eq!(DebugCommand::Disable(None), parse_cmd("Disable")?);
+        assert_eq!(DebugCommand::Enable(Some(3)), parse_cmd("enable 3")?);
+        assert_eq!(
+            DebugCommand::InfoBreakpoints,
+            parse_cmd("info breakpoints")?
+        );
+        assert_eq!(DebugCommand::InfoBreakpoints, parse_cmd("i b")?);
+        // disassemble still takes precedence for its short form:
+        assert_eq!(
+            DebugCommand::Disassemble(AddressRange::Default),
+            parse_cmd("di")?
+        );
+        assert!(matches!(
+            parse_cmd("delete 99999999999999999999999"),
+            Err(DebugCmdError::InvalidCommand(_))
+        ));
+        Ok(())
+    }
+
     #[test]
     fn parse_help() -> Result<(), DebugCmdError> {
         let cmd = parse_cmd("  help ")?;
@@ -383,4 +818,95 @@ mod tests {
         assert_eq!(DebugCommand::Step, cmd);
         Ok(())
     }
+
+    #[test]
+    fn parse_save_restore() -> Result<(), DebugCmdError> {
+        let cmd = parse_cmd("save /tmp/bug-42.state")?;
+        assert_eq!(DebugCommand::Save("/tmp/bug-42.state".to_string()), cmd);
+        let cmd = parse_cmd("  Restore  bug.state ")?;
+        assert_eq!(DebugCommand::Restore("bug.state".to_string()), cmd);
+        assert!(parse_cmd("save").is_err());
+        Ok(())
+    }
+
+    // ======== watchpoint commands
+    fn watchpoint(
+        range: std::ops::RangeInclusive<u16>,
+        kind: WatchKind,
+        value: Option<u8>,
+    ) -> DebugCommand {
+        DebugCommand::Watch(Watchpoint {
+            range,
+            kind,
+            value,
+            stop_before: false,
+        })
+    }
+
+    #[test]
+    fn parse_watch() -> Result<(), DebugCmdError> {
+        let cmd = parse_cmd("watch $0200")?;
+        assert_eq!(watchpoint(0x0200..=0x0200, WatchKind::Write, None), cmd);
+        let cmd = parse_cmd("rwatch 0x0200..0x0210")?;
+        assert_eq!(watchpoint(0x0200..=0x020F, WatchKind::Read, None), cmd);
+        let cmd = parse_cmd("AWatch 512..=527 == $ff")?;
+        assert_eq!(
+            watchpoint(0x0200..=0x020F, WatchKind::Access, Some(0xFF)),
+            cmd
+        );
+        let cmd = parse_cmd("watch $D012==42")?;
+        assert_eq!(watchpoint(0xD012..=0xD012, WatchKind::Write, Some(42)), cmd);
+        let cmd = parse_cmd("watch $0200 after")?;
+        assert_eq!(watchpoint(0x0200..=0x0200, WatchKind::Write, None), cmd);
+        let cmd = parse_cmd("rwatch $0200 == 7 Before")?;
+        assert_eq!(
+            DebugCommand::Watch(Watchpoint {
+                range: 0x0200..=0x0200,
+                kind: WatchKind::Read,
+                value: Some(7),
+                stop_before: true,
+            }),
+            cmd
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_trace() -> Result<(), DebugCmdError> {
+        assert_eq!(
+            DebugCommand::TraceOn("trace.log".to_string(), vec![]),
+            parse_cmd("trace on trace.log")?
+        );
+        assert_eq!(
+            DebugCommand::TraceOn(
+                "/tmp/t.log".to_string(),
+                vec![0x0200..=0x02FF, 0xD012..=0xD012]
+            ),
+            parse_cmd("Trace ON /tmp/t.log $0200..$0300 0xD012")?
+        );
+        assert_eq!(DebugCommand::TraceOff, parse_cmd(" trace off ")?);
+        assert!(parse_cmd("trace").is_err());
+        assert!(parse_cmd("trace on").is_err());
+        assert_eq!(
+            parse_cmd("trace on t.log $0300..$0200"),
+            Err(DebugCmdError::InvalidCommand(
+                "traced range is empty".to_string()
+            ))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_watch_invalid() {
+        assert!(parse_cmd("watch").is_err());
+        assert!(parse_cmd("watch pc").is_err());
+        assert!(parse_cmd("watch $0200..$0200").is_err());
+        assert!(parse_cmd("watch $0200 during").is_err());
+        assert_eq!(
+            parse_cmd("watch $0200 == 256"),
+            Err(DebugCmdError::InvalidCommand(
+                "value must be a byte: 256".to_string()
+            ))
+        );
+    }
 }
@@ -1,15 +1,55 @@
 use std::cmp;
-use std::io;
+use std::fs;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+use anyhow::Context;
 
 use crate::console_io::StdIo;
-use crate::dbg_cmd_parser::{AddressRange, DebugCmdError, DebugCommand, parse_cmd};
-use mos6502_emulator::{Cpu, CpuError, CpuRegisterSnapshot};
+use crate::dbg_cmd_parser::{AddressRange, BreakCondition, DebugCmdError, DebugCommand, parse_cmd};
+use mos6502_emulator::{
+    Cpu, CpuError, CpuRegisterSnapshot, RunLimits, StopReason, Tracer, WatchKind, Watchpoint,
+};
+
+// number of steps that can be undone while debugging
+const HISTORY_LIMIT: usize = 10_000;
+
+// numbered like in gdb; disabled breakpoints are removed from the CPU, but kept here
+// with the hits they still ignore
+#[derive(Debug, Clone, PartialEq)]
+struct Breakpoint {
+    number: usize,
+    address: u16,
+    enabled: bool,
+    condition: Option<BreakCondition>,
+    ignore_count: u32,
+}
+
+impl Breakpoint {
+    fn arm(&self, cpu: &mut Box<dyn Cpu>) {
+        let condition = self.condition.as_ref().map(|c| c.expr.clone());
+        cpu.add_conditional_breakpoint(self.address, condition, self.ignore_count);
+    }
+}
+
+// numbered together with the breakpoints; disabled watchpoints are removed from the CPU
+#[derive(Debug, Clone, PartialEq)]
+struct NumberedWatchpoint {
+    number: usize,
+    enabled: bool,
+    watchpoint: Watchpoint,
+}
 
 pub struct Debugger<'a> {
     stdio: &'a mut dyn StdIo,
     last_cmd: DebugCommand,
     last_prog_addr: Option<u16>,
     last_mem_addr: Option<u16>,
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<NumberedWatchpoint>,
+    next_breakpoint: usize,
+    // opt-in like for runs, as programs polling a device look like endless loops
+    stop_on_loop: bool,
 }
 
 impl Debugger<'_> {
@@ -19,27 +59,55 @@ impl Debugger<'_> {
             last_cmd: DebugCommand::Invalid,
             last_prog_addr: None,
             last_mem_addr: None,
+            breakpoints: vec![],
+            watchpoints: vec![],
+            next_breakpoint: 1,
+            stop_on_loop: false,
         }
     }
 
+    /// Stops continue at an endless loop, like --stop-on-loop does for runs.
+    pub fn set_stop_on_loop(&mut self, enabled: bool) {
+        self.stop_on_loop = enabled;
+    }
+
     pub fn debug_loop(
         &mut self,
         cpu: &mut Box<dyn Cpu>,
     ) -> Result<CpuRegisterSnapshot, DebugCmdError> {
+        cpu.set_history_limit(HISTORY_LIMIT);
+        // e.g. preset from the command line:
+        self.sync_breakpoints(cpu);
+        self.sync_watchpoints(cpu);
         self.print_snapshot(cpu, cpu.get_register_snapshot())?;
         loop {
             let cmd = self.get_user_input()?;
             match cmd {
                 DebugCommand::Step => {
-                    let snapshot = cpu.step()?;
+                    let (reason, snapshot) = cpu.run_for_instructions(1)?;
+                    self.print_stop_reason(reason);
                     self.print_snapshot(cpu, snapshot)?;
                     self.last_prog_addr = None;
                 }
                 DebugCommand::Continue => {
-                    let snapshot = cpu.run(Some(cpu.get_pc()))?;
+                    let limits = RunLimits {
+                        stop_on_loop: self.stop_on_loop,
+                        ..Default::default()
+                    };
+                    let (reason, snapshot) = cpu.run_limited(limits)?;
+                    self.print_stop_reason(reason);
+                    self.print_breakpoint_hit(snapshot.program_counter);
                     self.print_snapshot(cpu, snapshot)?;
                     self.last_prog_addr = None;
                 }
+                DebugCommand::Back => {
+                    let snapshot = cpu.step_back()?;
+                    self.print_history_snapshot(cpu, snapshot)?;
+                }
+                DebugCommand::ReverseContinue => {
+                    let snapshot = cpu.reverse_continue()?;
+                    self.print_history_snapshot(cpu, snapshot)?;
+                }
                 DebugCommand::Disassemble(addr_range) => {
                     let (start, end, line_cnt) =
                         calculate_range(self.last_prog_addr, addr_range, cpu);
@@ -70,6 +138,101 @@ impl Debugger<'_> {
                     }
                     self.last_mem_addr = Some(next_addr);
                 }
+                DebugCommand::Save(file_name) => match save_state(cpu.as_ref(), &file_name) {
+                    Ok(()) => {
+                        self.writeln(format!("Saved machine state to '{}'", file_name).as_str())
+                    }
+                    Err(e) => self.writeln(format!("{:#}", e).as_str()),
+                },
+                DebugCommand::Restore(file_name) => match restore_state(cpu.as_mut(), &file_name) {
+                    Ok(()) => {
+                        self.writeln(
+                            format!("Restored machine state from '{}'", file_name).as_str(),
+                        );
+                        self.print_snapshot(cpu, cpu.get_register_snapshot())?;
+                        self.last_prog_addr = None;
+                        self.last_mem_addr = None;
+                        // the restored state brings its own breakpoints:
+                        self.breakpoints.clear();
+                        self.sync_breakpoints(cpu);
+                    }
+                    Err(e) => self.writeln(format!("{:#}", e).as_str()),
+                },
+                DebugCommand::Break {
+                    address,
+                    condition,
+                    ignore_count,
+                } => {
+                    let address = address.unwrap_or(cpu.get_pc());
+                    // a new breakpoint replaces the one at the same address:
+                    self.breakpoints.retain(|b| b.address != address);
+                    let number = self.add_breakpoint(address, condition, ignore_count);
+                    self.breakpoints.last().unwrap().arm(cpu);
+                    self.writeln(format!("Breakpoint {} at {:04X}", number, address).as_str());
+                }
+                DebugCommand::Delete(number) => {
+                    let (breakpoints, watchpoints) = self.select_breakpoints(number);
+                    for bp in breakpoints {
+                        if bp.enabled {
+                            cpu.remove_breakpoint(bp.address);
+                        }
+                        self.breakpoints.retain(|b| b.number != bp.number);
+                        self.writeln(format!("Deleted breakpoint {}", bp.number).as_str());
+                    }
+                    for wp in watchpoints {
+                        if wp.enabled {
+                            cpu.remove_watchpoint(&wp.watchpoint);
+                        }
+                        self.watchpoints.retain(|w| w.number != wp.number);
+                        self.writeln(format!("Deleted watchpoint {}", wp.number).as_str());
+                    }
+                }
+                DebugCommand::Disable(number) => {
+                    self.update_ignore_counts(cpu);
+                    let (breakpoints, watchpoints) = self.select_breakpoints(number);
+                    for bp in breakpoints {
+                        cpu.remove_breakpoint(bp.address);
+                        self.set_breakpoint_enabled(bp.number, false);
+                    }
+                    for wp in watchpoints.iter().filter(|w| w.enabled) {
+                        cpu.remove_watchpoint(&wp.watchpoint);
+                        self.set_watchpoint_enabled(wp.number, false);
+                    }
+                }
+                DebugCommand::Enable(number) => {
+                    let (breakpoints, watchpoints) = self.select_breakpoints(number);
+                    for bp in breakpoints.iter().filter(|b| !b.enabled) {
+                        bp.arm(cpu);
+                        self.set_breakpoint_enabled(bp.number, true);
+                    }
+                    for wp in watchpoints.iter().filter(|w| !w.enabled) {
+                        cpu.add_watchpoint(wp.watchpoint.clone());
+                        self.set_watchpoint_enabled(wp.number, true);
+                    }
+                }
+                DebugCommand::InfoBreakpoints => {
+                    self.update_ignore_counts(cpu);
+                    self.print_breakpoints();
+                }
+                DebugCommand::TraceOn(file_name, ranges) => {
+                    match create_tracer(&file_name, ranges) {
+                        Ok(tracer) => {
+                            cpu.set_tracer(Some(tracer));
+                            self.writeln(format!("Tracing to '{}'", file_name).as_str());
+                        }
+                        Err(e) => self.writeln(format!("{:#}", e).as_str()),
+                    }
+                }
+                DebugCommand::TraceOff => {
+                    // dropping the tracer flushes the trace file
+                    cpu.set_tracer(None);
+                    self.writeln("Tracing off");
+                }
+                DebugCommand::Watch(watchpoint) => {
+                    cpu.add_watchpoint(watchpoint.clone());
+                    let number = self.add_watchpoint(watchpoint.clone());
+                    self.writeln(format_watchpoint(number, &watchpoint).as_str());
+                }
                 DebugCommand::Help | DebugCommand::Invalid => {
                     self.show_usage();
                 }
@@ -92,7 +255,7 @@ impl Debugger<'_> {
         let mut msg = format!("  {:04X}:", addr);
         let mut next_addr = addr;
         for i in 0..cnt {
-            msg.push_str(format!(" {:02X}", cpu.get_byte_at(addr + i)?).as_str());
+            msg.push_str(format!(" {:02X}", cpu.peek(addr + i)?).as_str());
             next_addr = next_addr.wrapping_add(1);
         }
         Ok((msg, next_addr))
@@ -132,10 +295,10 @@ impl Debugger<'_> {
             cmd = match self.last_cmd {
                 DebugCommand::Memory(_) => DebugCommand::Memory(AddressRange::Default),
                 DebugCommand::Disassemble(_) => DebugCommand::Disassemble(AddressRange::Default),
-                _ => self.last_cmd,
+                _ => self.last_cmd.clone(),
             }
         } else {
-            self.last_cmd = cmd;
+            self.last_cmd = cmd.clone();
         }
         Ok(cmd)
     }
@@ -151,13 +314,206 @@ impl Debugger<'_> {
         Ok(())
     }
 
+    fn add_breakpoint(
+        &mut self,
+        address: u16,
+        condition: Option<BreakCondition>,
+        ignore_count: u32,
+    ) -> usize {
+        let number = self.next_breakpoint;
+        self.next_breakpoint += 1;
+        self.breakpoints.push(Breakpoint {
+            number,
+            address,
+            enabled: true,
+            condition,
+            ignore_count,
+        });
+        number
+    }
+
+    // numbers the CPU's breakpoints that the debugger doesn't know about yet,
+    // e.g. restored from a snapshot
+    fn sync_breakpoints(&mut self, cpu: &mut Box<dyn Cpu>) {
+        for bp in cpu.get_breakpoints() {
+            if !self.breakpoints.iter().any(|b| b.address == bp.address) {
+                let condition = bp.condition.map(|expr| BreakCondition {
+                    text: expr.to_string(),
+                    expr,
+                });
+                self.add_breakpoint(bp.address, condition, bp.ignore_count);
+            }
+        }
+    }
+
+    // the CPU counts down the ignored hits of enabled breakpoints
+    fn update_ignore_counts(&mut self, cpu: &mut Box<dyn Cpu>) {
+        for cpu_bp in cpu.get_breakpoints() {
+            if let Some(bp) = self
+                .breakpoints
+                .iter_mut()
+                .find(|b| b.enabled && b.address == cpu_bp.address)
+            {
+                bp.ignore_count = cpu_bp.ignore_count;
+            }
+        }
+    }
+
+    fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> usize {
+        let number = self.next_breakpoint;
+        self.next_breakpoint += 1;
+        self.watchpoints.push(NumberedWatchpoint {
+            number,
+            enabled: true,
+            watchpoint,
+        });
+        number
+    }
+
+    // numbers the CPU's watchpoints that the debugger doesn't know about yet
+    fn sync_watchpoints(&mut self, cpu: &mut Box<dyn Cpu>) {
+        for watchpoint in cpu.get_watchpoints() {
+            if !self.watchpoints.iter().any(|w| w.watchpoint == watchpoint) {
+                self.add_watchpoint(watchpoint);
+            }
+        }
+    }
+
+    // the breakpoint or watchpoint with number, or all of them
+    fn select_breakpoints(
+        &mut self,
+        number: Option<usize>,
+    ) -> (Vec<Breakpoint>, Vec<NumberedWatchpoint>) {
+        let breakpoints: Vec<Breakpoint> = self
+            .breakpoints
+            .iter()
+            .filter(|b| number.is_none_or(|n| n == b.number))
+            .cloned()
+            .collect();
+        let watchpoints: Vec<NumberedWatchpoint> = self
+            .watchpoints
+            .iter()
+            .filter(|w| number.is_none_or(|n| n == w.number))
+            .cloned()
+            .collect();
+        if let Some(number) = number
+            && breakpoints.is_empty()
+            && watchpoints.is_empty()
+        {
+            self.writeln(format!("No breakpoint number {}", number).as_str());
+        }
+        (breakpoints, watchpoints)
+    }
+
+    fn set_breakpoint_enabled(&mut self, number: usize, enabled: bool) {
+        if let Some(bp) = self.breakpoints.iter_mut().find(|b| b.number == number) {
+            bp.enabled = enabled;
+        }
+    }
+
+    fn set_watchpoint_enabled(&mut self, number: usize, enabled: bool) {
+        if let Some(wp) = self.watchpoints.iter_mut().find(|w| w.number == number) {
+            wp.enabled = enabled;
+        }
+    }
+
+    fn print_breakpoint_hit(&mut self, pc: u16) {
+        if let Some(bp) = self
+            .breakpoints
+            .iter()
+            .find(|b| b.enabled && b.address == pc)
+        {
+            let msg = format!("Breakpoint {} at {:04X}", bp.number, bp.address);
+            self.writeln(msg.as_str());
+        }
+    }
+
+    fn print_breakpoints(&mut self) {
+        if self.breakpoints.is_empty() && self.watchpoints.is_empty() {
+            self.writeln("No breakpoints or watchpoints");
+            return;
+        }
+        self.writeln("  Num Enb Address");
+        for bp in self.breakpoints.clone() {
+            let enabled = if bp.enabled { "y" } else { "n" };
+            let msg = format!("  {:<3} {:<3} {:04X}", bp.number, enabled, bp.address);
+            self.writeln(msg.as_str());
+            if let Some(condition) = &bp.condition {
+                self.writeln(format!("        stop only if {}", condition.text).as_str());
+            }
+            if bp.ignore_count > 0 {
+                self.writeln(format!("        ignore next {} hits", bp.ignore_count).as_str());
+            }
+        }
+        for wp in self.watchpoints.clone() {
+            let enabled = if wp.enabled { "y" } else { "n" };
+            let range = format_watch_range(&wp.watchpoint);
+            let msg = format!("  {:<3} {:<3} {}", wp.number, enabled, range);
+            self.writeln(msg.as_str());
+            self.writeln(format!("        {}", describe_watchpoint(&wp.watchpoint)).as_str());
+        }
+    }
+
+    fn print_stop_reason(&mut self, reason: StopReason) {
+        match reason {
+            StopReason::Watchpoint(hit) => {
+                self.writeln(format!("Watchpoint hit: {}", hit).as_str());
+            }
+            StopReason::Loop(address) => {
+                self.writeln(format!("Stopped in endless loop at {:04X}", address).as_str());
+            }
+            _ => {}
+        }
+    }
+
+    fn print_history_snapshot(
+        &mut self,
+        cpu: &mut Box<dyn Cpu>,
+        snapshot: Option<CpuRegisterSnapshot>,
+    ) -> Result<(), DebugCmdError> {
+        match snapshot {
+            Some(snapshot) => {
+                self.print_snapshot(cpu, snapshot)?;
+                self.last_prog_addr = None;
+            }
+            None => self.writeln("No recorded history to step back"),
+        }
+        Ok(())
+    }
+
     fn show_usage(&mut self) {
         self.writeln("Usage:");
         self.writeln("  <empty line>                  - repeat last command");
         self.writeln("  step (s)                      - step one instruction");
         self.writeln("  continue (c)                  - continue execution");
+        self.writeln("  break (b) [addr]              - set breakpoint at address or current PC");
+        self.writeln("    [if cond] [ignore n]        - stop only if cond holds, after n hits");
+        self.writeln(
+            "  delete (d) [num]              - delete breakpoint or watchpoint num, or all",
+        );
+        self.writeln(
+            "  disable [num]                 - disable breakpoint or watchpoint num, or all",
+        );
+        self.writeln(
+            "  enable [num]                  - enable breakpoint or watchpoint num, or all",
+        );
+        self.writeln("  info breakpoints (i b)        - list breakpoints and watchpoints");
+        self.writeln("  back (rs, reverse-step)       - step back one instruction");
+        self.writeln("  reverse-continue (rc)         - step back to previous address trap");
         self.writeln("  disassemble (di) [addr_range] - disassemble instructions at address range");
         self.writeln("  memory (m) [addr_range]       - print memory at address range");
+        self.writeln("  watch <watch_range> [== val]  - stop after a write to watch_range");
+        self.writeln("  rwatch <watch_range> [== val] - stop after a read of watch_range");
+        self.writeln("  awatch <watch_range> [== val] - stop after a read or write of watch_range");
+        self.writeln(
+            "    [before | after]            - stop before the accessing instruction, or after",
+        );
+        self.writeln("  save <file>                   - save machine state to file");
+        self.writeln("  restore <file>                - restore machine state from file");
+        self.writeln(
+            "  trace on <file> [ranges]      - trace instructions in watch_ranges, or all, to file",
+        );
+        self.writeln("  trace off                     - stop tracing");
         self.writeln("  quit (q)                      - quit debugger");
         self.writeln("");
         self.writeln("  addr_range:");
@@ -166,6 +522,13 @@ impl Debugger<'_> {
         self.writeln("  <start_addr>..<end_addr>    - exclusive range from start_addr to end_addr");
         self.writeln("  <start_addr>..=<end_addr>   - inclusive range from start_addr to end_addr");
         self.writeln("  <start_addr>,<line_cnt>     - range from start_addr for line_cnt lines");
+        self.writeln("");
+        self.writeln("  watch_range:");
+        self.writeln(
+            "  <addr>                      - single address, dec or hex prefix '0x' or '$'",
+        );
+        self.writeln("  <start_addr>..<end_addr>    - exclusive range from start_addr to end_addr");
+        self.writeln("  <start_addr>..=<end_addr>   - inclusive range from start_addr to end_addr");
     }
 }
 
@@ -175,6 +538,47 @@ impl From<CpuError> for DebugCmdError {
     }
 }
 
+fn format_watchpoint(number: usize, watchpoint: &Watchpoint) -> String {
+    let kind = match watchpoint.kind {
+        WatchKind::Read => "Read watchpoint",
+        WatchKind::Write => "Watchpoint",
+        WatchKind::Access => "Access watchpoint",
+    };
+    let mut msg = format!("{} {} on {}", kind, number, format_watch_range(watchpoint));
+    if let Some(value) = watchpoint.value {
+        msg.push_str(format!(" == {:02X}", value).as_str());
+    }
+    if watchpoint.stop_before {
+        msg.push_str(", stops before the instruction");
+    }
+    msg
+}
+
+fn format_watch_range(watchpoint: &Watchpoint) -> String {
+    let (start, end) = (watchpoint.range.start(), watchpoint.range.end());
+    if start == end {
+        format!("{:04X}", start)
+    } else {
+        format!("{:04X}..={:04X}", start, end)
+    }
+}
+
+// e.g. "read watchpoint of 42, stops before the instruction"
+fn describe_watchpoint(watchpoint: &Watchpoint) -> String {
+    let mut msg = match watchpoint.kind {
+        WatchKind::Read => "read watchpoint".to_string(),
+        WatchKind::Write => "write watchpoint".to_string(),
+        WatchKind::Access => "access watchpoint".to_string(),
+    };
+    if let Some(value) = watchpoint.value {
+        msg.push_str(format!(" of {:02X}", value).as_str());
+    }
+    if watchpoint.stop_before {
+        msg.push_str(", stops before the instruction");
+    }
+    msg
+}
+
 pub fn print_register(writer: &mut dyn io::Write, snapshot: CpuRegisterSnapshot) {
     let msg = format!(
         "PC: {:04X}: A: {:02X} X: {:02X} Y: {:02X} S: {:08b} SP: {:04X}\n",
@@ -188,6 +592,31 @@ pub fn print_register(writer: &mut dyn io::Write, snapshot: CpuRegisterSnapshot)
     writer.write_all(msg.as_bytes()).unwrap();
 }
 
+pub fn save_state(cpu: &dyn Cpu, file_name: &str) -> anyhow::Result<()> {
+    let file = fs::File::create(file_name)
+        .with_context(|| format!("Error creating state file '{}'", file_name))?;
+    let mut writer = io::BufWriter::new(file);
+    cpu.save_state(&mut writer)
+        .with_context(|| format!("Error saving state to '{}'", file_name))?;
+    writer.flush()?;
+    Ok(())
+}
+
+// traces instructions in the address ranges, or all if there are none
+pub fn create_tracer(file_name: &str, ranges: Vec<RangeInclusive<u16>>) -> anyhow::Result<Tracer> {
+    let file = fs::File::create(file_name)
+        .with_context(|| format!("Error creating trace file '{}'", file_name))?;
+    Ok(Tracer::new(Box::new(io::BufWriter::new(file)), ranges))
+}
+
+pub fn restore_state(cpu: &mut dyn Cpu, file_name: &str) -> anyhow::Result<()> {
+    let file = fs::File::open(file_name)
+        .with_context(|| format!("Error opening state file '{}'", file_name))?;
+    cpu.load_state(&mut io::BufReader::new(file))
+        .with_context(|| format!("Error restoring state from '{}'", file_name))?;
+    Ok(())
+}
+
 fn calculate_range(
     last_addr: Option<u16>,
     addr_range: AddressRange,
@@ -219,6 +648,10 @@ mod tests {
             last_cmd: DebugCommand::Invalid,
             last_prog_addr: None,
             last_mem_addr: None,
+            breakpoints: vec![],
+            watchpoints: vec![],
+            next_breakpoint: 1,
+            stop_on_loop: false,
         }
     }
 
@@ -235,7 +668,7 @@ mod tests {
         assert_eq!(debugger.last_cmd, DebugCommand::Quit);
         let stdout = spy.get_stdout();
         // println!("{}", stdout);
-        assert!(stdout.contains("PC: 0300: A: 00 X: 00 Y: 00 S: 00000010 SP: 01FF"));
+        assert!(stdout.contains("PC: 0300: A: 00 X: 00 Y: 00 S: 00000100 SP: 01FD"));
         assert!(stdout.contains("0300 LDA #$42"));
         assert!(stdout.contains("PC: 0302: A: 42"));
         Ok(())
@@ -252,7 +685,7 @@ mod tests {
 
         let stdout = spy.get_stdout();
         // println!("{}", stdout);
-        assert!(stdout.contains("PC: 0300: A: 00 X: 00 Y: 00 S: 00000010 SP: 01FF"));
+        assert!(stdout.contains("PC: 0300: A: 00 X: 00 Y: 00 S: 00000100 SP: 01FD"));
         assert!(stdout.contains("0300 LDA #$42"));
         Ok(())
     }
@@ -289,6 +722,283 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn debug_loop_save_restore() -> Result<(), DebugCmdError> {
+        let state_file = std::env::temp_dir().join("r6502-debug-loop-save-restore.state");
+        let state_file = state_file.to_str().unwrap();
+        let input = format!("s\nsave {0}\ns\nrestore {0}\nquit\n", state_file);
+        let mut spy = Spy::new(input.as_str());
+        let mut debugger = create_debugger(&mut spy);
+        let mut cpu = mos6502_emulator::create_cpu(mos6502_emulator::CpuType::MOS6502)?;
+        // LDA #$42, LDX #$07, BRK
+        cpu.load_program(0x0300, &[0xA9, 0x42, 0xA2, 0x07, 0x00], true)?;
+        cpu.set_pc(0x0300)?;
+        let snapshot = debugger.debug_loop(&mut cpu)?;
+        let _ = fs::remove_file(state_file);
+
+        assert_eq!(snapshot.program_counter, 0x0302);
+        assert_eq!(snapshot.x_register, 0x00);
+        assert_eq!(snapshot.accumulated_instructions, 1);
+        let stdout = spy.get_stdout();
+        // println!("{}", stdout);
+        assert!(stdout.contains(format!("Saved machine state to '{}'", state_file).as_str()));
+        assert!(stdout.contains("PC: 0304: A: 42 X: 07"));
+        assert!(stdout.contains(format!("Restored machine state from '{}'", state_file).as_str()));
+        Ok(())
+    }
+
+    #[test]
+    fn debug_loop_step_back() -> Result<(), DebugCmdError> {
+        let mut spy = Spy::new("s\ns\nback\nrs\nback\nquit\n");
+        let mut debugger = create_debugger(&mut spy);
+        let mut cpu = mos6502_emulator::create_cpu(mos6502_emulator::CpuType::MOS6502)?;
+        // LDA #$42, STA $0F, BRK
+        cpu.load_program(0x0300, &[0xA9, 0x42, 0x85, 0x0F, 0x00], true)?;
+        cpu.set_pc(0x0300)?;
+        let snapshot = debugger.debug_loop(&mut cpu)?;
+
+        assert_eq!(snapshot.program_counter, 0x0300);
+        assert_eq!(snapshot.accumulator, 0x00);
+        assert_eq!(cpu.peek(0x000F)?, 0x00);
+        let stdout = spy.get_stdout();
+        // println!("{}", stdout);
+        assert!(stdout.contains("PC: 0304: A: 42"));
+        assert!(stdout.contains("No recorded history to step back"));
+        Ok(())
+    }
+
+    #[test]
+    fn debug_loop_reverse_continue() -> Result<(), DebugCmdError> {
+        let mut spy = Spy::new("c\nrc\nquit\n");
+        let mut debugger = create_debugger(&mut spy);
+        let mut cpu = mos6502_emulator::create_cpu(mos6502_emulator::CpuType::MOS6502)?;
+        // LDX #$07, INX, BRK
+        cpu.load_program(0x0300, &[0xA2, 0x07, 0xE8, 0x00], true)?;
+        cpu.set_pc(0x0300)?;
+        let snapshot = debugger.debug_loop(&mut cpu)?;
+
+        assert_eq!(snapshot.program_counter, 0x0300);
+        assert_eq!(snapshot.x_register, 0x00);
+        assert_eq!(snapshot.accumulated_instructions, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn debug_loop_watch() -> Result<(), DebugCmdError> {
+        let mut spy = Spy::new("watch $0F == $42\nc\nquit\n");
+        let mut debugger = create_debugger(&mut spy);
+        let mut cpu = mos6502_emulator::create_cpu(mos6502_emulator::CpuType::MOS6502)?;
+        // LDA #$41, STA $0F, LDA #$42, STA $0F, BRK
+        cpu.load_program(
+            0x0300,
+            &[0xA9, 0x41, 0x85, 0x0F, 0xA9, 0x42, 0x85, 0x0F, 0x00],
+            true,
+        )?;
+        cpu.set_pc(0x0300)?;
+        let snapshot = debugger.debug_loop(&mut cpu)?;
+
+        assert_eq!(snapshot.program_counter, 0x0308);
+        let stdout = spy.get_stdout();
+        // println!("{}", stdout);
+        assert!(stdout.contains("Watchpoint 1 on 000F == 42"));
+        assert!(
+            stdout.contains("Watchpoint hit: write of 0x42 to 0x000F by instruction at 0x0306")
+        );
+        assert!(stdout.contains("PC: 0308: A: 42"));
+        Ok(())
+    }
+
+    #[test]
+    fn debug_loop_watch_before_and_delete() -> Result<(), DebugCmdError> {
+        let input = "watch $0F before\nrwatch $10..=$11 == 7\ni b\nc\ndelete 1\nc\ni b\nquit\n";
+        let mut spy = Spy::new(input);
+        let mut debugger = create_debugger(&mut spy);
+        let mut cpu = mos6502_emulator::create_cpu(mos6502_emulator::CpuType::MOS6502)?;
+        // LDA #$41, STA $0F, LDA #$42, STA $0F, BRK
+        cpu.load_program(
+            0x0300,
+            &[0xA9, 0x41, 0x85, 0x0F, 0xA9, 0x42, 0x85, 0x0F, 0x00],
+            true,
+        )?;
+        cpu.set_pc(0x0300)?;
+        debugger.debug_loop(&mut cpu)?;
+
+        let stdout = spy.get_stdout();
+        // println!("{}", stdout);
+        assert!(stdout.contains("Watchpoint 1 on 000F, stops before the instruction"));
+        assert!(stdout.contains("Read watchpoint 2 on 0010..=0011 == 07"));
+        assert!(stdout.contains(
+            "  1   y   000F
+        write watchpoint, stops before the instruction
+  2   y   0010..=0011
+        read watchpoint of 07
+"
+        ));
+        // stopped before the first STA, which has not written yet:
+        assert!(stdout.contains("PC: 0302: A: 41"));
+        assert!(stdout.contains("Deleted watchpoint 1"));
+        let after_delete = stdout.split("Deleted watchpoint 1").nth(1).unwrap();
+        assert!(!after_delete.contains("  1   y"));
+        assert!(after_delete.contains("  2   y   0010..=0011"));
+        assert_eq!(cpu.get_byte_at(0x000F)?, 0x42);
+        assert_eq!(cpu.get_watchpoints().len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn debug_loop_conditional_breakpoints() -> Result<(), DebugCmdError> {
+        let input = "b $0304 if x == 2\nb $0304 if X == 6\nc\ni b\ndelete\nb $0302 ignore 1\ni b\nc\nquit\n";
+        let mut spy = Spy::new(input);
+        let mut debugger = create_debugger(&mut spy);
+        let mut cpu = mos6502_emulator::create_cpu(mos6502_emulator::CpuType::MOS6502)?;
+        // loop: INX, INY, INX, INY, JMP loop
+        cpu.load_program(0x0300, &[0xE8, 0xC8, 0xE8, 0xC8, 0x4C, 0x00, 0x03], true)?;
+        cpu.set_pc(0x0300)?;
+        let snapshot = debugger.debug_loop(&mut cpu)?;
+
+        let stdout = spy.get_stdout();
+        // println!("{}", stdout);
+        // the second breakpoint replaces the first one at the same address:
+        assert!(stdout.contains(
+            "Breakpoint 2 at 0304
+PC: 0304: A: 00 X: 06 Y: 06"
+        ));
+        assert!(stdout.contains("  2   y   0304\n        stop only if X == 6\n"));
+        assert!(stdout.contains("  3   y   0302\n        ignore next 1 hits\n"));
+        assert!(stdout.contains(
+            "Breakpoint 3 at 0302
+PC: 0302: A: 00 X: 09 Y: 09"
+        ));
+        assert_eq!(snapshot.program_counter, 0x0302);
+        Ok(())
+    }
+
+    #[test]
+    fn debug_loop_restored_breakpoints_keep_condition() -> Result<(), DebugCmdError> {
+        let state_file = std::env::temp_dir().join("r6502-debug-loop-breakpoint-state.state");
+        let state_file = state_file.to_str().unwrap();
+        let input = format!(
+            "b $0304\nb $0302 if X > 1 ignore 5\nc\nc\ni b\nsave {0}\ndelete\nrestore {0}\ni b\ndisable 4\nenable 4\nenable 3\ni b\nquit\n",
+            state_file
+        );
+        let mut spy = Spy::new(input.as_str());
+        let mut debugger = create_debugger(&mut spy);
+        let mut cpu = mos6502_emulator::create_cpu(mos6502_emulator::CpuType::MOS6502)?;
+        // loop: INX, INY, INX, INY, JMP loop
+        cpu.load_program(0x0300, &[0xE8, 0xC8, 0xE8, 0xC8, 0x4C, 0x00, 0x03], true)?;
+        cpu.set_pc(0x0300)?;
+        debugger.debug_loop(&mut cpu)?;
+        let _ = fs::remove_file(state_file);
+
+        let stdout = spy.get_stdout();
+        // println!("{}", stdout);
+        // the hit with X == 3 was ignored:
+        assert!(
+            stdout.contains(
+                "  2   y   0302\n        stop only if X > 1\n        ignore next 4 hits\n"
+            )
+        );
+        // restored from the snapshot, and neither disable/enable nor enabling
+        // an enabled breakpoint lose the condition or the remaining count:
+        let restored = stdout.split("Restored machine state").nth(1).unwrap();
+        let listing = "  3   y   0304\n  4   y   0302\n        stop only if X > $01\n        ignore next 4 hits\n";
+        assert_eq!(restored.matches(listing).count(), 2);
+        let cpu_bp = &cpu.get_breakpoints()[1];
+        assert_eq!(cpu_bp.address, 0x0302);
+        assert!(cpu_bp.condition.is_some());
+        assert_eq!(cpu_bp.ignore_count, 4);
+        Ok(())
+    }
+
+    #[test]
+    fn debug_loop_stops_in_endless_loop() -> Result<(), DebugCmdError> {
+        let mut spy = Spy::new("c\nquit\n");
+        let mut debugger = create_debugger(&mut spy);
+        let mut cpu = mos6502_emulator::create_cpu(mos6502_emulator::CpuType::MOS6502)?;
+        // LDX #$03, DEX, BNE *-1, JMP *
+        cpu.load_program(
+            0x0300,
+            &[0xA2, 0x03, 0xCA, 0xD0, 0xFD, 0x4C, 0x05, 0x03],
+            true,
+        )?;
+        cpu.set_pc(0x0300)?;
+        debugger.set_stop_on_loop(true);
+        let snapshot = debugger.debug_loop(&mut cpu)?;
+
+        assert_eq!(snapshot.program_counter, 0x0305);
+        let stdout = spy.get_stdout();
+        // println!("{}", stdout);
+        assert!(stdout.contains("Stopped in endless loop at 0305"));
+        Ok(())
+    }
+
+    #[test]
+    fn debug_loop_trace() -> Result<(), DebugCmdError> {
+        let trace_file = std::env::temp_dir().join("r6502-debug-loop-trace.log");
+        let trace_file = trace_file.to_str().unwrap();
+        let input = format!("trace on {} $0301\ns\ns\ntrace off\ns\nquit\n", trace_file);
+        let mut spy = Spy::new(input.as_str());
+        let mut debugger = create_debugger(&mut spy);
+        let mut cpu = mos6502_emulator::create_cpu(mos6502_emulator::CpuType::MOS6502)?;
+        // loop: INX, INY, JMP loop
+        cpu.load_program(0x0300, &[0xE8, 0xC8, 0x4C, 0x00, 0x03], true)?;
+        cpu.set_pc(0x0300)?;
+        debugger.debug_loop(&mut cpu)?;
+
+        let stdout = spy.get_stdout();
+        assert!(stdout.contains(format!("Tracing to '{}'", trace_file).as_str()));
+        assert!(stdout.contains("Tracing off"));
+        let trace = fs::read_to_string(trace_file).unwrap();
+        assert_eq!(
+            trace,
+            "0301  C8        INY                             A:00 X:01 Y:00 P:04 SP:FD CYC:2\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn debug_loop_breakpoints() -> Result<(), DebugCmdError> {
+        let input = "break $0302\nb $0304\nc\nc\ndisable 2\ninfo breakpoints\ndelete 1\nd 7\ni b\nc\nquit\n";
+        let mut spy = Spy::new(input);
+        let mut debugger = create_debugger(&mut spy);
+        let mut cpu = mos6502_emulator::create_cpu(mos6502_emulator::CpuType::MOS6502)?;
+        // loop: INX, INY, JMP loop
+        cpu.load_program(0x0300, &[0xE8, 0xC8, 0xE8, 0xC8, 0x4C, 0x00, 0x03], true)?;
+        // preset, e.g. by -B:
+        cpu.add_breakpoint(0x0300);
+        cpu.set_pc(0x0301)?;
+        let snapshot = debugger.debug_loop(&mut cpu)?;
+
+        let stdout = spy.get_stdout();
+        // println!("{}", stdout);
+        assert!(stdout.contains("Breakpoint 2 at 0302\n(dbg)> Breakpoint 3 at 0304"));
+        assert!(stdout.contains("Breakpoint 2 at 0302\nPC: 0302: A: 00 X: 00 Y: 01"));
+        assert!(stdout.contains("Breakpoint 3 at 0304\nPC: 0304: A: 00 X: 01 Y: 02"));
+        assert!(stdout.contains("  1   y   0300\n  2   n   0302\n  3   y   0304"));
+        assert!(stdout.contains("Deleted breakpoint 1"));
+        assert!(stdout.contains("No breakpoint number 7"));
+        assert!(stdout.contains("  2   n   0302\n  3   y   0304"));
+        // the disabled and deleted breakpoints don't stop the loop:
+        assert!(stdout.contains("Breakpoint 3 at 0304\nPC: 0304: A: 00 X: 03 Y: 04"));
+        assert_eq!(snapshot.program_counter, 0x0304);
+        let addresses: Vec<u16> = cpu.get_breakpoints().iter().map(|b| b.address).collect();
+        assert_eq!(addresses, vec![0x0304]);
+        Ok(())
+    }
+
+    #[test]
+    fn debug_loop_restore_missing_file() -> Result<(), DebugCmdError> {
+        let mut spy = Spy::new("restore does-not-exist.state\nquit\n");
+        let mut debugger = create_debugger(&mut spy);
+        let mut cpu = mos6502_emulator::create_cpu(mos6502_emulator::CpuType::MOS6502)?;
+        debugger.debug_loop(&mut cpu)?;
+
+        let stdout = spy.get_stdout();
+        assert!(stdout.contains("Error opening state file 'does-not-exist.state'"));
+        assert!(stdout.contains("Exiting..."));
+        Ok(())
+    }
+
     #[test]
     fn usage() -> Result<(), DebugCmdError> {
         let mut spy = Spy::new("help\nquit\n");
@@ -6,6 +6,7 @@ mod debugger;
 
 use std::process;
 use std::result::Result::Ok;
+use std::time::Instant;
 
 use anyhow::{Context, Error, Result};
 use clap::Parser;
@@ -13,9 +14,18 @@ use console_io::StdIo;
 use dbg_cmd_parser::DebugCmdError;
 
 use crate::console_io::ConsoleIo;
-use crate::debugger::{Debugger, print_register};
+use crate::debugger::{Debugger, create_tracer, print_register, restore_state, save_state};
 use args::CliArgs;
-use mos6502_emulator::{Cpu, CpuRegisterSnapshot, CpuType, create_cpu};
+use mos6502_emulator::{
+    Cpu, CpuError, CpuOptions, CpuRegisterSnapshot, CpuType, IllegalOpcodePolicy, RunLimits,
+    StopReason, create_cpu_with_options,
+};
+
+const RESET_VECTOR: u16 = 0xFFFC;
+// exit status when a run is stopped by --max-cycles, --max-instructions or --timeout
+const EXIT_LIMIT_REACHED: i32 = 2;
+// exit status when a run with --success-address ends in an endless loop elsewhere
+const EXIT_LOOP_FAILURE: i32 = 3;
 
 fn main() {
     let args = CliArgs::parse();
@@ -26,13 +36,18 @@ fn main() {
     };
     let outcome = main.try_main(&args);
 
+    process::exit(exit_code(&outcome, args.success_address));
+}
+
+fn exit_code(outcome: &Result<StopReason>, success_address: Option<u16>) -> i32 {
     match outcome {
-        Ok(()) => {
-            process::exit(0);
-        }
-        Err(_) => {
-            process::exit(1);
-        }
+        Ok(StopReason::Stopped | StopReason::Watchpoint(_)) => 0,
+        Ok(StopReason::Loop(address)) => match success_address {
+            Some(success) if success != *address => EXIT_LOOP_FAILURE,
+            _ => 0,
+        },
+        Ok(_) => EXIT_LIMIT_REACHED,
+        Err(_) => 1,
     }
 }
 
@@ -41,20 +56,30 @@ struct Main<'a> {
 }
 
 impl Main<'_> {
-    pub fn try_main(mut self, args: &CliArgs) -> Result<()> {
+    pub fn try_main(mut self, args: &CliArgs) -> Result<StopReason> {
         let outcome = match args.command {
             args::Command::Run => self.run(args),
-            args::Command::Debug => self.debug(args),
+            args::Command::Debug => self
+                .debug(args)
+                .map(|snapshot| (StopReason::Stopped, snapshot)),
         };
         match outcome {
-            Ok(snapshot) => {
+            Ok((reason, snapshot)) => {
                 self.writeln("");
                 self.print_snapshot(snapshot);
+                if reason != StopReason::Stopped {
+                    self.writeln(format!("Execution stopped: {}", reason).as_str());
+                }
                 self.writeln("done.");
-                Ok(())
+                Ok(reason)
             }
             Err(e) => {
-                self.err(format!("Program finished with error:\n {:#}", e).as_str());
+                self.err(format!("Program finished with error:\n {:#}\n", e).as_str());
+                if let Some(CpuError::Execution(exec)) = e.downcast_ref::<CpuError>() {
+                    let mut registers: Vec<u8> = vec![];
+                    print_register(&mut registers, exec.snapshot.clone());
+                    self.err(format!(" {}", String::from_utf8_lossy(&registers)).as_str());
+                }
                 Err(e)
             }
         }
@@ -73,10 +98,32 @@ impl Main<'_> {
         self.stdio.write_err(msg).unwrap();
     }
 
-    fn run(&mut self, args: &CliArgs) -> Result<CpuRegisterSnapshot> {
+    fn run(&mut self, args: &CliArgs) -> Result<(StopReason, CpuRegisterSnapshot)> {
         let (mut cpu, start_addr) = self.init_cpu(args)?;
 
-        anyhow::Ok(cpu.run(Some(start_addr))?)
+        cpu.set_pc(start_addr)?;
+        cpu.set_clock_speed(args.clock.as_hz());
+        let limits = RunLimits {
+            max_cycles: args.max_cycles,
+            max_instructions: args.max_instructions,
+            deadline: args.timeout.map(|timeout| Instant::now() + timeout),
+            stop_on_loop: args.stop_on_loop || args.success_address.is_some(),
+        };
+        let outcome = cpu.run_limited(limits);
+        if let Ok((StopReason::Stopped, snapshot)) = &outcome
+            && args.breakpoints.contains(&snapshot.program_counter)
+        {
+            let msg = format!("Stopped at breakpoint {:04X}", snapshot.program_counter);
+            self.writeln(msg.as_str());
+        }
+        if let Ok((StopReason::Loop(address), _)) = &outcome
+            && args.success_address == Some(*address)
+        {
+            self.writeln(format!("Reached success address {:04X}", address).as_str());
+        }
+        // also keep the state of a failed run, to reproduce the error
+        self.save_state(cpu.as_ref(), args)?;
+        anyhow::Ok(outcome?)
     }
 
     fn debug(&mut self, args: &CliArgs) -> Result<CpuRegisterSnapshot> {
@@ -84,17 +131,39 @@ impl Main<'_> {
 
         cpu.set_pc(start_addr)?;
         let mut dbg = Debugger::new(self.stdio);
+        dbg.set_stop_on_loop(args.stop_on_loop || args.success_address.is_some());
         dbg.debug_loop(&mut cpu)?;
+        self.save_state(cpu.as_ref(), args)?;
 
         anyhow::Ok(cpu.get_register_snapshot())
     }
 
+    fn save_state(&mut self, cpu: &dyn Cpu, args: &CliArgs) -> Result<()> {
+        if let Some(file_name) = &args.save_state {
+            save_state(cpu, file_name)?;
+            self.writeln(format!("Saved machine state to '{}'", file_name).as_str());
+        }
+        Ok(())
+    }
+
     fn init_cpu(&mut self, args: &CliArgs) -> Result<(Box<dyn Cpu>, u16), Error> {
-        let mut cpu = create_cpu(CpuType::MOS6502)?;
+        let cpu_type = match args.cpu {
+            args::CpuKind::Mos6502 => CpuType::MOS6502,
+            args::CpuKind::Mos6502Undocumented => CpuType::MOS6502Undocumented,
+            args::CpuKind::Wdc65c02 => CpuType::WDC65C02,
+        };
+        let illegal_opcodes = match args.illegal_opcodes {
+            args::IllegalOpcodes::Error => IllegalOpcodePolicy::Error,
+            args::IllegalOpcodes::Nop => IllegalOpcodePolicy::Nop,
+            args::IllegalOpcodes::Jam => IllegalOpcodePolicy::Jam,
+            args::IllegalOpcodes::Brk => IllegalOpcodePolicy::Brk,
+        };
+        let mut cpu = create_cpu_with_options(cpu_type, CpuOptions { illegal_opcodes })?;
         let load_addr: Option<u16>;
+        // without a start address, a ROM image with a reset vector boots via that vector
+        let has_reset_vector: bool;
 
-        if args.binary.is_some() {
-            let file_name = args.binary.as_ref().unwrap();
+        if let Some(file_name) = &args.binary {
             let b = bin_file::load_program(file_name, None)
                 .with_context(|| format!("Error loading binary file '{}'", file_name))?;
             load_addr = b.load_addr.or(args.load_address);
@@ -105,6 +174,8 @@ impl Main<'_> {
             }
             let load_addr = load_addr.unwrap();
             cpu.load_program(load_addr, &b.data, args.read_only)?;
+            has_reset_vector = load_addr <= RESET_VECTOR
+                && load_addr as usize + b.data.len() > RESET_VECTOR as usize + 1;
             self.writeln(
                 format!(
                     "Loaded {} bytes at address {:04X}; read-only mem={}",
@@ -115,16 +186,34 @@ impl Main<'_> {
                 .as_str(),
             );
         } else {
-            self.writeln(
-                "No binary file specified, running empty program with single BRK instruction",
-            );
-            load_addr = Some(0xFFFE); // RESET vector
+            if args.load_state.is_none() {
+                self.writeln(
+                    "No binary file specified, running empty program with single BRK instruction",
+                );
+            }
+            load_addr = None;
+            has_reset_vector = true;
         }
 
-        let start_addr = if args.start_address.is_some() {
-            args.start_address.unwrap()
-        } else {
-            load_addr.unwrap()
+        if let Some(file_name) = &args.load_state {
+            restore_state(cpu.as_mut(), file_name)?;
+            self.writeln(format!("Restored machine state from '{}'", file_name).as_str());
+        }
+        cpu.set_brk_trap(args.brk == args::Brk::Stop);
+        for address in &args.breakpoints {
+            cpu.add_breakpoint(*address);
+        }
+        if let Some(file_name) = &args.trace {
+            cpu.set_tracer(Some(create_tracer(file_name, args.trace_ranges.clone())?));
+        }
+
+        let start_addr = match args.start_address {
+            Some(start_addr) => start_addr,
+            None if args.load_state.is_some() => cpu.get_pc(),
+            None if has_reset_vector => {
+                u16::from_le_bytes([cpu.peek(RESET_VECTOR)?, cpu.peek(RESET_VECTOR + 1)?])
+            }
+            None => load_addr.unwrap(),
         };
         self.writeln(format!("Start execution at address {:04X}", start_addr).as_str());
         Ok((cpu, start_addr))
@@ -180,7 +269,7 @@ mod tests {
 
         let stdout = spy.get_stdout();
         println!("{}", stdout);
-        assert!(stdout.contains("Start execution at address FFFE"));
+        assert!(stdout.contains("Start execution at address 0000"));
         assert_eq!(spy.get_stderr().len(), 0);
         Ok(())
     }
@@ -193,7 +282,7 @@ mod tests {
         m.try_main(&args)?;
         let stdout = spy.get_stdout();
         println!("{}", stdout);
-        assert!(stdout.contains("Start execution at address FFFE"));
+        assert!(stdout.contains("Start execution at address 0000"));
         assert_eq!(spy.get_stderr().len(), 0);
         Ok(())
     }
@@ -219,6 +308,195 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn try_main_execution_error() {
+        let args = CliArgs::parse_from(["run", "-b=tests/assets/stack_overflow.bin", "-l=0x0600"]);
+
+        let mut spy = Spy::new("");
+        let m = prepare_main(&mut spy);
+
+        assert!(m.try_main(&args).is_err());
+        let stderr = spy.get_stderr();
+        // println!("ERR: {}", stderr);
+        assert!(stderr.contains("Program finished with error:"));
+        assert!(stderr.contains("stack overflow at 0603 (bytes: 68)"));
+        assert!(stderr.contains("PC: 0604: A: 00 X: FF"));
+    }
+
+    #[test]
+    fn try_main_stops_endless_loop() -> Result<(), Error> {
+        let args = CliArgs::parse_from([
+            "run",
+            "-b=tests/assets/endless_loop.bin",
+            "-l=0x0600",
+            "--max-instructions=1000",
+            "--max-cycles=100000",
+        ]);
+        let mut spy = Spy::new("");
+        let m = prepare_main(&mut spy);
+        let outcome = m.try_main(&args);
+        assert_eq!(exit_code(&outcome, None), EXIT_LIMIT_REACHED);
+        assert_eq!(outcome?, StopReason::InstructionLimit);
+
+        let stdout = spy.get_stdout();
+        assert!(stdout.contains("Instructions: 1000; Cycles: 3000"));
+        assert!(stdout.contains("Execution stopped: instruction limit reached"));
+
+        let args = CliArgs::parse_from([
+            "run",
+            "-b=tests/assets/endless_loop.bin",
+            "-l=0x0600",
+            "--timeout=0.01",
+        ]);
+        let mut spy = Spy::new("");
+        let mut m = prepare_main(&mut spy);
+        let (reason, _) = m.run(&args)?;
+        assert_eq!(reason, StopReason::Deadline);
+        Ok(())
+    }
+
+    #[test]
+    fn try_main_maps_success_address() -> Result<(), Error> {
+        let args = CliArgs::parse_from([
+            "run",
+            "-b=tests/assets/endless_loop.bin",
+            "-l=0x0600",
+            "--success-address=0x0600",
+        ]);
+        let mut spy = Spy::new("");
+        let m = prepare_main(&mut spy);
+        let outcome = m.try_main(&args);
+        assert_eq!(exit_code(&outcome, args.success_address), 0);
+        assert_eq!(outcome?, StopReason::Loop(0x0600));
+        let stdout = spy.get_stdout();
+        assert!(stdout.contains("Reached success address 0600"));
+        assert!(stdout.contains("Execution stopped: endless loop at 0600"));
+
+        let args = CliArgs::parse_from([
+            "run",
+            "-b=tests/assets/endless_loop.bin",
+            "-l=0x0600",
+            "--success-address=0x3469",
+        ]);
+        let mut spy = Spy::new("");
+        let m = prepare_main(&mut spy);
+        let outcome = m.try_main(&args);
+        assert_eq!(exit_code(&outcome, args.success_address), EXIT_LOOP_FAILURE);
+        assert!(!spy.get_stdout().contains("Reached success address"));
+
+        let args = CliArgs::parse_from([
+            "run",
+            "-b=tests/assets/endless_loop.bin",
+            "-l=0x0600",
+            "--stop-on-loop",
+        ]);
+        let mut spy = Spy::new("");
+        let mut m = prepare_main(&mut spy);
+        let (reason, _) = m.run(&args)?;
+        assert_eq!(reason, StopReason::Loop(0x0600));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_limits() {
+        let args = CliArgs::parse_from(["run", "--max-cycles=10", "--timeout=1.5"]);
+        assert_eq!(args.max_cycles, Some(10));
+        assert_eq!(args.max_instructions, None);
+        assert_eq!(args.timeout, Some(std::time::Duration::from_millis(1500)));
+        assert!(CliArgs::try_parse_from(["run", "--timeout=-1"]).is_err());
+    }
+
+    #[test]
+    fn parse_clock() {
+        let args = CliArgs::parse_from(["run"]);
+        assert_eq!(args.clock, args::ClockSpeed::Max);
+        assert_eq!(args.clock.as_hz(), None);
+        let args = CliArgs::parse_from(["run", "--clock=1.023"]);
+        assert_eq!(args.clock, args::ClockSpeed::MHz(1.023));
+        let args = CliArgs::parse_from(["run", "--clock=2"]);
+        assert_eq!(args.clock.as_hz(), Some(2_000_000.0));
+        let args = CliArgs::parse_from(["run", "--clock", "MAX"]);
+        assert_eq!(args.clock, args::ClockSpeed::Max);
+        assert!(CliArgs::try_parse_from(["run", "--clock=0"]).is_err());
+        assert!(CliArgs::try_parse_from(["run", "--clock=fast"]).is_err());
+    }
+
+    #[test]
+    fn try_main_stops_at_breakpoint() -> Result<(), Error> {
+        let args = CliArgs::parse_from([
+            "run",
+            "-b=tests/assets/endless_loop.bin",
+            "-l=0x0600",
+            "-B=0x0600",
+            "--break",
+            "1234",
+        ]);
+        assert_eq!(args.breakpoints, vec![0x0600, 1234]);
+        let mut spy = Spy::new("");
+        let m = prepare_main(&mut spy);
+        assert_eq!(m.try_main(&args)?, StopReason::Stopped);
+
+        let stdout = spy.get_stdout();
+        assert!(stdout.contains("Stopped at breakpoint 0600"));
+        assert!(stdout.contains("Instructions: 0; Cycles: 0"));
+        Ok(())
+    }
+
+    #[test]
+    fn try_main_writes_trace() -> Result<(), Error> {
+        let trace_file = std::env::temp_dir().join("r6502-main-trace.log");
+        let trace_file = trace_file.to_str().unwrap();
+        let args = CliArgs::parse_from([
+            "run",
+            "-b=tests/assets/endless_loop.bin",
+            "-l=0x0600",
+            "--max-instructions=3",
+            "--trace",
+            trace_file,
+            "--trace-range=0x0600..=0x0600",
+            "--trace-range=0x0700..0x0800",
+        ]);
+        assert_eq!(args.trace_ranges, vec![0x0600..=0x0600, 0x0700..=0x07FF]);
+        let mut spy = Spy::new("");
+        let m = prepare_main(&mut spy);
+        assert_eq!(m.try_main(&args)?, StopReason::InstructionLimit);
+
+        let trace = std::fs::read_to_string(trace_file)?;
+        let lines: Vec<&str> = trace.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("0600  4C 00 06  JMP $0600                       A:00"));
+        assert!(lines[2].ends_with(" CYC:6"));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_trace_ranges() {
+        let parse = |range: &str| CliArgs::try_parse_from(["run", "--trace=t.log", range]);
+        assert!(parse("--trace-range=0x0300..0x0300").is_err());
+        assert!(parse("--trace-range=0x0300").is_err());
+        assert!(parse("--trace-range=0..0x10000").is_err());
+        assert_eq!(
+            parse("--trace-range=768..=768").unwrap().trace_ranges,
+            vec![0x0300..=0x0300]
+        );
+        // ranges without a trace file are a mistake:
+        assert!(CliArgs::try_parse_from(["run", "--trace-range=0..10"]).is_err());
+    }
+
+    #[test]
+    fn exit_codes() {
+        assert_eq!(exit_code(&Ok(StopReason::Stopped), None), 0);
+        assert_eq!(
+            exit_code(&Ok(StopReason::Deadline), None),
+            EXIT_LIMIT_REACHED
+        );
+        assert_eq!(exit_code(&Err(anyhow::anyhow!("failed")), None), 1);
+        let looped = Ok(StopReason::Loop(0x3469));
+        assert_eq!(exit_code(&looped, None), 0);
+        assert_eq!(exit_code(&looped, Some(0x3469)), 0);
+        assert_eq!(exit_code(&looped, Some(0x0600)), EXIT_LOOP_FAILURE);
+    }
+
     #[test]
     fn try_main_unknown_load_address_error() -> Result<(), Error> {
         let args = CliArgs::parse_from(["run", "-b=tests/assets/simplest.bin"]);
@@ -237,6 +515,65 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn parse_cpu_type() {
+        let args = CliArgs::parse_from(["run"]);
+        assert_eq!(args.cpu, args::CpuKind::Mos6502);
+        let args = CliArgs::parse_from(["run", "--cpu=mos6502-undocumented"]);
+        assert_eq!(args.cpu, args::CpuKind::Mos6502Undocumented);
+        let args = CliArgs::parse_from(["run", "--cpu=wdc65c02"]);
+        assert_eq!(args.cpu, args::CpuKind::Wdc65c02);
+    }
+
+    #[test]
+    fn run_with_brk_interrupt() -> Result<(), Error> {
+        let args = CliArgs::parse_from([
+            "run",
+            "-b=tests/assets/simplest.prg",
+            "--brk=interrupt",
+            "--max-instructions=5",
+        ]);
+        let mut spy = Spy::new("");
+        let mut m = prepare_main(&mut spy);
+        let (reason, snapshot) = m.run(&args)?;
+        // the BRK at the end continues via the zeroed IRQ vector, i.e. at yet another BRK:
+        assert_eq!(reason, StopReason::InstructionLimit);
+        assert_eq!(snapshot.accumulated_instructions, 5);
+        assert_eq!(snapshot.accumulator, 0x42);
+        Ok(())
+    }
+
+    #[test]
+    fn run_with_illegal_opcode_policy() -> Result<(), Error> {
+        // LDA #$42, then the illegal opcode 0xFF:
+        let args = CliArgs::parse_from([
+            "run",
+            "-b=tests/assets/illegal_opcode.bin",
+            "-l=0x0600",
+            "--illegal-opcodes=error",
+        ]);
+        let mut spy = Spy::new("");
+        let mut m = prepare_main(&mut spy);
+        let err = m.run(&args).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("illegal op code instruction 255 at 0602")
+        );
+
+        let args = CliArgs::parse_from([
+            "run",
+            "-b=tests/assets/illegal_opcode.bin",
+            "-l=0x0600",
+            "--illegal-opcodes=jam",
+        ]);
+        let mut spy = Spy::new("");
+        let mut m = prepare_main(&mut spy);
+        let (_, snapshot) = m.run(&args)?;
+        assert_eq!(snapshot.program_counter, 0x0602);
+        assert_eq!(snapshot.accumulator, 0x42);
+        Ok(())
+    }
+
     #[test]
     fn main_running_simplest_prg() -> Result<(), Error> {
         let args = CliArgs::parse_from(["run", "-b=tests/assets/simplest.prg"]);
@@ -244,8 +581,8 @@ mod tests {
         let mut spy = Spy::new("");
         let mut m = prepare_main(&mut spy);
 
-        let snapshot = m.run(&args)?;
-        assert_eq!(snapshot.program_counter, 0xFFFE);
+        let (_, snapshot) = m.run(&args)?;
+        assert_eq!(snapshot.program_counter, 0x0000);
         assert_eq!(snapshot.accumulated_instructions, 3);
         assert_eq!(snapshot.accumulated_cycles, 12);
         assert_eq!(snapshot.accumulator, 0x42);
@@ -257,6 +594,67 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn main_running_rom_with_reset_vector() -> Result<(), Error> {
+        let args = CliArgs::parse_from(["run", "-b=tests/assets/reset_vector.bin", "-l=0xFFF0"]);
+
+        let mut spy = Spy::new("");
+        let mut m = prepare_main(&mut spy);
+
+        let (_, snapshot) = m.run(&args)?;
+        assert_eq!(snapshot.accumulator, 0x42);
+        assert_eq!(snapshot.accumulated_instructions, 2);
+
+        let stdout = spy.get_stdout();
+        assert!(stdout.contains("Start execution at address FFF0"));
+        Ok(())
+    }
+
+    #[test]
+    fn main_save_and_load_state() -> Result<(), Error> {
+        let state_file = std::env::temp_dir().join("r6502-main-save-and-load.state");
+        let state_file = state_file.to_str().unwrap();
+        let save = format!("--save-state={}", state_file);
+        let args = CliArgs::parse_from(["run", "-b=tests/assets/simplest.prg", save.as_str()]);
+        let mut spy = Spy::new("");
+        let mut m = prepare_main(&mut spy);
+        m.run(&args)?;
+        let stdout = spy.get_stdout();
+        assert!(stdout.contains(format!("Saved machine state to '{}'", state_file).as_str()));
+
+        // resumes at the restored PC, with the restored registers and counters:
+        let load = format!("--load-state={}", state_file);
+        let args = CliArgs::parse_from(["run", load.as_str()]);
+        let mut spy = Spy::new("");
+        let mut m = prepare_main(&mut spy);
+        let snapshot = m.run(&args);
+        let _ = std::fs::remove_file(state_file);
+        let (_, snapshot) = snapshot?;
+        assert_eq!(snapshot.accumulator, 0x42);
+        assert_eq!(snapshot.accumulated_instructions, 4);
+
+        let stdout = spy.get_stdout();
+        assert!(stdout.contains(format!("Restored machine state from '{}'", state_file).as_str()));
+        assert!(stdout.contains("Start execution at address 0000"));
+        assert!(!stdout.contains("No binary file specified"));
+        Ok(())
+    }
+
+    #[test]
+    fn main_load_state_missing_file_error() {
+        let args = CliArgs::parse_from(["run", "--load-state=does-not-exist.state"]);
+        let mut spy = Spy::new("");
+        let mut m = prepare_main(&mut spy);
+
+        let r = m.run(&args);
+        assert!(
+            r.err()
+                .unwrap()
+                .to_string()
+                .contains("Error opening state file 'does-not-exist.state'")
+        );
+    }
+
     #[test]
     fn main_print_snapshot() -> Result<(), Error> {
         #[allow(unused_variables)]
@@ -0,0 +1,67 @@
+// Throughput of the emulator core running the Euclid GCD program of tests/euclid_tests.rs;
+// the throughput is reported in cycles per second, so Melem/s reads as the emulated clock in MHz:
+//   cargo bench -p mos6502-emulator
+
+use criterion::{BatchSize, Criterion, Throughput, criterion_group, criterion_main};
+use mos6502_emulator::{Cpu, CpuError, CpuType, create_cpu};
+
+const PROGRAM: [u8; 28] = [
+    0xA5, 0x40, // LDA VAR_A
+    0x38, // SEC
+    0xE5, 0x41, // SBC VAR_B
+    0xF0, 0x12, // BEQ done
+    0x30, 0x05, // BMI swap
+    0x85, 0x40, // STA VAR_A
+    0x4C, 0x02, 0x02, // JMP diff
+    0xA6, 0x40, // LDX VAR_A
+    0xA4, 0x41, // LDY VAR_B
+    0x86, 0x41, // STX VAR_B
+    0x84, 0x40, // STY VAR_A
+    0x4C, 0x00, 0x02, // JMP start
+    0xA5, 0x40, // LDA VAR_A
+    0x00, // BRK
+];
+// cycles of one run with VAR_A = 126, VAR_B = 49
+const CYCLES_PER_RUN: u64 = 195;
+
+// a fresh CPU per run, as BRK pushes onto the stack
+fn load_gcd(history_limit: usize) -> Result<Box<dyn Cpu>, CpuError> {
+    let mut cpu = create_cpu(CpuType::MOS6502)?;
+    cpu.set_history_limit(history_limit);
+    cpu.load_program(0x0200, &PROGRAM, true)?;
+    cpu.set_byte_at(0x0040, 126)?; // VAR_A
+    cpu.set_byte_at(0x0041, 49)?; // VAR_B
+    Ok(cpu)
+}
+
+fn run_gcd(mut cpu: Box<dyn Cpu>) -> Result<u8, CpuError> {
+    cpu.run(Some(0x0200))?;
+    cpu.get_byte_at(0x0040)
+}
+
+fn euclid_gcd(c: &mut Criterion) {
+    assert_eq!(run_gcd(load_gcd(0).unwrap()).unwrap(), 7);
+
+    let mut group = c.benchmark_group("emulator");
+    group.throughput(Throughput::Elements(CYCLES_PER_RUN));
+    // without undo history, the default for batch runs of test programs:
+    group.bench_function("euclid_gcd", |b| {
+        b.iter_batched(
+            || load_gcd(0).unwrap(),
+            |cpu| run_gcd(cpu).unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+    // with the undo history the debugger records:
+    group.bench_function("euclid_gcd_history", |b| {
+        b.iter_batched(
+            || load_gcd(10_000).unwrap(),
+            |cpu| run_gcd(cpu).unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, euclid_gcd);
+criterion_main!(benches);
@@ -1,31 +1,50 @@
+use std::collections::BTreeSet;
 use std::fs::File;
 use std::io::*;
 use std::path::Path;
 use std::{env, io};
 
-// Convert CSV file wilt 6502 op codes info to a match {} map:
+// Convert CSV files with 6502 op codes info to a 256 entry decode table per CPU type,
+// and to a match {} that dispatches each op code to its execute function:
 fn main() -> io::Result<()> {
     let src_dir_path = env::var("CARGO_MANIFEST_DIR").unwrap();
-    let opcodes_file = Path::new(&src_dir_path)
-        .join("src")
-        .join("engine")
-        .join("opcodes-mos6502.csv");
-
     let out_dir = env::var("OUT_DIR").unwrap();
-    let out_file_path = Path::new(&out_dir).join("opcodes_mos6502.rs");
 
-    convert_csv_to_opcodes(&opcodes_file, &out_file_path)?;
+    let mut instruction_sets = vec![];
+    let mut mnemonics = BTreeSet::new();
+    for instruction_set in ["mos6502", "mos6502-undocumented", "wdc65c02"] {
+        let csv_file_name = format!("opcodes-{}.csv", instruction_set);
+        let opcodes_file = Path::new(&src_dir_path)
+            .join("src")
+            .join("engine")
+            .join(&csv_file_name);
+        instruction_sets.push(read_opcodes(&opcodes_file, &mut mnemonics)?);
+        println!("cargo:rerun-if-changed=src/engine/{}", csv_file_name);
+    }
+    let [mos6502, undocumented, wdc65c02] = <[OpcodeTable; 3]>::try_from(instruction_sets).unwrap();
+
+    // the undocumented and 65C02 tables list new and changed opcodes only;
+    // the 65C02 table also maps all reserved opcodes to NOPs
+    let tables = [
+        ("mos6502", mos6502.clone()),
+        ("mos6502_undocumented", overlay(&mos6502, &undocumented)),
+        ("wdc65c02", overlay(&mos6502, &wdc65c02)),
+    ];
+    for (cpu_type, table) in tables {
+        let out_file_path = Path::new(&out_dir).join(format!("opcodes_{}.rs", cpu_type));
+        write_decode_table(&table, &out_file_path)?;
+    }
+    write_dispatch(&mnemonics, &Path::new(&out_dir).join("execute_opcodes.rs"))?;
 
     println!("cargo:rerun-if-changed=build.rs");
-    println!("cargo:rerun-if-changed=src/engine/opcodes-mos6502.csv");
     Ok(())
 }
 
-fn convert_csv_to_opcodes(opcodes_file: &Path, out_file_path: &Path) -> io::Result<()> {
-    let mut out_file = LineWriter::new(File::create(out_file_path)?);
-    println!("cargo:warning=generating: {}...", out_file_path.display());
+// generated DecodedInstruction literal per opcode byte, None for an illegal opcode
+type OpcodeTable = Vec<Option<String>>;
 
-    out_file.write_all(b"    match opcode_byte{\n")?;
+fn read_opcodes(opcodes_file: &Path, mnemonics: &mut BTreeSet<String>) -> io::Result<OpcodeTable> {
+    let mut table: OpcodeTable = vec![None; 256];
 
     let mut line_cnt = 0;
     if let Ok(lines) = read_lines(opcodes_file) {
@@ -33,6 +52,8 @@ fn convert_csv_to_opcodes(opcodes_file: &Path, out_file_path: &Path) -> io::Resu
             const COLUMNS: usize = 5;
             //   opcode,mnemonic,addressing mode,bytes,cycles,flags
             //   0x69,ADC,IMM,2,2,<flags>
+            // cycles with a '*' suffix take an extra cycle if the indexed address crosses a page:
+            //   0x7d,ADC,ABSX,3,4*
             let [hex_opcode, mnemonic, mode, bytes, cycles] = <[String; COLUMNS]>::try_from(
                 line?
                     .split(',')
@@ -46,36 +67,84 @@ fn convert_csv_to_opcodes(opcodes_file: &Path, out_file_path: &Path) -> io::Resu
                 continue;
             }
 
-            writeln!(
-                out_file,
-                //     0x69 => Ok(DecodedInstruction { opcode: OpCode::ADC, mode: AddressingMode::Immediate, execute: execute_adc, extra_bytes: 2, cycles: 2, hex_opcode: 0x69, }),
-                "        {} => Ok(DecodedInstruction {{ opcode: OpCode::{}, mode: AddressingMode::{}, execute: execute_{}, extra_bytes: {}, cycles: {}, hex_opcode: {} }}),",
-                hex_opcode,
+            let cycles = cycles.trim();
+            let (cycles, page_cross_penalty) = match cycles.strip_suffix('*') {
+                Some(cycles) => (cycles, true),
+                None => (cycles, false),
+            };
+            let opcode_byte = u8::from_str_radix(hex_opcode.trim_start_matches("0x"), 16).unwrap();
+
+            table[opcode_byte as usize] = Some(format!(
+                //     DecodedInstruction { opcode: OpCode::ADC, mode: AddressingMode::Immediate, extra_bytes: 2, cycles: 2, page_cross_penalty: false, hex_opcode: 0x69, },
+                "DecodedInstruction {{ opcode: OpCode::{}, mode: AddressingMode::{}, extra_bytes: {}, cycles: {}, page_cross_penalty: {}, hex_opcode: {} }},",
                 mnemonic.to_ascii_uppercase(),
                 to_addressing_mode(&mode),
-                mnemonic.to_ascii_lowercase(),
                 bytes.parse::<u8>().unwrap() - 1, // TODO need better error handling for number parsing
                 cycles.parse::<u8>().unwrap(),
+                page_cross_penalty,
                 hex_opcode,
-            )?;
+            ));
 
+            mnemonics.insert(mnemonic.to_ascii_uppercase());
             line_cnt += 1;
         }
     } else {
         println!("cargo:warning=failed to read i{}", opcodes_file.display());
     }
-    writeln!(
-        out_file,
-        "        _ => Err(CpuError::InvalidOpcode(opcode_byte)),"
-    )?;
-    out_file.write_all(b"    }\n")?;
-
-    out_file.flush()?;
     println!(
         "cargo:warning=converted {} opcodes from {}",
         line_cnt,
         opcodes_file.display()
     );
+    Ok(table)
+}
+
+fn overlay(base: &OpcodeTable, changes: &OpcodeTable) -> OpcodeTable {
+    base.iter()
+        .zip(changes)
+        .map(|(base, change)| change.clone().or_else(|| base.clone()))
+        .collect()
+}
+
+fn write_decode_table(table: &OpcodeTable, out_file_path: &Path) -> io::Result<()> {
+    let mut out_file = LineWriter::new(File::create(out_file_path)?);
+    println!("cargo:warning=generating: {}...", out_file_path.display());
+
+    out_file.write_all(b"[\n")?;
+    for (opcode_byte, decoded) in table.iter().enumerate() {
+        match decoded {
+            Some(decoded) => writeln!(out_file, "    {}", decoded)?,
+            None => writeln!(
+                out_file,
+                "    DecodedInstruction {{ opcode: OpCode::ILL(0x{0:02x}), mode: AddressingMode::Implied, extra_bytes: 0, cycles: 0, page_cross_penalty: false, hex_opcode: 0x{0:02x} }},",
+                opcode_byte
+            )?,
+        }
+    }
+    out_file.write_all(b"]\n")?;
+
+    out_file.flush()?;
+    Ok(())
+}
+
+fn write_dispatch(mnemonics: &BTreeSet<String>, out_file_path: &Path) -> io::Result<()> {
+    let mut out_file = LineWriter::new(File::create(out_file_path)?);
+    println!("cargo:warning=generating: {}...", out_file_path.display());
+
+    out_file.write_all(b"    match decoded.opcode {\n")?;
+    for mnemonic in mnemonics {
+        //     OpCode::ADC => execute_adc(decoded.mode, cpu),
+        writeln!(
+            out_file,
+            "        OpCode::{} => execute_{}(decoded.mode, cpu),",
+            mnemonic,
+            mnemonic.to_ascii_lowercase()
+        )?;
+    }
+    out_file.write_all(b"        OpCode::ILL(opcode) => execute_illegal(opcode, cpu),\n")?;
+    out_file.write_all(b"    }\n")?;
+
+    out_file.flush()?;
     Ok(())
 }
 
@@ -102,6 +171,9 @@ fn to_addressing_mode(csv_mode: &str) -> String {
         "IND" => "Indirect",
         "INDX" => "IndexedXIndirect",
         "INDY" => "IndirectIndexedY",
+        "ZPI" => "ZeroPageIndirect",
+        "ABSXI" => "AbsoluteIndexedIndirect",
+        "ZPREL" => "ZeroPageRelative",
         _ => panic!("unknown addressing mode: {}", csv_mode),
     }
     .to_string()
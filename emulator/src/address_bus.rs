@@ -10,8 +10,8 @@ pub enum SystemVector {
 }
 
 pub trait AddressBus {
-    fn fetch_byte_at_pc(&mut self, mem: &mut dyn Memory) -> Result<u8, CpuError>;
-    fn fetch_word_at_pc(&mut self, mem: &mut dyn Memory) -> Result<u16, CpuError>;
+    fn fetch_byte_at_pc<M: Memory + ?Sized>(&mut self, mem: &mut M) -> Result<u8, CpuError>;
+    fn fetch_word_at_pc<M: Memory + ?Sized>(&mut self, mem: &mut M) -> Result<u16, CpuError>;
     fn set_pc(&mut self, address: u16) -> Result<(), CpuError>;
     fn get_pc(&self) -> u16;
 }
@@ -28,16 +28,17 @@ impl AddressBusImpl {
 }
 
 impl AddressBus for AddressBusImpl {
-    fn fetch_byte_at_pc(&mut self, mem: &mut dyn Memory) -> Result<u8, CpuError> {
+    fn fetch_byte_at_pc<M: Memory + ?Sized>(&mut self, mem: &mut M) -> Result<u8, CpuError> {
         if mem.get_size() <= self.pc as usize {
             return Err(CpuError::InvalidAddress);
         }
         let op = mem.read(self.pc)?;
-        self.pc += 1;
+        // wraps from 0xFFFF to 0x0000, like the program counter of the 6502
+        self.pc = self.pc.wrapping_add(1);
         Ok(op)
     }
 
-    fn fetch_word_at_pc(&mut self, mem: &mut dyn Memory) -> Result<u16, CpuError> {
+    fn fetch_word_at_pc<M: Memory + ?Sized>(&mut self, mem: &mut M) -> Result<u16, CpuError> {
         // little endian, so low byte is read first:
         let lo = self.fetch_byte_at_pc(mem)? as u16;
         let hi = self.fetch_byte_at_pc(mem)? as u16;
@@ -53,12 +54,6 @@ impl AddressBus for AddressBusImpl {
         self.pc
     }
 }
-impl std::fmt::Debug for dyn AddressBus {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "AddressBus {{ pc: 0x{:04X} }}", self.get_pc(),)
-    }
-}
-
 impl std::fmt::Debug for AddressBusImpl {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "AddressBus {{ pc: 0x{:04X} }}", self.pc,)
@@ -83,7 +78,7 @@ mod tests {
             fn get_size(&self) -> usize;
             fn load_program(&mut self, start_addr: u16, program: &[u8]) -> Result<(), CpuError>;
             fn write_zero_page_word(&mut self, address: u8, value: u16) -> Result<(), CpuError>;
-            fn add_readonly(&mut self, range: ops::Range<u16>) -> Result<(), CpuError>;
+            fn add_readonly(&mut self, range: ops::RangeInclusive<u16>) -> Result<(), CpuError>;
             fn clear_readonly_ranges(&mut self);
         }
     }
@@ -99,6 +94,24 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn fetch_wraps_at_top_of_memory() -> Result<(), CpuError> {
+        let mut bus = AddressBusImpl::new();
+        let mut mem = Mock_Memory::new();
+        mem.expect_read().with(eq(0xFFFF)).returning(|_| Ok(0x34));
+        mem.expect_read().with(eq(0x0000)).returning(|_| Ok(0x12));
+        mem.expect_get_size().returning(|| 0x10000);
+
+        bus.set_pc(0xFFFF)?;
+        assert_eq!(bus.fetch_byte_at_pc(&mut mem)?, 0x34);
+        assert_eq!(bus.get_pc(), 0x0000);
+
+        bus.set_pc(0xFFFF)?;
+        assert_eq!(bus.fetch_word_at_pc(&mut mem)?, 0x1234);
+        assert_eq!(bus.get_pc(), 0x0001);
+        Ok(())
+    }
+
     #[test]
     fn has_debug_fmt() {
         let bus = AddressBusImpl::new();
@@ -0,0 +1,341 @@
+use std::fmt;
+
+use crate::CpuError;
+use crate::memory::Memory;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegisterName {
+    Accumulator,
+    IndexX,
+    IndexY,
+    StackPointer,
+    ProgramCounter,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlagName {
+    Negative,
+    Overflow,
+    Decimal,
+    InterruptDisable,
+    Zero,
+    Carry,
+}
+
+impl FlagName {
+    fn mask(&self) -> u8 {
+        match self {
+            FlagName::Negative => 0b1000_0000,
+            FlagName::Overflow => 0b0100_0000,
+            FlagName::Decimal => 0b0000_1000,
+            FlagName::InterruptDisable => 0b0000_0100,
+            FlagName::Zero => 0b0000_0010,
+            FlagName::Carry => 0b0000_0001,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    BitAnd,
+    BitOr,
+}
+
+/// Expression of a breakpoint condition; the condition holds if it evaluates to non-zero.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(u16),
+    Register(RegisterName),
+    Flag(FlagName),
+    Byte(Box<Expr>), // memory byte at address
+    Word(Box<Expr>), // little endian memory word at address
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+/// Registers as seen by a condition, before the instruction at program_counter executes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Registers {
+    pub accumulator: u8,
+    pub index_x: u8,
+    pub index_y: u8,
+    pub stack_pointer: u8,
+    pub program_counter: u16,
+    pub status: u8,
+}
+
+impl Expr {
+    /// Evaluates the expression; memory is read without side effects.
+    pub fn eval(&self, registers: &Registers, memory: &dyn Memory) -> Result<i32, CpuError> {
+        let value = match self {
+            Expr::Number(value) => *value as i32,
+            Expr::Register(name) => match name {
+                RegisterName::Accumulator => registers.accumulator as i32,
+                RegisterName::IndexX => registers.index_x as i32,
+                RegisterName::IndexY => registers.index_y as i32,
+                RegisterName::StackPointer => registers.stack_pointer as i32,
+                RegisterName::ProgramCounter => registers.program_counter as i32,
+            },
+            Expr::Flag(name) => (registers.status & name.mask() != 0) as i32,
+            Expr::Byte(address) => {
+                let address = address.eval(registers, memory)? as u16;
+                memory.peek(address)? as i32
+            }
+            Expr::Word(address) => {
+                let address = address.eval(registers, memory)? as u16;
+                let lo = memory.peek(address)? as i32;
+                let hi = memory.peek(address.wrapping_add(1))? as i32;
+                (hi << 8) | lo
+            }
+            Expr::Unary(UnaryOp::Not, operand) => (operand.eval(registers, memory)? == 0) as i32,
+            Expr::Binary(op, left, right) => {
+                let left = left.eval(registers, memory)?;
+                // short circuit, like in Rust:
+                match op {
+                    BinaryOp::And if left == 0 => return Ok(0),
+                    BinaryOp::Or if left != 0 => return Ok(1),
+                    _ => {}
+                }
+                let right = right.eval(registers, memory)?;
+                match op {
+                    BinaryOp::Or | BinaryOp::And => (right != 0) as i32,
+                    BinaryOp::Eq => (left == right) as i32,
+                    BinaryOp::Ne => (left != right) as i32,
+                    BinaryOp::Lt => (left < right) as i32,
+                    BinaryOp::Le => (left <= right) as i32,
+                    BinaryOp::Gt => (left > right) as i32,
+                    BinaryOp::Ge => (left >= right) as i32,
+                    BinaryOp::Add => left.wrapping_add(right),
+                    BinaryOp::Sub => left.wrapping_sub(right),
+                    BinaryOp::BitAnd => left & right,
+                    BinaryOp::BitOr => left | right,
+                }
+            }
+        };
+        Ok(value)
+    }
+
+    pub fn is_true(&self, registers: &Registers, memory: &dyn Memory) -> Result<bool, CpuError> {
+        Ok(self.eval(registers, memory)? != 0)
+    }
+
+    // binding strength of the expression's operator; like in Rust, higher binds tighter
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Binary(op, _, _) => match op {
+                BinaryOp::Or => 1,
+                BinaryOp::And => 2,
+                BinaryOp::Eq
+                | BinaryOp::Ne
+                | BinaryOp::Lt
+                | BinaryOp::Le
+                | BinaryOp::Gt
+                | BinaryOp::Ge => 3,
+                BinaryOp::BitOr => 4,
+                BinaryOp::BitAnd => 5,
+                BinaryOp::Add | BinaryOp::Sub => 6,
+            },
+            Expr::Unary(_, _) => 7,
+            _ => 8,
+        }
+    }
+}
+
+impl BinaryOp {
+    fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOp::Or => "||",
+            BinaryOp::And => "&&",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::BitAnd => "&",
+            BinaryOp::BitOr => "|",
+        }
+    }
+}
+
+// formats an operand, in parentheses if it binds weaker than its context
+fn fmt_operand(f: &mut fmt::Formatter<'_>, expr: &Expr, min_precedence: u8) -> fmt::Result {
+    if expr.precedence() < min_precedence {
+        write!(f, "({})", expr)
+    } else {
+        write!(f, "{}", expr)
+    }
+}
+
+/// Formats the expression in the debugger's condition syntax, e.g. for conditions
+/// restored from a snapshot.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Number(value) if *value > 0xFF => write!(f, "${:04X}", value),
+            Expr::Number(value) => write!(f, "${:02X}", value),
+            Expr::Register(name) => f.write_str(match name {
+                RegisterName::Accumulator => "A",
+                RegisterName::IndexX => "X",
+                RegisterName::IndexY => "Y",
+                RegisterName::StackPointer => "SP",
+                RegisterName::ProgramCounter => "PC",
+            }),
+            Expr::Flag(name) => f.write_str(match name {
+                FlagName::Negative => "N",
+                FlagName::Overflow => "V",
+                FlagName::Decimal => "D",
+                FlagName::InterruptDisable => "I",
+                FlagName::Zero => "Z",
+                FlagName::Carry => "C",
+            }),
+            Expr::Byte(address) => write!(f, "mem[{}]", address),
+            Expr::Word(address) => write!(f, "word[{}]", address),
+            Expr::Unary(UnaryOp::Not, operand) => {
+                f.write_str("!")?;
+                fmt_operand(f, operand, self.precedence())
+            }
+            Expr::Binary(op, left, right) => {
+                // operators are left associative:
+                fmt_operand(f, left, self.precedence())?;
+                write!(f, " {} ", op.symbol())?;
+                fmt_operand(f, right, self.precedence() + 1)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::MemoryImpl;
+
+    fn binary(op: BinaryOp, left: Expr, right: Expr) -> Expr {
+        Expr::Binary(op, Box::new(left), Box::new(right))
+    }
+
+    fn registers() -> Registers {
+        Registers {
+            accumulator: 0x12,
+            index_x: 0x03,
+            index_y: 0x00,
+            stack_pointer: 0xFD,
+            program_counter: 0x0205,
+            status: 0b0000_0011,
+        }
+    }
+
+    #[test]
+    fn eval_registers_flags_and_memory() -> Result<(), CpuError> {
+        let mut memory = MemoryImpl::default();
+        memory.write_word(0x40, 0x1234)?;
+        let regs = registers();
+
+        let a = Expr::Register(RegisterName::Accumulator);
+        assert_eq!(a.eval(&regs, &memory)?, 0x12);
+        let pc = Expr::Register(RegisterName::ProgramCounter);
+        assert_eq!(pc.eval(&regs, &memory)?, 0x0205);
+        assert_eq!(Expr::Flag(FlagName::Zero).eval(&regs, &memory)?, 1);
+        assert_eq!(Expr::Flag(FlagName::Negative).eval(&regs, &memory)?, 0);
+
+        let byte = Expr::Byte(Box::new(binary(
+            BinaryOp::Add,
+            Expr::Number(0x3E),
+            Expr::Register(RegisterName::IndexX),
+        )));
+        assert_eq!(byte.eval(&regs, &memory)?, 0x12);
+        let word = Expr::Word(Box::new(Expr::Number(0x40)));
+        assert_eq!(word.eval(&regs, &memory)?, 0x1234);
+        Ok(())
+    }
+
+    #[test]
+    fn eval_operators() -> Result<(), CpuError> {
+        let memory = MemoryImpl::default();
+        let regs = registers();
+        let n = Expr::Number;
+
+        // A == $12 && X > 3
+        let condition = binary(
+            BinaryOp::And,
+            binary(
+                BinaryOp::Eq,
+                Expr::Register(RegisterName::Accumulator),
+                n(0x12),
+            ),
+            binary(BinaryOp::Gt, Expr::Register(RegisterName::IndexX), n(3)),
+        );
+        assert!(!condition.is_true(&regs, &memory)?);
+        let negated = Expr::Unary(UnaryOp::Not, Box::new(condition));
+        assert!(negated.is_true(&regs, &memory)?);
+
+        assert_eq!(binary(BinaryOp::Sub, n(1), n(2)).eval(&regs, &memory)?, -1);
+        assert_eq!(
+            binary(BinaryOp::BitAnd, n(0xF0), n(0x3C)).eval(&regs, &memory)?,
+            0x30
+        );
+        assert_eq!(
+            binary(BinaryOp::BitOr, n(0xF0), n(0x0F)).eval(&regs, &memory)?,
+            0xFF
+        );
+        assert_eq!(binary(BinaryOp::Or, n(0), n(7)).eval(&regs, &memory)?, 1);
+        assert_eq!(binary(BinaryOp::Le, n(3), n(3)).eval(&regs, &memory)?, 1);
+        assert_eq!(binary(BinaryOp::Ne, n(3), n(3)).eval(&regs, &memory)?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn display_in_condition_syntax() {
+        let a = || Expr::Register(RegisterName::Accumulator);
+        // A == $12 && mem[$40 + X] > 3
+        let condition = binary(
+            BinaryOp::And,
+            binary(BinaryOp::Eq, a(), Expr::Number(0x12)),
+            binary(
+                BinaryOp::Gt,
+                Expr::Byte(Box::new(binary(
+                    BinaryOp::Add,
+                    Expr::Number(0x40),
+                    Expr::Register(RegisterName::IndexX),
+                ))),
+                Expr::Number(3),
+            ),
+        );
+        assert_eq!(condition.to_string(), "A == $12 && mem[$40 + X] > $03");
+
+        // (A | $80) - (1 - word[$FFFE]), !(Z || C)
+        let n = Expr::Number;
+        let sum = binary(
+            BinaryOp::Sub,
+            binary(BinaryOp::BitOr, a(), n(0x80)),
+            binary(BinaryOp::Sub, n(1), Expr::Word(Box::new(n(0xFFFE)))),
+        );
+        assert_eq!(sum.to_string(), "(A | $80) - ($01 - word[$FFFE])");
+        let negated = Expr::Unary(
+            UnaryOp::Not,
+            Box::new(binary(
+                BinaryOp::Or,
+                Expr::Flag(FlagName::Zero),
+                Expr::Flag(FlagName::Carry),
+            )),
+        );
+        assert_eq!(negated.to_string(), "!(Z || C)");
+    }
+}
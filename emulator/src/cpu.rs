@@ -1,24 +1,30 @@
 use crate::disassembler::disassemble;
-use crate::{Cpu, CpuType};
+use crate::memory::Memory;
+use crate::snapshot::MachineState;
+use crate::{
+    Breakpoint, Cpu, CpuOptions, CpuType, Expr, RunLimits, StopReason, Tracer, TrapHandler,
+    Watchpoint,
+};
 use crate::{CpuError, CpuImpl, CpuRegisterSnapshot};
 
-pub struct CpuControllerImpl {
-    cpu: CpuImpl,
+// the Cpu trait object wraps a core that is monomorphized for its bus,
+// so dynamic dispatch only happens per API call, not per memory access
+pub struct CpuControllerImpl<B: Memory> {
+    cpu: CpuImpl<B>,
 }
 
-impl CpuControllerImpl {
-    pub fn create(kind: CpuType) -> Result<Box<dyn Cpu>, CpuError> {
-        let mut cpu = match kind {
-            CpuType::MOS6502 => CpuControllerImpl {
-                cpu: CpuImpl::default(),
-            },
+impl<B: Memory + 'static> CpuControllerImpl<B> {
+    pub fn create(kind: CpuType, bus: B, options: CpuOptions) -> Result<Box<dyn Cpu>, CpuError> {
+        let mut cpu = CpuControllerImpl {
+            cpu: CpuImpl::with_bus(kind, bus),
         };
-        cpu.reset()?;
+        cpu.cpu.set_illegal_opcode_policy(options.illegal_opcodes);
+        cpu.cpu.power_on()?;
         Ok(Box::new(cpu))
     }
 }
 
-impl Cpu for CpuControllerImpl {
+impl<B: Memory> Cpu for CpuControllerImpl<B> {
     fn reset(&mut self) -> Result<(), CpuError> {
         self.cpu.reset()
     }
@@ -47,11 +53,45 @@ impl Cpu for CpuControllerImpl {
         Ok(self.cpu.get_register_snapshot())
     }
 
+    fn run_limited(
+        &mut self,
+        limits: RunLimits,
+    ) -> Result<(StopReason, CpuRegisterSnapshot), CpuError> {
+        let reason = self.cpu.run_limited(limits)?;
+        Ok((reason, self.cpu.get_register_snapshot()))
+    }
+
     fn step(&mut self) -> Result<CpuRegisterSnapshot, CpuError> {
         self.cpu.step()?;
         Ok(self.cpu.get_register_snapshot())
     }
 
+    fn step_back(&mut self) -> Result<Option<CpuRegisterSnapshot>, CpuError> {
+        match self.cpu.step_back()? {
+            true => Ok(Some(self.cpu.get_register_snapshot())),
+            false => Ok(None),
+        }
+    }
+
+    fn reverse_continue(&mut self) -> Result<Option<CpuRegisterSnapshot>, CpuError> {
+        match self.cpu.reverse_continue()? {
+            true => Ok(Some(self.cpu.get_register_snapshot())),
+            false => Ok(None),
+        }
+    }
+
+    fn set_history_limit(&mut self, steps: usize) {
+        self.cpu.set_history_limit(steps);
+    }
+
+    fn set_clock_speed(&mut self, hz: Option<f64>) {
+        self.cpu.set_clock_speed(hz);
+    }
+
+    fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.cpu.set_tracer(tracer);
+    }
+
     fn get_register_snapshot(&self) -> CpuRegisterSnapshot {
         self.cpu.get_register_snapshot()
     }
@@ -60,11 +100,16 @@ impl Cpu for CpuControllerImpl {
         let mut disassembled_lines = Vec::new();
         let mut cnt = lines;
         let mut next_addr = start_addr;
-        while next_addr != 0 && cnt > 0 {
+        while cnt > 0 {
             let line: String;
-            (line, next_addr) = disassemble(&self.cpu, next_addr)?;
+            let addr = next_addr;
+            (line, next_addr) = disassemble(&self.cpu, addr)?;
             disassembled_lines.push(line);
             cnt -= 1;
+            // stop at the end of the address space:
+            if next_addr <= addr {
+                break;
+            }
         }
         Ok((disassembled_lines, next_addr))
     }
@@ -76,4 +121,70 @@ impl Cpu for CpuControllerImpl {
     fn set_byte_at(&mut self, address: u16, value: u8) -> Result<(), CpuError> {
         self.cpu.set_byte_at(address, value)
     }
+
+    fn peek(&self, address: u16) -> Result<u8, CpuError> {
+        self.cpu.peek(address)
+    }
+
+    fn poke(&mut self, address: u16, value: u8) -> Result<(), CpuError> {
+        self.cpu.poke(address, value)
+    }
+
+    fn assert_irq(&mut self) {
+        self.cpu.assert_irq();
+    }
+
+    fn release_irq(&mut self) {
+        self.cpu.release_irq();
+    }
+
+    fn trigger_nmi(&mut self) {
+        self.cpu.trigger_nmi();
+    }
+
+    fn add_conditional_breakpoint(
+        &mut self,
+        address: u16,
+        condition: Option<Expr>,
+        ignore_count: u32,
+    ) {
+        self.cpu.add_breakpoint(address, condition, ignore_count);
+    }
+
+    fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.cpu.remove_breakpoint(address)
+    }
+
+    fn get_breakpoints(&self) -> Vec<Breakpoint> {
+        self.cpu.get_breakpoints()
+    }
+
+    fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.cpu.add_watchpoint(watchpoint);
+    }
+
+    fn remove_watchpoint(&mut self, watchpoint: &Watchpoint) -> bool {
+        self.cpu.remove_watchpoint(watchpoint)
+    }
+
+    fn get_watchpoints(&self) -> Vec<Watchpoint> {
+        self.cpu.get_watchpoints()
+    }
+
+    fn set_brk_trap(&mut self, enabled: bool) {
+        self.cpu.set_brk_trap(enabled);
+    }
+
+    fn add_trap_handler(&mut self, address: u16, handler: Box<TrapHandler>) {
+        self.cpu.add_trap_handler(address, handler);
+    }
+
+    fn save_state(&self, writer: &mut dyn std::io::Write) -> Result<(), CpuError> {
+        self.cpu.save_state()?.write_to(writer)
+    }
+
+    fn load_state(&mut self, reader: &mut dyn std::io::Read) -> Result<(), CpuError> {
+        let state = MachineState::read_from(reader)?;
+        self.cpu.load_state(state)
+    }
 }
@@ -1,16 +1,25 @@
+use std::collections::VecDeque;
 use std::time::{Duration, Instant};
 
-use crate::CpuError;
 use crate::address_bus::AddressBusImpl;
 use crate::address_bus::{AddressBus, SystemVector};
-use crate::cpu_traps::{TrapDoor, TrapOutcomeStatus};
+use crate::condition::{Expr, Registers};
+use crate::cpu_traps::{
+    AccessKind, Breakpoint, TrapDoor, TrapHandler, TrapOutcomeStatus, TrapResult, WatchHit,
+    Watchpoint,
+};
+use crate::disassembler::disassemble;
 use crate::engine::decoder;
 use crate::engine::decoder::DecodedInstruction;
+use crate::journal::{JournaledMemory, UndoRecord};
 use crate::memory::Memory;
 use crate::memory::MemoryImpl;
+use crate::snapshot::MachineState;
 use crate::stack_pointer::StackPointer;
 use crate::stack_pointer::StackPointerImpl;
 use crate::status_register::StatusRegister;
+use crate::trace::{self, Tracer};
+use crate::{CpuError, CpuType, ExecutionError, IllegalOpcodePolicy, RunLimits, StopReason};
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum AddressingMode {
@@ -27,19 +36,61 @@ pub enum AddressingMode {
     Indirect,         // JMP ($1234)
     IndexedXIndirect, // LDA ($10,X)
     IndirectIndexedY, // LDA ($10),Y
+
+    // 65C02 only:
+    ZeroPageIndirect,        // LDA ($10)
+    AbsoluteIndexedIndirect, // JMP ($1234,X)
+    ZeroPageRelative,        // BBR0 $10,$20
 }
 
+// steps between checks of a run's deadline
+const DEADLINE_CHECK_INTERVAL: u32 = 256;
+// an endless loop is detected if the registers repeat within this many instructions
+const LOOP_WINDOW: usize = 8;
+// a throttled run sleeps after each batch of this many seconds of emulated cycles
+const THROTTLE_INTERVAL: f64 = 0.01;
+
+/// The CPU core, generic over the memory bus it runs on, so that the memory accesses of
+/// an instruction are static calls that can be inlined.
 #[derive(Debug)]
-pub struct CpuImpl {
+pub struct CpuImpl<B: Memory = MemoryImpl> {
+    cpu_type: CpuType,
+    illegal_opcodes: IllegalOpcodePolicy,
     pub accumulator: u8,
     pub index_x: u8,
     pub index_y: u8,
     pub status: StatusRegister,
 
-    pub memory: Box<dyn Memory>, // TODO: should be reverted back to private
-    pub address_bus: Box<dyn AddressBus>, // TODO: should be reverted back to private
-    pub stack: Box<dyn StackPointer>, // TODO: should be reverted back to private
+    pub memory: JournaledMemory<B>, // TODO: should be reverted back to private
+    pub address_bus: AddressBusImpl, // TODO: should be reverted back to private
+    pub stack: StackPointerImpl,    // TODO: should be reverted back to private
     traps: TrapDoor,
+    halted: bool,  // e.g. by a JAM instruction, until next reset
+    waiting: bool, // by a WAI instruction, until next interrupt or reset
+
+    // interrupt lines, serviced between instructions:
+    irq_asserted: bool, // level triggered, masked by the I flag
+    nmi_pending: bool,  // edge triggered, latched until serviced
+
+    // undo records of the most recent steps, for stepping back:
+    history: VecDeque<UndoRecord>,
+    history_limit: usize,
+
+    // the watchpoint that stopped the last step:
+    watch_hit: Option<WatchHit>,
+    // the instruction a breakpoint or watchpoint stopped before;
+    // it is executed when resuming, instead of stopping again
+    resume_pc: Option<u16>,
+
+    // target clock frequency in Hz, None runs as fast as the host allows:
+    clock_speed: Option<f64>,
+
+    // writes a line per executed instruction:
+    tracer: Option<Tracer>,
+
+    // cycle penalties of the currently executing instruction:
+    page_crossed: bool,
+    extra_cycles: u8,
 
     // stats counters:
     elapsed_time: Duration,
@@ -56,33 +107,75 @@ impl Default for CpuImpl {
 
 impl CpuImpl {
     pub fn new() -> CpuImpl {
+        Self::with_cpu_type(CpuType::MOS6502)
+    }
+
+    pub fn with_cpu_type(cpu_type: CpuType) -> CpuImpl {
+        Self::with_bus(cpu_type, MemoryImpl::default())
+    }
+}
+
+impl<B: Memory> CpuImpl<B> {
+    pub fn with_bus(cpu_type: CpuType, bus: B) -> CpuImpl<B> {
         CpuImpl {
+            cpu_type,
+            illegal_opcodes: IllegalOpcodePolicy::default(),
             accumulator: 0,
             index_x: 0,
             index_y: 0,
             status: StatusRegister::new(),
-            memory: Box::<MemoryImpl>::default(),
-            address_bus: Box::new(AddressBusImpl::new()),
-            stack: Box::new(StackPointerImpl::new()),
+            memory: JournaledMemory::new(bus),
+            address_bus: AddressBusImpl::new(),
+            stack: StackPointerImpl::new(),
             accumulated_cycles: 0,
             accumulated_instructions: 0,
             approximate_clock_speed: 0.0,
             elapsed_time: Duration::new(0, 0),
             traps: TrapDoor::new(),
+            halted: false,
+            waiting: false,
+            irq_asserted: false,
+            nmi_pending: false,
+            history: VecDeque::new(),
+            history_limit: 0,
+            watch_hit: None,
+            resume_pc: None,
+            clock_speed: None,
+            tracer: None,
+            page_crossed: false,
+            extra_cycles: 0,
         }
     }
 
-    pub fn reset(&mut self) -> Result<(), CpuError> {
-        self.stack.reset()?;
-
-        self.address_bus.set_pc(SystemVector::Reset as u16)?;
+    /// Cold start: clears all registers, then runs the reset sequence; SP starts at 0x0100
+    /// so that it ends up at 0x01FD like on the real hardware.
+    pub fn power_on(&mut self) -> Result<(), CpuError> {
         self.accumulator = 0;
         self.index_x = 0;
         self.index_y = 0;
-        self.status.update_from(self.accumulator);
+        self.status.set_status(0);
+        self.stack.set_sp(0x0100)?;
+        self.reset()
+    }
+
+    /// Warm reset: the reset sequence is an interrupt with suppressed writes to the stack,
+    /// which then continues at the address read from the reset vector. A, X and Y are unchanged.
+    pub fn reset(&mut self) -> Result<(), CpuError> {
+        let sp = self.stack.get_sp()? as u8;
+        self.stack.set_sp(0x0100 | sp.wrapping_sub(3) as u16)?;
+        self.status.set_interrupt_disable(true);
+        if self.cpu_type == CpuType::WDC65C02 {
+            self.status.set_decimal_mode(false);
+        }
+        let address = self.memory.read_word(SystemVector::Reset as u16)?;
+        self.address_bus.set_pc(address)?;
 
         self.accumulated_cycles = 0;
         self.accumulated_instructions = 0;
+        self.halted = false;
+        self.waiting = false;
+        self.nmi_pending = false;
+        self.history.clear();
 
         Ok(())
     }
@@ -94,9 +187,15 @@ impl CpuImpl {
         is_readonly: bool,
     ) -> Result<(), CpuError> {
         self.memory.load_program(start_addr, program)?;
-        if is_readonly {
-            self.memory
-                .add_readonly(start_addr..start_addr + program.len() as u16)?;
+        if is_readonly && !program.is_empty() {
+            let end_addr = start_addr.wrapping_add((program.len() - 1) as u16);
+            if end_addr >= start_addr {
+                self.memory.add_readonly(start_addr..=end_addr)?;
+            } else {
+                // wrapped around to the zero page:
+                self.memory.add_readonly(start_addr..=0xFFFF)?;
+                self.memory.add_readonly(0x0000..=end_addr)?;
+            }
         }
         Ok(())
     }
@@ -109,48 +208,474 @@ impl CpuImpl {
         self.address_bus.get_pc()
     }
 
+    /// Runs from start_addr, or from the address read from the reset vector.
     pub fn run(&mut self, start_addr: Option<u16>) -> Result<(), CpuError> {
-        self.address_bus.set_pc(if start_addr.is_some() {
-            start_addr.unwrap()
-        } else {
-            SystemVector::Reset as u16
-        })?;
+        let start_addr = match start_addr {
+            Some(address) => address,
+            None => self.memory.read_word(SystemVector::Reset as u16)?,
+        };
+        self.address_bus.set_pc(start_addr)?;
+        self.run_limited(RunLimits::default())?;
+        Ok(())
+    }
+
+    /// Runs from the current PC until stopped, or until one of the limits is reached.
+    pub fn run_limited(&mut self, limits: RunLimits) -> Result<StopReason, CpuError> {
         let start = Instant::now();
-        loop {
-            let is_break = self.step()?;
+        let start_cycles = self.accumulated_cycles;
+        let start_instructions = self.accumulated_instructions;
+        let mut steps: u32 = 0;
+        let batch_cycles = self
+            .clock_speed
+            .map_or(0, |hz| ((hz * THROTTLE_INTERVAL) as u64).max(1));
+        let mut next_sync = batch_cycles;
+        // registers and write count after the most recent steps:
+        let mut recent_states: VecDeque<(Registers, u64)> = VecDeque::new();
+        let reason = loop {
+            if limits
+                .max_cycles
+                .is_some_and(|max| self.accumulated_cycles - start_cycles >= max)
+            {
+                break StopReason::CycleLimit;
+            }
+            if limits
+                .max_instructions
+                .is_some_and(|max| self.accumulated_instructions - start_instructions >= max)
+            {
+                break StopReason::InstructionLimit;
+            }
+            // reading the clock is expensive compared to a step, so only check it periodically
+            if let Some(deadline) = limits.deadline
+                && steps.is_multiple_of(DEADLINE_CHECK_INTERVAL)
+                && Instant::now() >= deadline
+            {
+                break StopReason::Deadline;
+            }
+            steps = steps.wrapping_add(1);
+
+            if self.step()? {
+                break match self.watch_hit {
+                    Some(hit) => StopReason::Watchpoint(hit),
+                    None => StopReason::Stopped,
+                };
+            }
+            if limits.stop_on_loop {
+                // without writes in between, the same registers repeat forever
+                let state = (
+                    self.registers(self.address_bus.get_pc())?,
+                    self.memory.write_count(),
+                );
+                if recent_states.contains(&state) {
+                    break StopReason::Loop(state.0.program_counter);
+                }
+                if recent_states.len() == LOOP_WINDOW {
+                    recent_states.pop_front();
+                }
+                recent_states.push_back(state);
+            }
 
-            if is_break {
-                break;
+            let run_cycles = self.accumulated_cycles - start_cycles;
+            if let Some(hz) = self.clock_speed
+                && run_cycles >= next_sync
+            {
+                self.throttle(start, run_cycles, hz);
+                next_sync = run_cycles + batch_cycles;
             }
+        };
+        // also pace the last partial batch, so that consecutive bounded runs keep the clock speed
+        if let Some(hz) = self.clock_speed {
+            self.throttle(start, self.accumulated_cycles - start_cycles, hz);
         }
         self.elapsed_time = start.elapsed();
         self.approximate_clock_speed =
-            self.accumulated_cycles as f64 / self.elapsed_time.as_secs_f64();
-        Ok(())
+            (self.accumulated_cycles - start_cycles) as f64 / self.elapsed_time.as_secs_f64();
+        Ok(reason)
+    }
+
+    // sleeps until the wall-clock time of a run catches up with its emulated cycles
+    fn throttle(&self, start: Instant, run_cycles: u64, hz: f64) {
+        let emulated = Duration::from_secs_f64(run_cycles as f64 / hz);
+        let elapsed = start.elapsed();
+        if emulated > elapsed {
+            std::thread::sleep(emulated - elapsed);
+        }
     }
 
+    /// Target clock frequency in Hz that runs are throttled to; None runs as fast as possible.
+    pub fn set_clock_speed(&mut self, hz: Option<f64>) {
+        self.clock_speed = hz;
+    }
+
+    /// Executes one instruction or services a pending interrupt; returns true if execution stopped.
+    /// With a history limit set, each step that changes the machine state is recorded,
+    /// so it can be undone by step_back.
     pub fn step(&mut self) -> Result<bool, CpuError> {
+        let pc = self.address_bus.get_pc();
+        self.watch_hit = None;
+        let resuming = self.resume_pc.take() == Some(pc);
+        let trace_line = self.trace_line(pc);
+        let instructions = self.accumulated_instructions;
+        let outcome = if self.history_limit == 0 && !self.traps.has_watchpoints() {
+            self.execute_step(resuming)
+        } else {
+            self.record_step(resuming)
+        };
+        let stopped = outcome.map_err(|cause| self.execution_error(cause, pc))?;
+        // interrupts, and instructions stopped before or undone by a watchpoint, are not traced:
+        if let Some(line) = trace_line
+            && self.accumulated_instructions != instructions
+            && let Some(tracer) = &mut self.tracer
+        {
+            tracer.write_line(&line)?;
+        }
+        Ok(stopped)
+    }
+
+    /// Writes a trace line for each executed instruction; None stops tracing.
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    // the trace line of the instruction at pc, formatted before it executes
+    fn trace_line(&self, pc: u16) -> Option<String> {
+        let tracer = self.tracer.as_ref()?;
+        if !tracer.is_traced(pc) || self.halted || self.waiting {
+            return None;
+        }
+        let (disassembly, next_pc) = disassemble(self, pc).ok()?;
+        let bytes: Vec<u8> = (0..next_pc.wrapping_sub(pc))
+            .filter_map(|i| self.peek(pc.wrapping_add(i)).ok())
+            .collect();
+        // the disassembly starts with the address, which has its own column:
+        let instruction = disassembly.split_once(' ').map_or("", |(_, i)| i);
+        let registers = self.registers(pc).ok()?;
+        Some(trace::format_line(
+            &registers,
+            &bytes,
+            instruction,
+            self.accumulated_cycles,
+        ))
+    }
+
+    // registers before the instruction at program_counter executes
+    fn registers(&self, program_counter: u16) -> Result<Registers, CpuError> {
+        Ok(Registers {
+            accumulator: self.accumulator,
+            index_x: self.index_x,
+            index_y: self.index_y,
+            stack_pointer: self.stack.get_sp()? as u8,
+            program_counter,
+            status: self.status.get_status(),
+        })
+    }
+
+    fn record_step(&mut self, resuming: bool) -> Result<bool, CpuError> {
+        let mut record = self.undo_record();
+        self.memory.clear_journal(self.traps.has_watchpoints());
+        let outcome = self.execute_step(resuming);
+        record.writes = self.memory.take_journal();
+
+        let pc = record.program_counter;
+        let mut accesses = self.memory.take_accesses();
+        // instruction fetches are not data accesses:
+        let length = self.instruction_length(pc);
+        accesses.retain(|a| a.kind == AccessKind::Write || a.address.wrapping_sub(pc) >= length);
+        let watch_hit = match outcome {
+            Ok(_) => self.traps.check_watchpoints(pc, &accesses),
+            Err(_) => None,
+        };
+        if let Some(hit) = watch_hit
+            && hit.stop_before
+            && !resuming
+        {
+            self.restore(&record)?;
+            self.watch_hit = Some(hit);
+            self.resume_pc = Some(pc);
+            return Ok(true);
+        }
+
+        if self.history_limit > 0
+            && (!record.writes.is_empty()
+                || record.program_counter != self.address_bus.get_pc()
+                || record.accumulated_cycles != self.accumulated_cycles)
+        {
+            if self.history.len() >= self.history_limit {
+                self.history.pop_front();
+            }
+            self.history.push_back(record);
+        }
+        match watch_hit {
+            Some(hit) if !hit.stop_before => {
+                self.watch_hit = Some(hit);
+                Ok(true)
+            }
+            _ => outcome,
+        }
+    }
+
+    /// Adds a breakpoint that stops before the instruction at address, if the condition holds;
+    /// the first ignore_count hits don't stop.
+    pub fn add_breakpoint(&mut self, address: u16, condition: Option<Expr>, ignore_count: u32) {
+        self.traps
+            .add_conditional_address_trap(address, condition, ignore_count);
+    }
+
+    /// Returns false if there was no breakpoint at address.
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.traps.remove_address_trap(address)
+    }
+
+    pub fn get_breakpoints(&self) -> Vec<Breakpoint> {
+        self.traps.get_address_traps()
+    }
+
+    /// Stops runs on data accesses; see Watchpoint.
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.traps.add_watchpoint(watchpoint);
+    }
+
+    /// Returns false if there was no equal watchpoint.
+    pub fn remove_watchpoint(&mut self, watchpoint: &Watchpoint) -> bool {
+        self.traps.remove_watchpoint(watchpoint)
+    }
+
+    pub fn get_watchpoints(&self) -> Vec<Watchpoint> {
+        self.traps.get_watchpoints()
+    }
+
+    /// With the BRK trap, a BRK stops execution after it is executed; without it,
+    /// BRK is a software interrupt that continues via the IRQ vector.
+    pub fn set_brk_trap(&mut self, enabled: bool) {
+        self.traps.set_brk_trap(enabled);
+    }
+
+    /// Undoes the most recent recorded step; returns false if there is no history left.
+    pub fn step_back(&mut self) -> Result<bool, CpuError> {
+        let Some(record) = self.history.pop_back() else {
+            return Ok(false);
+        };
+        self.restore(&record)?;
+        Ok(true)
+    }
+
+    fn restore(&mut self, record: &UndoRecord) -> Result<(), CpuError> {
+        self.memory.undo(&record.writes)?;
+        self.accumulator = record.accumulator;
+        self.index_x = record.index_x;
+        self.index_y = record.index_y;
+        self.stack.set_sp(record.stack_pointer)?;
+        self.address_bus.set_pc(record.program_counter)?;
+        self.status.set_status(record.status);
+        self.halted = record.halted;
+        self.waiting = record.waiting;
+        self.nmi_pending = record.nmi_pending;
+        self.accumulated_cycles = record.accumulated_cycles;
+        self.accumulated_instructions = record.accumulated_instructions;
+        Ok(())
+    }
+
+    /// Steps back until the PC is at an address trap, or the history is exhausted;
+    /// returns false if there was no history to step back.
+    pub fn reverse_continue(&mut self) -> Result<bool, CpuError> {
+        if !self.step_back()? {
+            return Ok(false);
+        }
+        while !self.traps.is_address_trap(self.address_bus.get_pc()) && self.step_back()? {}
+        Ok(true)
+    }
+
+    /// Limits the number of steps that can be undone; 0, the default, disables recording.
+    pub fn set_history_limit(&mut self, steps: usize) {
+        self.history_limit = steps;
+        while self.history.len() > steps {
+            self.history.pop_front();
+        }
+    }
+
+    // adds the faulting instruction and the registers to an error
+    fn execution_error(&self, cause: CpuError, pc: u16) -> CpuError {
+        let length = self.instruction_length(pc);
+        let opcode_bytes = (0..length)
+            .filter_map(|i| self.peek(pc.wrapping_add(i)).ok())
+            .collect();
+        CpuError::Execution(Box::new(ExecutionError {
+            cause,
+            pc,
+            opcode_bytes,
+            snapshot: self.get_register_snapshot(),
+        }))
+    }
+
+    // number of bytes of the instruction at address, including its operands
+    fn instruction_length(&self, address: u16) -> u16 {
+        self.peek(address).map_or(1, |op_code| {
+            decoder::decode(op_code, self.cpu_type).extra_bytes as u16 + 1
+        })
+    }
+
+    fn undo_record(&self) -> UndoRecord {
+        UndoRecord {
+            accumulator: self.accumulator,
+            index_x: self.index_x,
+            index_y: self.index_y,
+            stack_pointer: self.stack.get_sp().unwrap(),
+            program_counter: self.address_bus.get_pc(),
+            status: self.status.get_status(),
+            halted: self.halted,
+            waiting: self.waiting,
+            nmi_pending: self.nmi_pending,
+            accumulated_cycles: self.accumulated_cycles,
+            accumulated_instructions: self.accumulated_instructions,
+            writes: vec![],
+        }
+    }
+
+    fn execute_step(&mut self, resuming: bool) -> Result<bool, CpuError> {
+        if self.halted {
+            return Ok(true);
+        }
+        if self.waiting {
+            // any interrupt wakes up the CPU, even if the IRQ is masked:
+            if !self.nmi_pending && !self.irq_asserted {
+                return Ok(true);
+            }
+            self.waiting = false;
+        }
+        if self.nmi_pending {
+            self.nmi_pending = false;
+            self.service_interrupt(SystemVector::NMI)?;
+            return Ok(false);
+        }
+        if self.irq_asserted && !self.status.interrupt_disable() {
+            self.service_interrupt(SystemVector::IRQ)?;
+            return Ok(false);
+        }
         let address = self.address_bus.get_pc();
         let decoded = self.fetch_and_decode()?;
 
-        let outcome = self.traps.pre_execute(decoded.clone(), address)?;
+        let registers = self.registers(address)?;
+        let outcome =
+            self.traps
+                .pre_execute(decoded, address, resuming, &registers, &self.memory)?;
 
         match outcome.status {
             TrapOutcomeStatus::Continue | TrapOutcomeStatus::StopAfter => {
                 // execute instruction:
-                (decoded.execute)(decoded.mode, self)?;
+                self.page_crossed = false;
+                self.extra_cycles = 0;
+                decoder::execute(decoded, self)?;
                 self.accumulated_instructions += 1;
-                self.accumulated_cycles += decoded.cycles as u64;
-                Ok(outcome.status == TrapOutcomeStatus::StopAfter)
+                self.accumulated_cycles += self.instruction_cycles(decoded) as u64;
+                Ok(outcome.status == TrapOutcomeStatus::StopAfter || self.halted || self.waiting)
             }
             TrapOutcomeStatus::Handled => {
-                // TODO: transfer trap result to CPU registers
+                self.call_trap_handler(address)?;
                 Ok(false)
             }
-            TrapOutcomeStatus::Stop => Ok(true),
+            TrapOutcomeStatus::Stop => {
+                // stop before the instruction, so that resuming executes it
+                self.address_bus.set_pc(address)?;
+                self.resume_pc = Some(address);
+                Ok(true)
+            }
         }
     }
 
+    /// Registers a Rust handler that replaces the routine at address; see TrapHandler.
+    pub fn add_trap_handler(&mut self, address: u16, handler: Box<TrapHandler>) {
+        self.traps.add_handler(address, handler);
+    }
+
+    // transfers the handler's result to the registers, then returns like RTS
+    fn call_trap_handler(&mut self, address: u16) -> Result<(), CpuError> {
+        let return_address = self.trap_return_address()?;
+        let registers = TrapResult {
+            accumulator: self.accumulator,
+            index_x: self.index_x,
+            index_y: self.index_y,
+            status: self.status.get_status(),
+        };
+        let result = self
+            .traps
+            .call_handler(address, registers, &mut self.memory)?;
+        self.accumulator = result.accumulator;
+        self.index_x = result.index_x;
+        self.index_y = result.index_y;
+        self.status.set_status(result.status);
+
+        self.stack.pop_word(&self.memory)?;
+        self.address_bus.set_pc(return_address.wrapping_add(1))?;
+        self.accumulated_instructions += 1;
+        self.accumulated_cycles += 6;
+        Ok(())
+    }
+
+    // the return address on top of the stack, e.g. pushed by a JSR to the routine or to a
+    // vector that jumps to it; with an empty stack the implicit RTS would have nowhere to go
+    fn trap_return_address(&self) -> Result<u16, CpuError> {
+        let mut stack = self.stack.clone();
+        stack
+            .pop_word(&self.memory)
+            .map_err(|_| CpuError::MissingReturnAddress)
+    }
+
+    pub fn get_cpu_type(&self) -> CpuType {
+        self.cpu_type
+    }
+
+    /// Sets how opcodes the CPU type does not define are executed.
+    pub fn set_illegal_opcode_policy(&mut self, policy: IllegalOpcodePolicy) {
+        self.illegal_opcodes = policy;
+    }
+
+    pub fn get_illegal_opcode_policy(&self) -> IllegalOpcodePolicy {
+        self.illegal_opcodes
+    }
+
+    /// Stops the CPU, further steps are ignored until the next reset.
+    pub fn halt(&mut self) {
+        self.halted = true;
+    }
+
+    /// Asserts the IRQ line; it stays asserted until released, the interrupt is serviced
+    /// before the next instruction while the I flag is cleared.
+    pub fn assert_irq(&mut self) {
+        self.irq_asserted = true;
+    }
+
+    pub fn release_irq(&mut self) {
+        self.irq_asserted = false;
+    }
+
+    /// Latches an NMI, which is serviced before the next instruction.
+    pub fn trigger_nmi(&mut self) {
+        self.nmi_pending = true;
+    }
+
+    // push PC and status, then continue at the address read from the interrupt vector
+    fn service_interrupt(&mut self, vector: SystemVector) -> Result<(), CpuError> {
+        let pc = self.address_bus.get_pc();
+        self.stack.push_word(&mut self.memory, pc)?;
+        // unlike BRK, the Break flag is cleared on the pushed status value
+        let status = self.status.get_status() & !0b0001_0000;
+        self.stack.push_byte(&mut self.memory, status)?;
+
+        self.status.set_interrupt_disable(true);
+        if self.cpu_type == CpuType::WDC65C02 {
+            self.status.set_decimal_mode(false);
+        }
+        let address = self.memory.read_word(vector as u16)?;
+        self.address_bus.set_pc(address)?;
+        self.accumulated_cycles += 7;
+        Ok(())
+    }
+
+    /// Suspends the CPU until the next interrupt; without interrupt sources, steps are ignored
+    /// until the next reset.
+    pub fn wait_for_interrupt(&mut self) {
+        self.waiting = true;
+    }
+
     pub fn get_register_snapshot(&self) -> crate::CpuRegisterSnapshot {
         crate::CpuRegisterSnapshot {
             accumulator: self.accumulator,
@@ -174,10 +699,89 @@ impl CpuImpl {
         self.memory.write(address, value)
     }
 
-    fn fetch_and_decode(&mut self) -> Result<DecodedInstruction, CpuError> {
-        let opcode_byte = self.address_bus.fetch_byte_at_pc(self.memory.as_mut())?;
-        let res = decoder::decode(opcode_byte)?;
-        Ok(res)
+    pub fn peek(&self, address: u16) -> Result<u8, CpuError> {
+        self.memory.peek(address)
+    }
+
+    pub fn poke(&mut self, address: u16, value: u8) -> Result<(), CpuError> {
+        self.memory.poke(address, value)
+    }
+
+    pub fn save_state(&self) -> Result<MachineState, CpuError> {
+        let memory = (0..self.memory.get_size())
+            .map(|address| self.memory.peek(address as u16))
+            .collect::<Result<Vec<u8>, CpuError>>()?;
+        Ok(MachineState {
+            cpu_type: self.cpu_type,
+            accumulator: self.accumulator,
+            index_x: self.index_x,
+            index_y: self.index_y,
+            stack_pointer: self.stack.get_sp()? as u8,
+            program_counter: self.address_bus.get_pc(),
+            status: self.status.get_status(),
+            halted: self.halted,
+            waiting: self.waiting,
+            irq_asserted: self.irq_asserted,
+            nmi_pending: self.nmi_pending,
+            accumulated_cycles: self.accumulated_cycles,
+            accumulated_instructions: self.accumulated_instructions,
+            memory,
+            readonly_ranges: self.memory.get_readonly_ranges(),
+            traps: self.traps.get_traps(),
+        })
+    }
+
+    /// Restores a saved machine state; the memory image is loaded underneath any attached devices.
+    pub fn load_state(&mut self, state: MachineState) -> Result<(), CpuError> {
+        if state.memory.len() != self.memory.get_size() {
+            return Err(CpuError::InvalidState(format!(
+                "memory size {} does not match {}",
+                state.memory.len(),
+                self.memory.get_size()
+            )));
+        }
+        self.memory.clear_readonly_ranges();
+        self.memory.load_program(0, &state.memory)?;
+        for range in state.readonly_ranges {
+            self.memory.add_readonly(range)?;
+        }
+        self.traps.set_traps(state.traps);
+
+        self.cpu_type = state.cpu_type;
+        self.accumulator = state.accumulator;
+        self.index_x = state.index_x;
+        self.index_y = state.index_y;
+        self.stack.set_sp(0x0100 | state.stack_pointer as u16)?;
+        self.address_bus.set_pc(state.program_counter)?;
+        self.status.set_status(state.status);
+        self.halted = state.halted;
+        self.waiting = state.waiting;
+        self.irq_asserted = state.irq_asserted;
+        self.nmi_pending = state.nmi_pending;
+        self.accumulated_cycles = state.accumulated_cycles;
+        self.accumulated_instructions = state.accumulated_instructions;
+        self.history.clear();
+        Ok(())
+    }
+
+    /// Adds cycles to the currently executing instruction, e.g. for a taken branch.
+    pub fn add_extra_cycles(&mut self, cycles: u8) {
+        self.extra_cycles += cycles;
+    }
+
+    /// True if the last indexed effective address crossed a page boundary.
+    pub fn is_page_crossed(&self) -> bool {
+        self.page_crossed
+    }
+
+    fn instruction_cycles(&self, decoded: &DecodedInstruction) -> u8 {
+        let page_cross_cycles = (self.page_crossed && decoded.page_cross_penalty) as u8;
+        decoded.cycles + page_cross_cycles + self.extra_cycles
+    }
+
+    fn fetch_and_decode(&mut self) -> Result<&'static DecodedInstruction, CpuError> {
+        let opcode_byte = self.address_bus.fetch_byte_at_pc(&mut self.memory)?;
+        Ok(decoder::decode(opcode_byte, self.cpu_type))
     }
 
     /// Fetches bytes according to addressing mode to calculate effective address;
@@ -185,43 +789,48 @@ impl CpuImpl {
     pub fn get_effective_address(&mut self, mode: AddressingMode) -> Result<u16, CpuError> {
         match mode {
             AddressingMode::ZeroPage => {
-                Ok(self.address_bus.fetch_byte_at_pc(self.memory.as_mut())? as u16)
+                Ok(self.address_bus.fetch_byte_at_pc(&mut self.memory)? as u16)
             }
             AddressingMode::ZeroPageX => Ok(self
                 .address_bus
-                .fetch_byte_at_pc(self.memory.as_mut())?
+                .fetch_byte_at_pc(&mut self.memory)?
                 .wrapping_add(self.index_x) as u16),
             AddressingMode::ZeroPageY => Ok(self
                 .address_bus
-                .fetch_byte_at_pc(self.memory.as_mut())?
+                .fetch_byte_at_pc(&mut self.memory)?
                 .wrapping_add(self.index_y) as u16),
             AddressingMode::Relative => {
-                let offset = self.address_bus.fetch_byte_at_pc(self.memory.as_mut())? as i8;
+                let offset = self.address_bus.fetch_byte_at_pc(&mut self.memory)? as i8;
                 Ok(self.address_bus.get_pc().wrapping_add(offset as u16))
             }
             AddressingMode::Absolute => {
-                let word = self.address_bus.fetch_word_at_pc(self.memory.as_mut())?;
+                let word = self.address_bus.fetch_word_at_pc(&mut self.memory)?;
                 Ok(word)
             }
             AddressingMode::AbsoluteX => {
-                let word = self.address_bus.fetch_word_at_pc(self.memory.as_mut())?;
-                Ok(word + self.index_x as u16)
+                let word = self.address_bus.fetch_word_at_pc(&mut self.memory)?;
+                let address = word.wrapping_add(self.index_x as u16);
+                self.page_crossed = is_page_crossed(word, address);
+                Ok(address)
             }
             AddressingMode::AbsoluteY => {
-                let word = self.address_bus.fetch_word_at_pc(self.memory.as_mut())?;
-                Ok(word + self.index_y as u16)
+                let word = self.address_bus.fetch_word_at_pc(&mut self.memory)?;
+                let address = word.wrapping_add(self.index_y as u16);
+                self.page_crossed = is_page_crossed(word, address);
+                Ok(address)
             }
             AddressingMode::Indirect => {
-                let indirect_addr = self.address_bus.fetch_word_at_pc(self.memory.as_mut())?;
+                let indirect_addr = self.address_bus.fetch_word_at_pc(&mut self.memory)?;
                 // 6502 bug: if low byte is 0xff, then high byte is fetched from non-incremented high byte
-                // i.e. no proper page boundary crossing
+                // i.e. no proper page boundary crossing; fixed on the 65C02
                 let low_indirect = self.memory.read(indirect_addr)? as u16;
 
-                let high_indirect = if indirect_addr & 0xff == 0xff {
-                    self.memory.read(indirect_addr & 0xff00)?
-                } else {
-                    self.memory.read(indirect_addr + 1)?
-                } as u16;
+                let high_indirect =
+                    if indirect_addr & 0xff == 0xff && self.cpu_type != CpuType::WDC65C02 {
+                        self.memory.read(indirect_addr & 0xff00)?
+                    } else {
+                        self.memory.read(indirect_addr.wrapping_add(1))?
+                    } as u16;
                 Ok(high_indirect << 8 | low_indirect)
             }
             AddressingMode::IndexedXIndirect => {
@@ -231,18 +840,31 @@ impl CpuImpl {
             }
             AddressingMode::IndirectIndexedY => {
                 let zero_page_addr = self.get_effective_address(AddressingMode::ZeroPage)?;
-                let word =
-                    self.memory.read_zero_page_word(zero_page_addr as u8)? + self.index_y as u16;
+                let word = self.memory.read_zero_page_word(zero_page_addr as u8)?;
+                let address = word.wrapping_add(self.index_y as u16);
+                self.page_crossed = is_page_crossed(word, address);
+                Ok(address)
+            }
+            AddressingMode::ZeroPageIndirect => {
+                let zero_page_addr = self.get_effective_address(AddressingMode::ZeroPage)?;
+                let word = self.memory.read_zero_page_word(zero_page_addr as u8)?;
                 Ok(word)
             }
-            // Implied, Accumulator, Immediate modes have no address
+            AddressingMode::AbsoluteIndexedIndirect => {
+                let word = self.address_bus.fetch_word_at_pc(&mut self.memory)?;
+                let indirect_addr = word.wrapping_add(self.index_x as u16);
+                let low_indirect = self.memory.read(indirect_addr)? as u16;
+                let high_indirect = self.memory.read(indirect_addr.wrapping_add(1))? as u16;
+                Ok(high_indirect << 8 | low_indirect)
+            }
+            // Implied, Accumulator, Immediate, ZeroPageRelative modes have no single address
             _ => Err(CpuError::InvalidAddressingMode),
         }
     }
 
     pub fn get_effective_operand(&mut self, mode: AddressingMode) -> Result<u8, CpuError> {
         match mode {
-            AddressingMode::Immediate => self.address_bus.fetch_byte_at_pc(self.memory.as_mut()),
+            AddressingMode::Immediate => self.address_bus.fetch_byte_at_pc(&mut self.memory),
             AddressingMode::Accumulator => Ok(self.accumulator),
             AddressingMode::Relative => Err(CpuError::InvalidAddressingMode),
             _ => {
@@ -253,9 +875,14 @@ impl CpuImpl {
     }
 }
 
+pub fn is_page_crossed(from: u16, to: u16) -> bool {
+    from & 0xFF00 != to & 0xFF00
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cpu_traps::WatchKind;
 
     const START_ADDR: u16 = 0x0300;
     const ZERO_PAGE_ADDR: u8 = 0xE0;
@@ -269,7 +896,7 @@ mod tests {
         cpu.address_bus.set_pc(START_ADDR)?;
 
         // prepare PC to point past the opcode:
-        let opcode_read = cpu.address_bus.fetch_byte_at_pc(cpu.memory.as_mut())?;
+        let opcode_read = cpu.address_bus.fetch_byte_at_pc(&mut cpu.memory)?;
         assert_eq!(opcode_read, OP_CODE);
 
         Ok(cpu)
@@ -288,15 +915,482 @@ mod tests {
     #[test]
     fn reset() -> Result<(), CpuError> {
         let mut cpu = CpuImpl::default();
+        cpu.memory.write_word(SystemVector::Reset as u16, 0x1234)?;
+        cpu.accumulator = 0x42;
+        cpu.status.set_carry(true);
+        let sp = cpu.stack.get_sp()?;
 
         cpu.reset()?;
 
-        assert_eq!(cpu.address_bus.get_pc(), SystemVector::Reset as u16);
+        assert_eq!(cpu.address_bus.get_pc(), 0x1234);
+        assert_eq!(cpu.accumulator, 0x42);
+        // I = 1, other flags unchanged:
+        assert_eq!(cpu.status.get_status(), 0b0000_0101);
+        assert_eq!(cpu.stack.get_sp()?, sp - 3);
+        // no writes to the stack:
+        assert_eq!(cpu.memory.read_word(sp - 1)?, 0x0000);
+        Ok(())
+    }
+
+    #[test]
+    fn power_on() -> Result<(), CpuError> {
+        let mut cpu = CpuImpl::new();
+        cpu.accumulator = 0x42;
+        cpu.status.set_carry(true);
+
+        cpu.power_on()?;
+
+        assert_eq!(cpu.address_bus.get_pc(), 0x0000);
+        assert_eq!(cpu.accumulator, 0);
+        assert_eq!(cpu.status.get_status(), 0b0000_0100);
+        assert_eq!(cpu.stack.get_sp()?, 0x01FD);
+        Ok(())
+    }
+
+    #[test]
+    fn run_follows_reset_vector() -> Result<(), CpuError> {
+        let mut cpu = CpuImpl::default();
+        // LDA #$42, BRK
+        cpu.load_program(START_ADDR, &[0xA9, 0x42, 0x00], false)?;
+        cpu.memory
+            .write_word(SystemVector::Reset as u16, START_ADDR)?;
+        cpu.run(None)?;
+        assert_eq!(cpu.accumulator, 0x42);
+        assert_eq!(cpu.accumulated_instructions, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn run_limited_stops_endless_loop() -> Result<(), CpuError> {
+        let mut cpu = CpuImpl::new();
+        // JMP $0300
+        cpu.load_program(START_ADDR, &[0x4C, 0x00, 0x03], false)?;
+        cpu.set_pc(START_ADDR)?;
+
+        let cycles = RunLimits {
+            max_cycles: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(cpu.run_limited(cycles)?, StopReason::CycleLimit);
+        // JMP takes 3 cycles:
+        assert_eq!(cpu.accumulated_cycles, 12);
+
+        // limits are relative to the start of each run:
+        let instructions = RunLimits {
+            max_instructions: Some(5),
+            ..Default::default()
+        };
+        assert_eq!(cpu.run_limited(instructions)?, StopReason::InstructionLimit);
+        assert_eq!(cpu.accumulated_instructions, 9);
+
+        let deadline = RunLimits {
+            deadline: Some(Instant::now() + Duration::from_millis(5)),
+            ..Default::default()
+        };
+        assert_eq!(cpu.run_limited(deadline)?, StopReason::Deadline);
+        Ok(())
+    }
+
+    #[test]
+    fn run_limited_detects_loops() -> Result<(), CpuError> {
+        let stop_on_loop = RunLimits {
+            stop_on_loop: true,
+            max_instructions: Some(1000),
+            ..Default::default()
+        };
+        let mut cpu = CpuImpl::new();
+        // LDX #3, loop: DEX, BNE loop, done: JMP done
+        cpu.load_program(
+            START_ADDR,
+            &[0xA2, 0x03, 0xCA, 0xD0, 0xFD, 0x4C, 0x05, 0x03],
+            false,
+        )?;
+        cpu.set_pc(START_ADDR)?;
+        // the counting loop is not endless:
+        assert_eq!(cpu.run_limited(stop_on_loop)?, StopReason::Loop(0x0305));
+        assert_eq!(cpu.index_x, 0);
+        assert_eq!(cpu.accumulated_instructions, 8);
+
+        // loop: LDA $10, BEQ loop
+        cpu.load_program(START_ADDR, &[0xA5, 0x10, 0xF0, 0xFC], false)?;
+        cpu.set_pc(START_ADDR)?;
+        assert_eq!(cpu.run_limited(stop_on_loop)?, StopReason::Loop(0x0302));
+
+        // loop: INC $10, JMP loop; writes to memory, so it isn't endless
+        cpu.load_program(START_ADDR, &[0xE6, 0x10, 0x4C, 0x00, 0x03], false)?;
+        cpu.set_pc(START_ADDR)?;
+        assert_eq!(cpu.run_limited(stop_on_loop)?, StopReason::InstructionLimit);
+        Ok(())
+    }
+
+    #[test]
+    fn run_throttled_to_clock_speed() -> Result<(), CpuError> {
+        let mut cpu = CpuImpl::new();
+        // JMP $0300
+        cpu.load_program(START_ADDR, &[0x4C, 0x00, 0x03], false)?;
+        cpu.set_pc(START_ADDR)?;
+        cpu.set_clock_speed(Some(1_000_000.0));
+
+        // 30000 cycles at 1 MHz take at least 30ms:
+        let limits = RunLimits {
+            max_cycles: Some(30_000),
+            ..Default::default()
+        };
+        let start = Instant::now();
+        assert_eq!(cpu.run_limited(limits)?, StopReason::CycleLimit);
+        assert!(start.elapsed() >= Duration::from_millis(30));
+        assert!(cpu.approximate_clock_speed <= 1_000_000.0);
+        Ok(())
+    }
+
+    // a trace writer whose output can be inspected after the tracer was handed to the CPU
+    #[derive(Clone, Default)]
+    struct SharedBuffer(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+    impl std::io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn trace_executed_instructions_in_range() -> Result<(), CpuError> {
+        let mut cpu = CpuImpl::new();
+        // loop: LDA #$42, INX, JMP loop
+        cpu.load_program(START_ADDR, &[0xA9, 0x42, 0xE8, 0x4C, 0x00, 0x03], false)?;
+        cpu.set_pc(START_ADDR)?;
+        let buffer = SharedBuffer::default();
+        let tracer = Tracer::new(Box::new(buffer.clone()), vec![START_ADDR..=START_ADDR + 2]);
+        cpu.set_tracer(Some(tracer));
+        // the stop before the breakpoint is not traced, only the resumed instruction:
+        cpu.add_breakpoint(START_ADDR + 2, None, 0);
+        assert_eq!(cpu.run_limited(RunLimits::default())?, StopReason::Stopped);
+        cpu.remove_breakpoint(START_ADDR + 2);
+
+        let limits = RunLimits {
+            max_instructions: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(cpu.run_limited(limits)?, StopReason::InstructionLimit);
+        cpu.set_tracer(None);
+
+        let trace = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+        let lines: Vec<&str> = trace.lines().collect();
+        // the JMP is outside of the traced range:
+        assert_eq!(
+            lines,
+            vec![
+                "0300  A9 42     LDA #$42                        A:00 X:00 Y:00 P:00 SP:FF CYC:0",
+                "0302  E8        INX                             A:42 X:00 Y:00 P:00 SP:FF CYC:2",
+                "0300  A9 42     LDA #$42                        A:42 X:01 Y:00 P:00 SP:FF CYC:7",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn breakpoint_stops_before_instruction() -> Result<(), CpuError> {
+        let mut cpu = CpuImpl::new();
+        // loop: INX, JMP loop
+        cpu.load_program(START_ADDR, &[0xE8, 0x4C, 0x00, 0x03], false)?;
+        cpu.set_pc(START_ADDR)?;
+        cpu.add_breakpoint(START_ADDR + 1, None, 0);
+        assert_eq!(cpu.get_breakpoints()[0].address, START_ADDR + 1);
+
+        assert_eq!(cpu.run_limited(RunLimits::default())?, StopReason::Stopped);
+        assert_eq!(cpu.get_pc(), START_ADDR + 1);
+        assert_eq!(cpu.index_x, 1);
+        assert_eq!(cpu.accumulated_instructions, 1);
+
+        // continuing executes the instruction at the breakpoint, then stops there again:
+        assert_eq!(cpu.run_limited(RunLimits::default())?, StopReason::Stopped);
+        assert_eq!(cpu.get_pc(), START_ADDR + 1);
+        assert_eq!(cpu.index_x, 2);
+
+        assert!(cpu.remove_breakpoint(START_ADDR + 1));
+        let limits = RunLimits {
+            max_instructions: Some(4),
+            ..Default::default()
+        };
+        assert_eq!(cpu.run_limited(limits)?, StopReason::InstructionLimit);
+        assert_eq!(cpu.index_x, 4);
+        Ok(())
+    }
+
+    #[test]
+    fn watchpoint_stops_after_write() -> Result<(), CpuError> {
+        let mut cpu = CpuImpl::new();
+        // LDA #$42, STA $0200, LDA $0200, BRK
+        cpu.load_program(
+            START_ADDR,
+            &[0xA9, 0x42, 0x8D, 0x00, 0x02, 0xAD, 0x00, 0x02, 0x00],
+            false,
+        )?;
+        cpu.set_pc(START_ADDR)?;
+        cpu.add_watchpoint(Watchpoint {
+            range: 0x0200..=0x0200,
+            kind: WatchKind::Write,
+            value: None,
+            stop_before: false,
+        });
+        let reason = cpu.run_limited(RunLimits::default())?;
+        let StopReason::Watchpoint(hit) = reason else {
+            panic!("unexpected stop: {}", reason);
+        };
+        assert_eq!(hit.pc, START_ADDR + 2);
+        assert_eq!(hit.access.value, 0x42);
+        assert_eq!(cpu.get_pc(), START_ADDR + 5);
+        assert_eq!(cpu.memory.read(0x0200)?, 0x42);
+
+        // the read doesn't trigger a write watchpoint:
+        assert_eq!(cpu.run_limited(RunLimits::default())?, StopReason::Stopped);
+        Ok(())
+    }
+
+    #[test]
+    fn watchpoint_stops_before_read() -> Result<(), CpuError> {
+        let mut cpu = CpuImpl::new();
+        // LDX #$01, LDA $0200,X, INX, BRK
+        cpu.load_program(
+            START_ADDR,
+            &[0xA2, 0x01, 0xBD, 0x00, 0x02, 0xE8, 0x00],
+            false,
+        )?;
+        cpu.memory.write(0x0201, 0x99)?;
+        cpu.set_pc(START_ADDR)?;
+        cpu.add_watchpoint(Watchpoint {
+            range: 0x0200..=0x02FF,
+            kind: WatchKind::Read,
+            value: Some(0x99),
+            stop_before: true,
+        });
+        let reason = cpu.run_limited(RunLimits::default())?;
+        assert_eq!(
+            reason.to_string(),
+            "watchpoint hit: read of 0x99 from 0x0201 by instruction at 0x0302"
+        );
+        assert_eq!(cpu.get_pc(), START_ADDR + 2);
         assert_eq!(cpu.accumulator, 0);
+        assert_eq!(cpu.accumulated_instructions, 1);
+
+        // resuming executes the instruction instead of stopping again:
+        assert_eq!(cpu.run_limited(RunLimits::default())?, StopReason::Stopped);
+        assert_eq!(cpu.accumulator, 0x99);
+        assert_eq!(cpu.index_x, 0x02);
+        Ok(())
+    }
+
+    #[test]
+    fn watchpoint_ignores_instruction_fetch() -> Result<(), CpuError> {
+        let mut cpu = CpuImpl::new();
+        // LDA #$42, BRK
+        cpu.load_program(START_ADDR, &[0xA9, 0x42, 0x00], false)?;
+        cpu.set_pc(START_ADDR)?;
+        cpu.add_watchpoint(Watchpoint {
+            range: START_ADDR..=START_ADDR + 2,
+            kind: WatchKind::Access,
+            value: None,
+            stop_before: false,
+        });
+        assert_eq!(cpu.run_limited(RunLimits::default())?, StopReason::Stopped);
+        assert_eq!(cpu.accumulator, 0x42);
+        Ok(())
+    }
+
+    #[test]
+    fn run_limited_stops_at_brk() -> Result<(), CpuError> {
+        let mut cpu = CpuImpl::new();
+        // NOP, BRK
+        cpu.load_program(START_ADDR, &[0xEA, 0x00], false)?;
+        cpu.set_pc(START_ADDR)?;
+        let limits = RunLimits {
+            max_instructions: Some(5),
+            ..Default::default()
+        };
+        assert_eq!(cpu.run_limited(limits)?, StopReason::Stopped);
+        assert_eq!(cpu.accumulated_instructions, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn save_and_load_state() -> Result<(), CpuError> {
+        let mut cpu = CpuImpl::new();
+        // LDX #$07, LDY #$09, BRK
+        cpu.load_program(START_ADDR, &[0xA2, 0x07, 0xA0, 0x09, 0x00], true)?;
+        cpu.traps.add_address_trap(0x1234);
+        cpu.run(Some(START_ADDR))?;
+        let state = cpu.save_state()?;
+
+        let mut restored = CpuImpl::with_cpu_type(CpuType::WDC65C02);
+        restored.load_state(state.clone())?;
+        assert_eq!(restored.get_cpu_type(), CpuType::MOS6502);
+        assert_eq!(restored.index_x, 0x07);
+        assert_eq!(restored.index_y, 0x09);
+        assert_eq!(restored.get_pc(), cpu.get_pc());
+        assert_eq!(restored.stack.get_sp()?, cpu.stack.get_sp()?);
+        assert_eq!(restored.accumulated_cycles, cpu.accumulated_cycles);
+        assert_eq!(restored.peek(START_ADDR)?, 0xA2);
+        assert_eq!(
+            restored.set_byte_at(START_ADDR, 0xEA),
+            Err(CpuError::ReadOnlyMemory)
+        );
+        assert_eq!(restored.save_state()?, state);
+        Ok(())
+    }
+
+    #[test]
+    fn step_error_carries_instruction_and_registers() -> Result<(), CpuError> {
+        let mut cpu = CpuImpl::new();
+        // JSR to JMP $1000 at a routine whose handler fails
+        cpu.load_program(START_ADDR, &[0x20, 0xD2, 0xFF], false)?;
+        cpu.load_program(0xFFD2, &[0x4C, 0x00, 0x10], false)?;
+        cpu.add_trap_handler(0xFFD2, Box::new(|_| Err(CpuError::ReadOnlyMemory)));
+        cpu.set_pc(START_ADDR)?;
+        cpu.accumulator = 0x41;
+        cpu.step()?;
+
+        let err = cpu.step().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "memory range is read-only at FFD2 (bytes: 4C 00 10)"
+        );
+        let CpuError::Execution(exec) = err else {
+            panic!("expected an execution error");
+        };
+        assert_eq!(exec.cause, CpuError::ReadOnlyMemory);
+        assert_eq!(exec.snapshot.accumulator, 0x41);
+        Ok(())
+    }
+
+    #[test]
+    fn trap_handler_requires_return_address() -> Result<(), CpuError> {
+        let mut cpu = CpuImpl::new();
+        // JMP $FFD2 to a routine with a handler, with nothing on the stack
+        cpu.load_program(START_ADDR, &[0x4C, 0xD2, 0xFF], false)?;
+        cpu.load_program(0xFFD2, &[0x60], false)?;
+        cpu.add_trap_handler(0xFFD2, Box::new(|ctx| Ok(ctx.result())));
+        cpu.set_pc(START_ADDR)?;
+        cpu.step()?;
+
+        let err = cpu.step().unwrap_err();
+        let CpuError::Execution(exec) = err else {
+            panic!("expected an execution error");
+        };
+        assert_eq!(exec.cause, CpuError::MissingReturnAddress);
+        assert_eq!(exec.pc, 0xFFD2);
+        Ok(())
+    }
+
+    #[test]
+    fn trap_handler_reached_through_vector() -> Result<(), CpuError> {
+        let mut cpu = CpuImpl::new();
+        // JSR $FFD2, which jumps through the vector at $0326 to the routine at $F1CA
+        cpu.load_program(START_ADDR, &[0x20, 0xD2, 0xFF, 0xEA], false)?;
+        cpu.load_program(0xFFD2, &[0x6C, 0x26, 0x03], false)?;
+        cpu.memory.write_word(0x0326, 0xF1CA)?;
+        cpu.load_program(0xF1CA, &[0x60], false)?;
+        cpu.add_trap_handler(
+            0xF1CA,
+            Box::new(|ctx| {
+                let mut result = ctx.result();
+                result.accumulator = 0x42;
+                Ok(result)
+            }),
+        );
+        cpu.set_pc(START_ADDR)?;
+        let sp = cpu.stack.get_sp()?;
+        cpu.step()?;
+        cpu.step()?;
+        assert_eq!(cpu.get_pc(), 0xF1CA);
+
+        cpu.step()?;
+        assert_eq!(cpu.accumulator, 0x42);
+        assert_eq!(cpu.get_pc(), START_ADDR + 3);
+        assert_eq!(cpu.stack.get_sp()?, sp);
+        Ok(())
+    }
+
+    #[test]
+    fn step_back_undoes_registers_and_memory() -> Result<(), CpuError> {
+        let mut cpu = CpuImpl::new();
+        // LDA #$42, STA $10, PHA, INX
+        cpu.load_program(START_ADDR, &[0xA9, 0x42, 0x85, 0x10, 0x48, 0xE8], false)?;
+        cpu.memory.write(0x0010, 0x99)?;
+        cpu.set_pc(START_ADDR)?;
+        cpu.set_history_limit(100);
+        let sp = cpu.stack.get_sp()?;
+        for _ in 0..4 {
+            cpu.step()?;
+        }
+        assert_eq!(cpu.index_x, 1);
+        assert_eq!(cpu.peek(0x0010)?, 0x42);
+
+        assert!(cpu.step_back()?);
         assert_eq!(cpu.index_x, 0);
-        assert_eq!(cpu.index_y, 0);
-        // Z = 1
-        assert_eq!(cpu.status.get_status(), 0b0000_0010);
+        assert!(cpu.step_back()?);
+        assert_eq!(cpu.stack.get_sp()?, sp);
+        assert!(cpu.step_back()?);
+        assert_eq!(cpu.peek(0x0010)?, 0x99);
+        assert_eq!(cpu.get_pc(), START_ADDR + 2);
+        assert_eq!(cpu.accumulated_instructions, 1);
+        assert_eq!(cpu.accumulated_cycles, 2);
+        assert!(cpu.step_back()?);
+        assert_eq!(cpu.accumulator, 0);
+        assert_eq!(cpu.get_pc(), START_ADDR);
+        assert!(!cpu.step_back()?);
+
+        // replays the same way:
+        for _ in 0..4 {
+            cpu.step()?;
+        }
+        assert_eq!(cpu.peek(0x0010)?, 0x42);
+        assert_eq!(cpu.index_x, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn reverse_continue_stops_at_address_trap() -> Result<(), CpuError> {
+        let mut cpu = CpuImpl::new();
+        // INX, INX, INX, INX
+        cpu.load_program(START_ADDR, &[0xE8, 0xE8, 0xE8, 0xE8], false)?;
+        cpu.set_pc(START_ADDR)?;
+        cpu.set_history_limit(100);
+        for _ in 0..4 {
+            cpu.step()?;
+        }
+        cpu.traps.add_address_trap(START_ADDR + 1);
+        assert!(cpu.reverse_continue()?);
+        assert_eq!(cpu.get_pc(), START_ADDR + 1);
+        assert_eq!(cpu.index_x, 1);
+
+        // without further traps, back to the start of the history:
+        assert!(cpu.reverse_continue()?);
+        assert_eq!(cpu.get_pc(), START_ADDR);
+        assert!(!cpu.reverse_continue()?);
+        Ok(())
+    }
+
+    #[test]
+    fn history_is_bounded() -> Result<(), CpuError> {
+        let mut cpu = CpuImpl::new();
+        // INX, INX, INX, INX
+        cpu.load_program(START_ADDR, &[0xE8, 0xE8, 0xE8, 0xE8], false)?;
+        cpu.set_pc(START_ADDR)?;
+        cpu.set_history_limit(2);
+        for _ in 0..4 {
+            cpu.step()?;
+        }
+        assert!(cpu.step_back()?);
+        assert!(cpu.step_back()?);
+        assert!(!cpu.step_back()?);
+        assert_eq!(cpu.index_x, 2);
+
+        cpu.set_history_limit(0);
+        cpu.step()?;
+        assert!(!cpu.step_back()?);
         Ok(())
     }
 
@@ -334,7 +1428,7 @@ mod tests {
     fn get_effective_operand_zero_page() -> Result<(), CpuError> {
         let mut cpu = setup_test_cpu(&[OP_CODE, ZERO_PAGE_ADDR])?;
 
-        populate_zero_page(cpu.memory.as_mut(), &[EXPECTED])?;
+        populate_zero_page(&mut cpu.memory, &[EXPECTED])?;
 
         let res = cpu.get_effective_operand(AddressingMode::ZeroPageX)?;
         assert_eq!(res, EXPECTED);
@@ -346,7 +1440,7 @@ mod tests {
     fn get_effective_operand_zero_page_indexed_x() -> Result<(), CpuError> {
         let mut cpu = setup_test_cpu(&[OP_CODE, ZERO_PAGE_ADDR])?;
 
-        populate_zero_page(cpu.memory.as_mut(), &[0xaa, 0xbb, 0xcc, EXPECTED])?;
+        populate_zero_page(&mut cpu.memory, &[0xaa, 0xbb, 0xcc, EXPECTED])?;
 
         cpu.index_x = 3;
         cpu.index_y = 2; // should be ignored, since testing Indexed_X
@@ -360,7 +1454,7 @@ mod tests {
     fn get_effective_operand_zero_page_indexed_y() -> Result<(), CpuError> {
         let mut cpu = setup_test_cpu(&[OP_CODE, ZERO_PAGE_ADDR])?;
 
-        populate_zero_page(cpu.memory.as_mut(), &[0xaa, 0xbb, EXPECTED])?;
+        populate_zero_page(&mut cpu.memory, &[0xaa, 0xbb, EXPECTED])?;
 
         cpu.index_y = 2;
         cpu.index_x = 3; // should be ignored, since testing Indexed_Y
@@ -415,7 +1509,7 @@ mod tests {
     #[test]
     fn get_effective_operand_indexed_x_indirect() -> Result<(), CpuError> {
         let mut cpu = setup_test_cpu(&[OP_CODE, ZERO_PAGE_ADDR])?;
-        populate_zero_page(cpu.memory.as_mut(), &[0xaa, 0xbb, 0x21, 0x03])?;
+        populate_zero_page(&mut cpu.memory, &[0xaa, 0xbb, 0x21, 0x03])?;
         cpu.memory.write(0x0321, EXPECTED)?;
 
         cpu.index_x = 2;
@@ -428,7 +1522,7 @@ mod tests {
     #[test]
     fn get_effective_operand_indirect_indexed_y() -> Result<(), CpuError> {
         let mut cpu = setup_test_cpu(&[OP_CODE, ZERO_PAGE_ADDR])?;
-        populate_zero_page(cpu.memory.as_mut(), &[0x1F, 0x03])?;
+        populate_zero_page(&mut cpu.memory, &[0x1F, 0x03])?;
         cpu.memory.write(0x0321, EXPECTED)?;
 
         cpu.index_y = 2;
@@ -441,7 +1535,7 @@ mod tests {
     #[test]
     fn get_effective_operand_invalid_addressing_mode() -> Result<(), CpuError> {
         let mut cpu = setup_test_cpu(&[OP_CODE, ZERO_PAGE_ADDR])?;
-        populate_zero_page(cpu.memory.as_mut(), &[0x1F, 0x03])?;
+        populate_zero_page(&mut cpu.memory, &[0x1F, 0x03])?;
         cpu.memory.write(0x0321, EXPECTED)?;
 
         cpu.index_y = 2;
@@ -504,4 +1598,329 @@ mod tests {
         assert_final_pc(&cpu, 3);
         Ok(())
     }
+
+    #[test]
+    fn get_effective_address_indirect_wdc65c02() -> Result<(), CpuError> {
+        let mut cpu = setup_test_cpu(&[OP_CODE, 0xFF, 0x02])?;
+        cpu.cpu_type = CpuType::WDC65C02;
+        cpu.memory.write(0x02FF, 0x34)?;
+        cpu.memory.write(0x0300, 0x12)?;
+        let res = cpu.get_effective_address(AddressingMode::Indirect)?;
+        assert_eq!(res, 0x1234);
+        assert_final_pc(&cpu, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn get_effective_address_zero_page_indirect() -> Result<(), CpuError> {
+        let mut cpu = setup_test_cpu(&[OP_CODE, ZERO_PAGE_ADDR])?;
+        populate_zero_page(&mut cpu.memory, &[0x34, 0x12])?;
+        let res = cpu.get_effective_address(AddressingMode::ZeroPageIndirect)?;
+        assert_eq!(res, 0x1234);
+        assert_final_pc(&cpu, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn get_effective_address_absolute_indexed_indirect() -> Result<(), CpuError> {
+        let mut cpu = setup_test_cpu(&[OP_CODE, 0xFE, 0x02])?;
+        cpu.index_x = 0x01;
+        // no page wrap bug, the high byte is read from the next page:
+        cpu.memory.write(0x02FF, 0x34)?;
+        cpu.memory.write(0x0300, 0x12)?;
+        let res = cpu.get_effective_address(AddressingMode::AbsoluteIndexedIndirect)?;
+        assert_eq!(res, 0x1234);
+        assert_final_pc(&cpu, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn get_effective_address_absolute_indexed_page_crossing() -> Result<(), CpuError> {
+        let mut cpu = setup_test_cpu(&[OP_CODE, 0xF0, 0x03])?;
+        cpu.index_x = 0x0F;
+        cpu.get_effective_address(AddressingMode::AbsoluteX)?;
+        assert!(!cpu.page_crossed);
+
+        let mut cpu = setup_test_cpu(&[OP_CODE, 0xF0, 0x03])?;
+        cpu.index_y = 0x10;
+        let res = cpu.get_effective_address(AddressingMode::AbsoluteY)?;
+        assert_eq!(res, 0x0400);
+        assert!(cpu.page_crossed);
+        Ok(())
+    }
+
+    #[test]
+    fn get_effective_address_indirect_indexed_y_page_crossing() -> Result<(), CpuError> {
+        let mut cpu = setup_test_cpu(&[OP_CODE, ZERO_PAGE_ADDR])?;
+        populate_zero_page(&mut cpu.memory, &[0xFF, 0x03])?;
+        cpu.index_y = 1;
+        let res = cpu.get_effective_address(AddressingMode::IndirectIndexedY)?;
+        assert_eq!(res, 0x0400);
+        assert!(cpu.page_crossed);
+        Ok(())
+    }
+
+    #[test]
+    fn get_effective_address_absolute_indexed_wraps_to_zero_page() -> Result<(), CpuError> {
+        let mut cpu = setup_test_cpu(&[OP_CODE, 0xF0, 0xFF])?;
+        cpu.index_x = 0x20;
+        let res = cpu.get_effective_address(AddressingMode::AbsoluteX)?;
+        assert_eq!(res, 0x0010);
+        assert!(cpu.page_crossed);
+
+        let mut cpu = setup_test_cpu(&[OP_CODE, 0xFF, 0xFF])?;
+        cpu.index_y = 0x01;
+        let res = cpu.get_effective_address(AddressingMode::AbsoluteY)?;
+        assert_eq!(res, 0x0000);
+        Ok(())
+    }
+
+    #[test]
+    fn get_effective_address_zero_page_indexed_stays_in_zero_page() -> Result<(), CpuError> {
+        let mut cpu = setup_test_cpu(&[OP_CODE, 0xF0])?;
+        cpu.index_x = 0x20;
+        let res = cpu.get_effective_address(AddressingMode::ZeroPageX)?;
+        assert_eq!(res, 0x0010);
+        Ok(())
+    }
+
+    #[test]
+    fn get_effective_address_zero_page_pointer_wraps() -> Result<(), CpuError> {
+        // pointer at 0xFF has its high byte at 0x00:
+        let mut cpu = setup_test_cpu(&[OP_CODE, 0xFF])?;
+        cpu.memory.write(0x00FF, 0xF0)?;
+        cpu.memory.write(0x0000, 0xFF)?;
+        cpu.memory.write(0x0100, 0x12)?;
+        cpu.index_y = 0x20;
+        let res = cpu.get_effective_address(AddressingMode::IndirectIndexedY)?;
+        assert_eq!(res, 0x0010);
+
+        let mut cpu = setup_test_cpu(&[OP_CODE, 0xFE])?;
+        cpu.memory.write(0x00FF, 0x34)?;
+        cpu.memory.write(0x0000, 0x12)?;
+        cpu.index_x = 0x01;
+        let res = cpu.get_effective_address(AddressingMode::IndexedXIndirect)?;
+        assert_eq!(res, 0x1234);
+        Ok(())
+    }
+
+    #[test]
+    fn load_program_rom_up_to_and_across_top_of_memory() -> Result<(), CpuError> {
+        let mut cpu = CpuImpl::default();
+        cpu.load_program(0xFFFC, &[0x00, 0x03, 0x00, 0x04], true)?;
+        assert_eq!(
+            cpu.memory.write(0xFFFF, 0x55),
+            Err(CpuError::ReadOnlyMemory)
+        );
+        assert_eq!(cpu.memory.read_word(0xFFFC)?, 0x0300);
+        cpu.memory.write(0x0000, 0x55)?;
+
+        // a ROM image that wraps protects both ends:
+        let mut cpu = CpuImpl::default();
+        cpu.load_program(0xFFFE, &[0x12, 0x34, 0x56], true)?;
+        assert_eq!(
+            cpu.memory.write(0xFFFE, 0x55),
+            Err(CpuError::ReadOnlyMemory)
+        );
+        assert_eq!(
+            cpu.memory.write(0x0000, 0x55),
+            Err(CpuError::ReadOnlyMemory)
+        );
+        cpu.memory.write(0x0001, 0x55)?;
+        cpu.memory.write(0xFFFD, 0x55)?;
+        Ok(())
+    }
+
+    //============= cycle counting tests =============
+    fn step_cycles(program: &[u8], index: u8) -> Result<u64, CpuError> {
+        let mut cpu = CpuImpl::default();
+        cpu.load_program(START_ADDR, program, false)?;
+        cpu.address_bus.set_pc(START_ADDR)?;
+        cpu.index_x = index;
+        cpu.step()?;
+        Ok(cpu.accumulated_cycles)
+    }
+
+    #[test]
+    fn step_adds_page_crossing_penalty() -> Result<(), CpuError> {
+        // LDA $03F0,X
+        assert_eq!(step_cycles(&[0xBD, 0xF0, 0x03], 0x0F)?, 4);
+        assert_eq!(step_cycles(&[0xBD, 0xF0, 0x03], 0x10)?, 5);
+        // STA $03F0,X always takes 5 cycles:
+        assert_eq!(step_cycles(&[0x9D, 0xF0, 0x03], 0x0F)?, 5);
+        assert_eq!(step_cycles(&[0x9D, 0xF0, 0x03], 0x10)?, 5);
+        Ok(())
+    }
+
+    #[test]
+    fn step_adds_branch_taken_penalty() -> Result<(), CpuError> {
+        // BNE, Z is cleared after CpuImpl::new():
+        assert_eq!(step_cycles(&[0xD0, 0x10], 0)?, 3);
+        // branch target on next page:
+        assert_eq!(step_cycles(&[0xD0, 0x7F], 0)?, 3);
+        assert_eq!(step_cycles(&[0xD0, 0x80], 0)?, 4);
+        // BEQ is not taken:
+        assert_eq!(step_cycles(&[0xF0, 0x80], 0)?, 2);
+        Ok(())
+    }
+
+    //============= illegal opcode tests =============
+    fn setup_illegal_opcode_cpu(policy: IllegalOpcodePolicy) -> Result<CpuImpl, CpuError> {
+        let mut cpu = CpuImpl::default();
+        cpu.set_illegal_opcode_policy(policy);
+        // undocumented NOP $03F0,X, then a JAM:
+        cpu.load_program(START_ADDR, &[0x1C, 0xF0, 0x03, 0x02], false)?;
+        cpu.set_pc(START_ADDR)?;
+        Ok(cpu)
+    }
+
+    #[test]
+    fn illegal_opcode_error() -> Result<(), CpuError> {
+        let mut cpu = setup_illegal_opcode_cpu(IllegalOpcodePolicy::Error)?;
+        let CpuError::Execution(exec) = cpu.step().unwrap_err() else {
+            panic!("expected an execution error");
+        };
+        assert_eq!(exec.cause, CpuError::InvalidOpcode(0x1C));
+        assert_eq!(exec.pc, START_ADDR);
+        assert_eq!(cpu.get_pc(), START_ADDR);
+        Ok(())
+    }
+
+    #[test]
+    fn illegal_opcode_nop() -> Result<(), CpuError> {
+        let mut cpu = setup_illegal_opcode_cpu(IllegalOpcodePolicy::Nop)?;
+        cpu.index_x = 0x10;
+        assert!(!cpu.step()?);
+        assert_eq!(cpu.get_pc(), START_ADDR + 3);
+        // 4 cycles and a page crossing:
+        assert_eq!(cpu.accumulated_cycles, 5);
+
+        // JAM is skipped, too:
+        assert!(!cpu.step()?);
+        assert_eq!(cpu.get_pc(), START_ADDR + 4);
+        Ok(())
+    }
+
+    #[test]
+    fn illegal_opcode_jam() -> Result<(), CpuError> {
+        let mut cpu = setup_illegal_opcode_cpu(IllegalOpcodePolicy::Jam)?;
+        assert!(cpu.step()?);
+        assert_eq!(cpu.get_pc(), START_ADDR);
+        assert!(cpu.halted);
+        Ok(())
+    }
+
+    #[test]
+    fn illegal_opcode_brk() -> Result<(), CpuError> {
+        let mut cpu = setup_illegal_opcode_cpu(IllegalOpcodePolicy::Brk)?;
+        cpu.memory.write_word(SystemVector::IRQ as u16, 0x4567)?;
+        cpu.step()?;
+        assert_eq!(cpu.get_pc(), 0x4567);
+        // BRK pushes PC + 2 and the status:
+        assert_eq!(cpu.stack.get_sp()?, 0x01FC);
+        Ok(())
+    }
+
+    fn setup_interrupt_cpu(cpu_type: CpuType) -> Result<CpuImpl, CpuError> {
+        let mut cpu = CpuImpl::with_cpu_type(cpu_type);
+        // NOPs at START_ADDR, interrupt handlers with a NOP at 0x0400 (IRQ) and 0x0500 (NMI)
+        cpu.load_program(START_ADDR, &[0xEA, 0xEA, 0xCB, 0xEA], false)?;
+        cpu.load_program(0x0400, &[0xEA, 0x40], false)?;
+        cpu.load_program(0x0500, &[0xEA, 0x40], false)?;
+        cpu.memory.write_word(SystemVector::IRQ as u16, 0x0400)?;
+        cpu.memory.write_word(SystemVector::NMI as u16, 0x0500)?;
+        cpu.address_bus.set_pc(START_ADDR)?;
+        Ok(cpu)
+    }
+
+    #[test]
+    fn step_services_irq() -> Result<(), CpuError> {
+        let mut cpu = setup_interrupt_cpu(CpuType::MOS6502)?;
+        cpu.status.set_carry(true);
+        cpu.step()?;
+        cpu.assert_irq();
+        assert!(!cpu.step()?);
+        assert_eq!(cpu.address_bus.get_pc(), 0x0400);
+        assert!(cpu.status.interrupt_disable());
+        assert_eq!(cpu.accumulated_cycles, 2 + 7);
+        assert_eq!(cpu.accumulated_instructions, 1);
+        // status with B clear, and PC of next instruction are pushed:
+        assert_eq!(cpu.memory.read(0x01FD)?, 0b0000_0001);
+        assert_eq!(cpu.memory.read_word(0x01FE)?, START_ADDR + 1);
+
+        // IRQ is masked while in the handler:
+        cpu.step()?;
+        assert_eq!(cpu.address_bus.get_pc(), 0x0401);
+        cpu.release_irq();
+        // RTI returns to interrupted program:
+        cpu.step()?;
+        assert_eq!(cpu.address_bus.get_pc(), START_ADDR + 1);
+        assert!(!cpu.status.interrupt_disable());
+        assert!(cpu.status.carry());
+        cpu.step()?;
+        assert_eq!(cpu.address_bus.get_pc(), START_ADDR + 2);
+        Ok(())
+    }
+
+    #[test]
+    fn step_ignores_masked_irq() -> Result<(), CpuError> {
+        let mut cpu = setup_interrupt_cpu(CpuType::MOS6502)?;
+        cpu.status.set_interrupt_disable(true);
+        cpu.assert_irq();
+        cpu.step()?;
+        assert_eq!(cpu.address_bus.get_pc(), START_ADDR + 1);
+        Ok(())
+    }
+
+    #[test]
+    fn step_services_nmi_once() -> Result<(), CpuError> {
+        let mut cpu = setup_interrupt_cpu(CpuType::MOS6502)?;
+        cpu.status.set_interrupt_disable(true);
+        cpu.trigger_nmi();
+        cpu.step()?;
+        assert_eq!(cpu.address_bus.get_pc(), 0x0500);
+        assert_eq!(cpu.accumulated_cycles, 7);
+        cpu.step()?;
+        assert_eq!(cpu.address_bus.get_pc(), 0x0501);
+        Ok(())
+    }
+
+    #[test]
+    fn interrupt_wakes_up_wai() -> Result<(), CpuError> {
+        let mut cpu = setup_interrupt_cpu(CpuType::WDC65C02)?;
+        cpu.status.set_decimal_mode(true);
+        cpu.address_bus.set_pc(START_ADDR + 2)?;
+        // WAI:
+        assert!(cpu.step()?);
+        assert!(cpu.step()?);
+        cpu.assert_irq();
+        assert!(!cpu.step()?);
+        assert_eq!(cpu.address_bus.get_pc(), 0x0400);
+        // 65C02 clears D on interrupts:
+        assert!(!cpu.status.decimal_mode());
+
+        // with a masked IRQ, execution continues after WAI:
+        let mut cpu = setup_interrupt_cpu(CpuType::WDC65C02)?;
+        cpu.status.set_interrupt_disable(true);
+        cpu.address_bus.set_pc(START_ADDR + 2)?;
+        assert!(cpu.step()?);
+        cpu.assert_irq();
+        assert!(!cpu.step()?);
+        assert_eq!(cpu.address_bus.get_pc(), START_ADDR + 4);
+        Ok(())
+    }
+
+    #[test]
+    fn step_wdc65c02_decimal_mode_penalty() -> Result<(), CpuError> {
+        let mut cpu = CpuImpl::with_cpu_type(CpuType::WDC65C02);
+        // ADC #$01, SED, ADC #$01
+        cpu.load_program(START_ADDR, &[0x69, 0x01, 0xF8, 0x69, 0x01], false)?;
+        cpu.address_bus.set_pc(START_ADDR)?;
+        cpu.step()?;
+        assert_eq!(cpu.accumulated_cycles, 2);
+        cpu.step()?;
+        cpu.step()?;
+        assert_eq!(cpu.accumulated_cycles, 2 + 2 + 3);
+        Ok(())
+    }
 }
@@ -1,8 +1,11 @@
 use std::fmt;
+use std::ops::RangeInclusive;
 
 use crate::{
-    CpuError,
+    CpuError, CpuType,
+    condition::{Expr, Registers},
     engine::decoder::{self, DecodedInstruction},
+    memory::Memory,
 };
 
 #[derive(Debug, Clone, PartialEq)]
@@ -15,7 +18,7 @@ impl fmt::Display for CpuTrap {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             CpuTrap::ByInstruction(op_code_byte) => {
-                let decoded = decoder::decode(*op_code_byte).unwrap().get_mnemonic();
+                let decoded = decoder::decode(*op_code_byte, CpuType::MOS6502).get_mnemonic();
                 write!(f, "Opcode trap: 0x{:02X} ({})", op_code_byte, decoded)
             }
             CpuTrap::ByAddress(addr) => write!(f, "Address trap: 0x{:04X}", addr),
@@ -40,6 +43,117 @@ pub struct TrapResult {
     pub status: u8,
 }
 
+/// Registers and memory as seen by a trap handler; the registers are transferred back
+/// to the CPU via the returned TrapResult.
+pub struct TrapContext<'a> {
+    pub accumulator: u8,
+    pub index_x: u8,
+    pub index_y: u8,
+    pub status: u8,
+    memory: &'a mut dyn Memory,
+}
+
+impl TrapContext<'_> {
+    pub fn read(&self, address: u16) -> Result<u8, CpuError> {
+        self.memory.read(address)
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) -> Result<(), CpuError> {
+        self.memory.write(address, value)
+    }
+
+    /// The context's current registers, e.g. after the handler modified them.
+    pub fn result(&self) -> TrapResult {
+        TrapResult {
+            accumulator: self.accumulator,
+            index_x: self.index_x,
+            index_y: self.index_y,
+            status: self.status,
+        }
+    }
+}
+
+/// Emulates a routine in Rust, e.g. a ROM routine like CHROUT; called instead of the
+/// instruction at the trap address, followed by an implicit RTS. The routine may be reached
+/// directly by JSR or through a vector or jump table; an empty stack is a MissingReturnAddress error.
+pub type TrapHandler = dyn FnMut(&mut TrapContext) -> Result<TrapResult, CpuError>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// A data read or write of an instruction, as recorded while watchpoints are set.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryAccess {
+    pub address: u16,
+    pub value: u8,
+    pub kind: AccessKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access, // read or write
+}
+
+/// An address breakpoint with its condition, and the number of hits it still ignores.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Breakpoint {
+    pub address: u16,
+    pub condition: Option<Expr>,
+    pub ignore_count: u32,
+}
+
+/// Data watchpoint on an address range; instruction fetches do not trigger it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watchpoint {
+    pub range: RangeInclusive<u16>,
+    pub kind: WatchKind,
+    // only triggers if the value read or written matches:
+    pub value: Option<u8>,
+    // stops before the accessing instruction (its effects are undone), instead of after it;
+    // side effects of device reads cannot be undone
+    pub stop_before: bool,
+}
+
+impl Watchpoint {
+    pub fn matches(&self, access: &MemoryAccess) -> bool {
+        let kind_matches = match self.kind {
+            WatchKind::Read => access.kind == AccessKind::Read,
+            WatchKind::Write => access.kind == AccessKind::Write,
+            WatchKind::Access => true,
+        };
+        kind_matches
+            && self.range.contains(&access.address)
+            && self.value.is_none_or(|value| value == access.value)
+    }
+}
+
+/// A triggered watchpoint, with the address of the accessing instruction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WatchHit {
+    pub pc: u16,
+    pub access: MemoryAccess,
+    pub stop_before: bool,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (kind, preposition) = match self.access.kind {
+            AccessKind::Read => ("read", "from"),
+            AccessKind::Write => ("write", "to"),
+        };
+        write!(
+            f,
+            "{} of 0x{:02X} {} 0x{:04X} by instruction at 0x{:04X}",
+            kind, self.access.value, preposition, self.access.address, self.pc
+        )
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct TrapOutcome {
     pub status: TrapOutcomeStatus,
@@ -51,12 +165,28 @@ pub struct TrapOutcome {
 pub struct Trap {
     pub cpu_trap: CpuTrap,
     pub requested_outcome: TrapOutcomeStatus,
+    // the trap only fires if the condition holds, and after ignore_count such hits:
+    pub condition: Option<Expr>,
+    pub ignore_count: u32,
 }
 
-#[derive(Debug)]
 pub struct TrapDoor {
     address_traps: Vec<Trap>,
     opcode_traps: Vec<Trap>,
+    // handlers are code, so they are not part of the traps saved in snapshots
+    handlers: Vec<(u16, Box<TrapHandler>)>,
+    watchpoints: Vec<Watchpoint>,
+}
+
+impl fmt::Debug for TrapDoor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TrapDoor")
+            .field("address_traps", &self.address_traps)
+            .field("opcode_traps", &self.opcode_traps)
+            .field("handlers", &self.handlers.len())
+            .field("watchpoints", &self.watchpoints)
+            .finish()
+    }
 }
 
 impl TrapDoor {
@@ -64,6 +194,8 @@ impl TrapDoor {
         let mut td = TrapDoor {
             address_traps: vec![],
             opcode_traps: vec![],
+            handlers: vec![],
+            watchpoints: vec![],
         };
         td.add_brk_trap();
         td
@@ -78,24 +210,47 @@ impl TrapDoor {
         self.opcode_traps.push(trap);
     }
 
+    /// Checks the traps for the instruction at address; when resuming at an address trap
+    /// that stopped execution, that trap is skipped. Conditions of address traps are
+    /// evaluated with the registers before the instruction; a hit counts down an ignore count.
     pub fn pre_execute(
-        &self,
-        decoded: DecodedInstruction,
+        &mut self,
+        decoded: &DecodedInstruction,
         address: u16,
+        resuming: bool,
+        registers: &Registers,
+        memory: &dyn Memory,
     ) -> Result<TrapOutcome, CpuError> {
         // precedence order: by address, then by instruction
         let try_address = CpuTrap::ByAddress(address);
         // TODO: looping over all traps is ok for small number of traps
-        for trap in &self.address_traps {
-            if try_address == trap.cpu_trap {
-                return Ok(TrapOutcome {
-                    // TODO: stop is correct for break points, but need to rethink when adding trap handlers
-                    // for Handled: set result and Continue
-                    status: trap.requested_outcome,
-                    triggered_by: Some(try_address),
-                    result: None,
-                });
+        for trap in self.address_traps.iter_mut() {
+            if try_address != trap.cpu_trap
+                || (resuming && trap.requested_outcome == TrapOutcomeStatus::Stop)
+            {
+                continue;
+            }
+            if let Some(condition) = &trap.condition
+                && !condition.is_true(registers, memory)?
+            {
+                continue;
             }
+            if trap.ignore_count > 0 {
+                trap.ignore_count -= 1;
+                continue;
+            }
+            return Ok(TrapOutcome {
+                status: trap.requested_outcome,
+                triggered_by: Some(try_address),
+                result: None,
+            });
+        }
+        if self.handlers.iter().any(|(a, _)| *a == address) {
+            return Ok(TrapOutcome {
+                status: TrapOutcomeStatus::Handled,
+                triggered_by: Some(try_address),
+                result: None,
+            });
         }
 
         let try_op_code = CpuTrap::ByInstruction(decoded.hex_opcode);
@@ -119,21 +274,196 @@ impl TrapDoor {
         self.add_opcode_trap(Trap {
             cpu_trap: CpuTrap::ByInstruction(0x00), // BRK
             requested_outcome: TrapOutcomeStatus::StopAfter,
+            condition: None,
+            ignore_count: 0,
         });
     }
 
+    /// Adds or removes the default BRK trap; without it, BRK is a software interrupt.
+    pub fn set_brk_trap(&mut self, enabled: bool) {
+        self.opcode_traps
+            .retain(|t| t.cpu_trap != CpuTrap::ByInstruction(0x00));
+        if enabled {
+            self.add_brk_trap();
+        }
+    }
+
+    /// All traps, address traps first.
+    pub fn get_traps(&self) -> Vec<Trap> {
+        self.address_traps
+            .iter()
+            .chain(self.opcode_traps.iter())
+            .cloned()
+            .collect()
+    }
+
+    /// Replaces all traps, including the default BRK trap.
+    pub fn set_traps(&mut self, traps: Vec<Trap>) {
+        self.address_traps.clear();
+        self.opcode_traps.clear();
+        for trap in traps {
+            match trap.cpu_trap {
+                CpuTrap::ByAddress(_) => self.add_addr_trap(trap),
+                CpuTrap::ByInstruction(_) => self.add_opcode_trap(trap),
+            }
+        }
+    }
+
+    pub fn is_address_trap(&self, address: u16) -> bool {
+        self.address_traps
+            .iter()
+            .any(|t| t.cpu_trap == CpuTrap::ByAddress(address))
+    }
+
+    /// Registers a handler for an address, replacing any previous handler for it.
+    pub fn add_handler(&mut self, address: u16, handler: Box<TrapHandler>) {
+        self.handlers.retain(|(a, _)| *a != address);
+        self.handlers.push((address, handler));
+    }
+
+    /// Calls the handler registered for the address with the CPU's registers and memory.
+    pub fn call_handler(
+        &mut self,
+        address: u16,
+        registers: TrapResult,
+        memory: &mut dyn Memory,
+    ) -> Result<TrapResult, CpuError> {
+        let (_, handler) = self
+            .handlers
+            .iter_mut()
+            .find(|(a, _)| *a == address)
+            .ok_or(CpuError::InvalidAddress)?;
+        let mut context = TrapContext {
+            accumulator: registers.accumulator,
+            index_x: registers.index_x,
+            index_y: registers.index_y,
+            status: registers.status,
+            memory,
+        };
+        handler(&mut context)
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    /// Removes a watchpoint equal to watchpoint; returns false if there was none.
+    pub fn remove_watchpoint(&mut self, watchpoint: &Watchpoint) -> bool {
+        match self.watchpoints.iter().position(|w| w == watchpoint) {
+            Some(index) => {
+                self.watchpoints.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn get_watchpoints(&self) -> Vec<Watchpoint> {
+        self.watchpoints.clone()
+    }
+
+    pub fn has_watchpoints(&self) -> bool {
+        !self.watchpoints.is_empty()
+    }
+
+    /// Returns the first access that triggers a watchpoint; a watchpoint that stops
+    /// before the instruction takes precedence.
+    pub fn check_watchpoints(&self, pc: u16, accesses: &[MemoryAccess]) -> Option<WatchHit> {
+        let mut hit = None;
+        for access in accesses {
+            for watchpoint in self.watchpoints.iter().filter(|w| w.matches(access)) {
+                if watchpoint.stop_before {
+                    return Some(WatchHit {
+                        pc,
+                        access: *access,
+                        stop_before: true,
+                    });
+                }
+                hit = hit.or(Some(WatchHit {
+                    pc,
+                    access: *access,
+                    stop_before: false,
+                }));
+            }
+        }
+        hit
+    }
+
+    /// Adds a breakpoint: a trap that stops before the instruction at address.
     #[allow(dead_code)]
     pub fn add_address_trap(&mut self, address: u16) {
+        self.add_conditional_address_trap(address, None, 0);
+    }
+
+    /// Adds a breakpoint that stops if the condition holds, after ignoring ignore_count hits;
+    /// it replaces a previous breakpoint at address.
+    pub fn add_conditional_address_trap(
+        &mut self,
+        address: u16,
+        condition: Option<Expr>,
+        ignore_count: u32,
+    ) {
+        self.remove_address_trap(address);
         self.add_addr_trap(Trap {
-            cpu_trap: CpuTrap::ByAddress(address), // BRK
+            cpu_trap: CpuTrap::ByAddress(address),
             requested_outcome: TrapOutcomeStatus::Stop,
+            condition,
+            ignore_count,
         });
     }
+
+    /// Removes the address traps at address; returns false if there was none.
+    pub fn remove_address_trap(&mut self, address: u16) -> bool {
+        let count = self.address_traps.len();
+        self.address_traps
+            .retain(|t| t.cpu_trap != CpuTrap::ByAddress(address));
+        self.address_traps.len() != count
+    }
+
+    /// All address traps as breakpoints, in the order they were added; ignore counts
+    /// are what is left of them.
+    pub fn get_address_traps(&self) -> Vec<Breakpoint> {
+        self.address_traps
+            .iter()
+            .filter_map(|t| match t.cpu_trap {
+                CpuTrap::ByAddress(address) => Some(Breakpoint {
+                    address,
+                    condition: t.condition.clone(),
+                    ignore_count: t.ignore_count,
+                }),
+                CpuTrap::ByInstruction(_) => None,
+            })
+            .collect()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::memory::MemoryImpl;
+
+    fn pre_execute(
+        td: &mut TrapDoor,
+        decoded: &DecodedInstruction,
+        address: u16,
+        resuming: bool,
+    ) -> Result<TrapOutcome, CpuError> {
+        let registers = Registers {
+            accumulator: 0,
+            index_x: 0,
+            index_y: 0,
+            stack_pointer: 0xFD,
+            program_counter: address,
+            status: 0,
+        };
+        td.pre_execute(
+            decoded,
+            address,
+            resuming,
+            &registers,
+            &MemoryImpl::default(),
+        )
+    }
 
     #[test]
     fn can_print_cpu_trap() {
@@ -148,47 +478,214 @@ mod tests {
 
     #[test]
     fn can_trap_on_brk_opcode() -> Result<(), CpuError> {
-        let td = TrapDoor::new();
-        let decoded = decoder::decode(0x00)?;
-        let outcome = td.pre_execute(decoded, 0x0000)?;
+        let mut td = TrapDoor::new();
+        let decoded = decoder::decode(0x00, CpuType::MOS6502);
+        let outcome = pre_execute(&mut td, decoded, 0x0000, false)?;
         assert_eq!(outcome.status, TrapOutcomeStatus::StopAfter);
         assert_eq!(outcome.triggered_by, Some(CpuTrap::ByInstruction(0x00)));
+
+        td.set_brk_trap(false);
+        assert!(td.get_traps().is_empty());
+        let outcome = pre_execute(&mut td, decoded, 0x0000, false)?;
+        assert_eq!(outcome.status, TrapOutcomeStatus::Continue);
+
+        // enabling it twice still adds a single trap:
+        td.set_brk_trap(true);
+        td.set_brk_trap(true);
+        assert_eq!(td.get_traps().len(), 1);
         Ok(())
     }
 
     #[test]
     fn can_trap_on_address() -> Result<(), CpuError> {
         let mut td = TrapDoor::new();
-        let decoded = decoder::decode(0xA9)?;
+        let decoded = decoder::decode(0xA9, CpuType::MOS6502);
         let address = 0x0400;
         td.add_address_trap(address);
         // try non-matching address
-        let outcome = td.pre_execute(decoded, 0x1234)?;
+        let outcome = pre_execute(&mut td, decoded, 0x1234, false)?;
         assert_eq!(outcome.status, TrapOutcomeStatus::Continue);
         assert_eq!(outcome.triggered_by, None);
 
-        let decoded = decoder::decode(0x85)?;
-        let outcome = td.pre_execute(decoded, address)?;
+        let decoded = decoder::decode(0x85, CpuType::MOS6502);
+        let outcome = pre_execute(&mut td, decoded, address, false)?;
         assert_eq!(outcome.status, TrapOutcomeStatus::Stop);
         assert_eq!(outcome.triggered_by, Some(CpuTrap::ByAddress(address)));
+        // resuming at the address trap continues:
+        let outcome = pre_execute(&mut td, decoded, address, true)?;
+        assert_eq!(outcome.status, TrapOutcomeStatus::Continue);
+        Ok(())
+    }
+
+    #[test]
+    fn can_add_and_remove_address_traps() {
+        let mut td = TrapDoor::new();
+        td.add_address_trap(0x0400);
+        td.add_address_trap(0x0300);
+        // adding a trap again replaces it:
+        td.add_address_trap(0x0400);
+        let addresses = |td: &TrapDoor| -> Vec<u16> {
+            td.get_address_traps().iter().map(|b| b.address).collect()
+        };
+        assert_eq!(addresses(&td), vec![0x0300, 0x0400]);
+
+        assert!(td.remove_address_trap(0x0400));
+        assert!(!td.remove_address_trap(0x0400));
+        assert_eq!(addresses(&td), vec![0x0300]);
+        // the BRK trap is unaffected:
+        assert_eq!(td.get_traps().len(), 2);
+    }
+
+    #[test]
+    fn can_call_handler() -> Result<(), CpuError> {
+        let mut td = TrapDoor::new();
+        let mut memory = crate::memory::MemoryImpl::default();
+        td.add_handler(
+            0xFFD2,
+            Box::new(|ctx: &mut TrapContext| {
+                ctx.write(0x0400, ctx.accumulator)?;
+                ctx.index_x = 0x01;
+                Ok(ctx.result())
+            }),
+        );
+        let decoded = decoder::decode(0xEA, CpuType::MOS6502);
+        let outcome = pre_execute(&mut td, decoded, 0xFFD2, false)?;
+        assert_eq!(outcome.status, TrapOutcomeStatus::Handled);
+        assert_eq!(outcome.triggered_by, Some(CpuTrap::ByAddress(0xFFD2)));
+        // handlers are not part of the saved traps:
+        assert_eq!(td.get_traps().len(), 1);
+
+        let registers = TrapResult {
+            accumulator: 0x41,
+            index_x: 0,
+            index_y: 0,
+            status: 0,
+        };
+        let result = td.call_handler(0xFFD2, registers.clone(), &mut memory)?;
+        assert_eq!(result.accumulator, 0x41);
+        assert_eq!(result.index_x, 0x01);
+        assert_eq!(memory.read(0x0400)?, 0x41);
+
+        assert_eq!(
+            td.call_handler(0xFFE4, registers, &mut memory),
+            Err(CpuError::InvalidAddress)
+        );
+        // address traps, e.g. breakpoints, take precedence over handlers:
+        td.add_address_trap(0xFFD2);
+        let outcome = pre_execute(&mut td, decoded, 0xFFD2, false)?;
+        assert_eq!(outcome.status, TrapOutcomeStatus::Stop);
         Ok(())
     }
 
     #[test]
     fn can_trap_precedence() -> Result<(), CpuError> {
         let mut td = TrapDoor::new();
-        let decoded = decoder::decode(0x00)?;
+        let decoded = decoder::decode(0x00, CpuType::MOS6502);
         let address = 0x0400;
         td.add_address_trap(address);
         // try non-matching address
-        let outcome = td.pre_execute(decoded.clone(), address)?;
+        let outcome = pre_execute(&mut td, decoded, address, false)?;
         println!("outcome: {:?}", outcome);
         assert_eq!(outcome.status, TrapOutcomeStatus::Stop);
         assert_eq!(outcome.triggered_by, Some(CpuTrap::ByAddress(address)));
 
-        let outcome = td.pre_execute(decoded, address + 1)?;
+        let outcome = pre_execute(&mut td, decoded, address + 1, false)?;
         assert_eq!(outcome.status, TrapOutcomeStatus::StopAfter);
         assert_eq!(outcome.triggered_by, Some(CpuTrap::ByInstruction(0x00)));
         Ok(())
     }
+
+    #[test]
+    fn can_check_watchpoints() {
+        let mut td = TrapDoor::new();
+        let read = MemoryAccess {
+            address: 0x0210,
+            value: 0x42,
+            kind: AccessKind::Read,
+        };
+        let write = MemoryAccess {
+            kind: AccessKind::Write,
+            ..read
+        };
+        assert_eq!(td.check_watchpoints(0x0300, &[read, write]), None);
+
+        td.add_watchpoint(Watchpoint {
+            range: 0x0200..=0x020F,
+            kind: WatchKind::Access,
+            value: None,
+            stop_before: false,
+        });
+        assert_eq!(td.check_watchpoints(0x0300, &[read, write]), None);
+
+        td.add_watchpoint(Watchpoint {
+            range: 0x0210..=0x0210,
+            kind: WatchKind::Write,
+            value: Some(0x42),
+            stop_before: false,
+        });
+        let hit = td.check_watchpoints(0x0300, &[read, write]).unwrap();
+        assert_eq!(hit.access, write);
+        assert_eq!(
+            hit.to_string(),
+            "write of 0x42 to 0x0210 by instruction at 0x0300"
+        );
+        let other_value = MemoryAccess {
+            value: 0x43,
+            ..write
+        };
+        assert_eq!(td.check_watchpoints(0x0300, &[other_value]), None);
+
+        // stopping before the instruction takes precedence:
+        td.add_watchpoint(Watchpoint {
+            range: 0x0210..=0x0210,
+            kind: WatchKind::Read,
+            value: None,
+            stop_before: true,
+        });
+        let hit = td.check_watchpoints(0x0300, &[write, read]).unwrap();
+        assert_eq!(hit.access, read);
+        assert!(hit.stop_before);
+
+        let watchpoints = td.get_watchpoints();
+        assert_eq!(watchpoints.len(), 3);
+        assert!(td.remove_watchpoint(&watchpoints[2]));
+        assert!(!td.remove_watchpoint(&watchpoints[2]));
+        let hit = td.check_watchpoints(0x0300, &[write, read]).unwrap();
+        assert_eq!(hit.access, write);
+    }
+
+    #[test]
+    fn can_trap_on_condition_after_ignore_count() -> Result<(), CpuError> {
+        use crate::condition::{BinaryOp, RegisterName};
+        let mut td = TrapDoor::new();
+        let decoded = decoder::decode(0xEA, CpuType::MOS6502);
+        let memory = MemoryImpl::default();
+        let mut registers = Registers {
+            accumulator: 0x11,
+            index_x: 0,
+            index_y: 0,
+            stack_pointer: 0xFD,
+            program_counter: 0x0205,
+            status: 0,
+        };
+        // A == $12, ignore 1
+        let condition = Expr::Binary(
+            BinaryOp::Eq,
+            Box::new(Expr::Register(RegisterName::Accumulator)),
+            Box::new(Expr::Number(0x12)),
+        );
+        td.add_conditional_address_trap(0x0205, Some(condition), 1);
+
+        let mut check = |registers: &Registers| {
+            td.pre_execute(decoded, 0x0205, false, registers, &memory)
+                .map(|outcome| outcome.status)
+        };
+        assert_eq!(check(&registers)?, TrapOutcomeStatus::Continue);
+        registers.accumulator = 0x12;
+        // the first hit is ignored:
+        assert_eq!(check(&registers)?, TrapOutcomeStatus::Continue);
+        assert_eq!(check(&registers)?, TrapOutcomeStatus::Stop);
+        assert_eq!(check(&registers)?, TrapOutcomeStatus::Stop);
+        Ok(())
+    }
 }
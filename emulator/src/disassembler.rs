@@ -1,14 +1,15 @@
+use crate::memory::Memory;
 use crate::{CpuError, CpuImpl, cpu_impl::AddressingMode, engine::decoder};
 
-pub fn disassemble(cpu: &CpuImpl, address: u16) -> Result<(String, u16), CpuError> {
-    let decoded_instr = decoder::decode(cpu.get_byte_at(address)?)?;
+pub fn disassemble<B: Memory>(cpu: &CpuImpl<B>, address: u16) -> Result<(String, u16), CpuError> {
+    let decoded_instr = decoder::decode(cpu.peek(address)?, cpu.get_cpu_type());
     let mut operand_bytes: [u8; 2] = [0; 2];
 
     for i in 0..decoded_instr.extra_bytes {
-        operand_bytes[i as usize] = cpu.get_byte_at(address.wrapping_add(i as u16 + 1))?;
+        operand_bytes[i as usize] = cpu.peek(address.wrapping_add(i as u16 + 1))?;
     }
     // for (i, val) in operand_bytes.iter_mut().enumerate() {
-    //     *val = cpu.get_byte_at(address.wrapping_add(i as u16 + 1))?;
+    //     *val = cpu.peek(address.wrapping_add(i as u16 + 1))?;
     // }
     let disassembly = format!(
         "{:04X} {} {}",
@@ -41,7 +42,7 @@ fn get_operand(
             "${:02X} ({:04X})",
             extra_bytes[0],
             // branch instruction offsets are relative to the next instruction
-            addr_from_offset(extra_bytes[0], address + 2)
+            addr_from_offset(extra_bytes[0], address.wrapping_add(2))
         )),
         AddressingMode::Absolute => Ok(format!("${:04X}", as_word(extra_bytes))),
         AddressingMode::AbsoluteX => Ok(format!("${:04X},X", as_word(extra_bytes))),
@@ -49,6 +50,14 @@ fn get_operand(
         AddressingMode::Indirect => Ok(format!("(${:04X})", as_word(extra_bytes))),
         AddressingMode::IndexedXIndirect => Ok(format!("(${:02X},X)", extra_bytes[0])),
         AddressingMode::IndirectIndexedY => Ok(format!("(${:02X}),Y", extra_bytes[0])),
+        AddressingMode::ZeroPageIndirect => Ok(format!("(${:02X})", extra_bytes[0])),
+        AddressingMode::AbsoluteIndexedIndirect => Ok(format!("(${:04X},X)", as_word(extra_bytes))),
+        AddressingMode::ZeroPageRelative => Ok(format!(
+            "${:02X},${:02X} ({:04X})",
+            extra_bytes[0],
+            extra_bytes[1],
+            addr_from_offset(extra_bytes[1], address.wrapping_add(3))
+        )),
     }
 }
 
@@ -139,4 +148,58 @@ mod tests {
         assert_eq!(next_addr, 0x0604);
         Ok(())
     }
+
+    #[test]
+    fn disassemble_undocumented_opcode() -> Result<(), CpuError> {
+        let mut cpu = CpuImpl::with_cpu_type(crate::CpuType::MOS6502Undocumented);
+        cpu.load_program(
+            0x0600,
+            &[
+                0xA7, 0x10, // LAX $10
+                0xFF, 0x34, 0x12, // ISC $1234,X
+                0xFA, // NOP
+            ],
+            true,
+        )?;
+
+        let (mut result, mut next_addr) = disassemble(&cpu, 0x0600)?;
+        assert_eq!(result, "0600 LAX $10");
+        assert_eq!(next_addr, 0x0602);
+
+        (result, next_addr) = disassemble(&cpu, next_addr)?;
+        assert_eq!(result, "0602 ISC $1234,X");
+        assert_eq!(next_addr, 0x0605);
+
+        (result, next_addr) = disassemble(&cpu, next_addr)?;
+        assert_eq!(result, "0605 NOP");
+        assert_eq!(next_addr, 0x0606);
+        Ok(())
+    }
+
+    #[test]
+    fn disassemble_wdc65c02_opcode() -> Result<(), CpuError> {
+        let mut cpu = CpuImpl::with_cpu_type(crate::CpuType::WDC65C02);
+        cpu.load_program(
+            0x0600,
+            &[
+                0xB2, 0x10, // LDA ($10)
+                0x7C, 0x34, 0x12, // JMP ($1234,X)
+                0x8F, 0x20, 0xFB, // BBS0 $20,$FB
+            ],
+            true,
+        )?;
+
+        let (mut result, mut next_addr) = disassemble(&cpu, 0x0600)?;
+        assert_eq!(result, "0600 LDA ($10)");
+        assert_eq!(next_addr, 0x0602);
+
+        (result, next_addr) = disassemble(&cpu, next_addr)?;
+        assert_eq!(result, "0602 JMP ($1234,X)");
+        assert_eq!(next_addr, 0x0605);
+
+        (result, next_addr) = disassemble(&cpu, next_addr)?;
+        assert_eq!(result, "0605 BBS0 $20,$FB (0603)");
+        assert_eq!(next_addr, 0x0608);
+        Ok(())
+    }
 }
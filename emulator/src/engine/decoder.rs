@@ -1,23 +1,24 @@
-use crate::CpuError;
 use crate::cpu_impl::{AddressingMode, CpuImpl};
 use crate::engine::opcodes::OpCode;
 use crate::engine::ops::alu::*;
 use crate::engine::ops::branch_jump::*;
-use crate::engine::ops::execute_nop;
 use crate::engine::ops::flag_compare::*;
 use crate::engine::ops::interrupt::*;
 use crate::engine::ops::stack::*;
 use crate::engine::ops::transfer::*;
-
-type OpCodeExecute = fn(AddressingMode, &mut CpuImpl) -> Result<(), CpuError>;
+use crate::engine::ops::undocumented::*;
+use crate::engine::ops::wdc65c02::*;
+use crate::engine::ops::{execute_illegal, execute_nop};
+use crate::memory::Memory;
+use crate::{CpuError, CpuType};
 
 #[derive(Debug, Clone)]
 pub struct DecodedInstruction {
     pub opcode: OpCode,
     pub mode: AddressingMode,
-    pub execute: OpCodeExecute,
     pub extra_bytes: u8,
     pub cycles: u8,
+    pub page_cross_penalty: bool, // +1 cycle if indexed address crosses a page boundary
     pub hex_opcode: u8,
 }
 
@@ -28,68 +29,156 @@ impl DecodedInstruction {
     }
 }
 
+/// Looks up the instruction for an opcode byte; opcodes the CPU type does not define
+/// decode to OpCode::ILL.
+pub fn decode(opcode_byte: u8, cpu_type: CpuType) -> &'static DecodedInstruction {
+    let table = match cpu_type {
+        CpuType::MOS6502 => &MOS6502_OPCODES,
+        CpuType::MOS6502Undocumented => &MOS6502_UNDOCUMENTED_OPCODES,
+        CpuType::WDC65C02 => &WDC65C02_OPCODES,
+    };
+    &table[opcode_byte as usize]
+}
+
+/// Executes a decoded instruction. The dispatch is a match over the op codes rather than
+/// function pointers in the decode tables, so that it is monomorphized for the bus type.
 #[rustfmt::skip]
-pub fn decode(opcode_byte: u8) -> Result<DecodedInstruction, CpuError> {
-    match decode_via_csv(opcode_byte) {
-        Ok(decoded) => Ok(decoded),
-        Err(_) => Ok(DecodedInstruction {
-            opcode: OpCode::ILL(opcode_byte),
-            mode: AddressingMode::Implied,
-            execute: execute_brk,
-            extra_bytes: 0,
-            cycles: 0,
-            hex_opcode: opcode_byte,
-        }),
-    }
+pub fn execute<B: Memory>(decoded: &DecodedInstruction, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    // generated via ../build.rs from the op codes of all CSV files
+    include!(concat!(env!("OUT_DIR"), "/execute_opcodes.rs"))
 }
 
+// lookup tables are generated via ../build.rs from the CSV files:
+// generated files somewhere at: target/debug/build/mos6502-emulator-<generatedId>/out/opcodes_mos6502.rs
+// see also compile output for actual path
+#[rustfmt::skip]
+static MOS6502_OPCODES: [DecodedInstruction; 256] =
+    include!(concat!(env!("OUT_DIR"), "/opcodes_mos6502.rs"));
+
+#[rustfmt::skip]
+static MOS6502_UNDOCUMENTED_OPCODES: [DecodedInstruction; 256] =
+    include!(concat!(env!("OUT_DIR"), "/opcodes_mos6502_undocumented.rs"));
+
 #[rustfmt::skip]
-fn decode_via_csv(opcode_byte: u8) -> Result<DecodedInstruction, CpuError> {
-    // lookup table is generated via ../build.rs from a CSV file:
-    // generated file somewhere at: target/debug/build/mos6502-emulator-<generatedId>/out/opcodes_mos6502.r
-    // see also compile output for actual path
-    include!(concat!(env!("OUT_DIR"), "/opcodes_mos6502.rs"))
- }
+static WDC65C02_OPCODES: [DecodedInstruction; 256] =
+    include!(concat!(env!("OUT_DIR"), "/opcodes_wdc65c02.rs"));
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
-    fn decode_legal_opcode() -> Result<(), CpuError> {
+    fn decode_legal_opcode() {
         // BRK
-        let decoded = decode(0x00)?;
+        let decoded = decode(0x00, CpuType::MOS6502);
         assert_eq!(decoded.opcode, OpCode::BRK);
         assert_eq!(decoded.mode, AddressingMode::Implied);
         assert_eq!(decoded.extra_bytes, 0);
         assert_eq!(decoded.cycles, 7);
 
         // STY
-        let decoded = decode(0x8c)?;
+        let decoded = decode(0x8c, CpuType::MOS6502);
         assert_eq!(decoded.opcode, OpCode::STY);
         assert_eq!(decoded.mode, AddressingMode::Absolute);
         assert_eq!(decoded.extra_bytes, 2);
         assert_eq!(decoded.cycles, 4);
-        Ok(())
+        assert!(!decoded.page_cross_penalty);
+
+        // LDA abs,X
+        let decoded = decode(0xbd, CpuType::MOS6502);
+        assert_eq!(decoded.opcode, OpCode::LDA);
+        assert_eq!(decoded.mode, AddressingMode::AbsoluteX);
+        assert_eq!(decoded.cycles, 4);
+        assert!(decoded.page_cross_penalty);
     }
 
     #[test]
-    fn decode_illegal_opcode() -> Result<(), CpuError> {
-        let decoded = decode(0xff)?;
+    fn decode_illegal_opcode() {
+        let decoded = decode(0xff, CpuType::MOS6502);
         assert_eq!(decoded.opcode, OpCode::ILL(0xff));
         assert_eq!(decoded.mode, AddressingMode::Implied);
         assert_eq!(decoded.extra_bytes, 0);
         assert_eq!(decoded.cycles, 0);
-        Ok(())
     }
 
     #[test]
-    fn get_mnemonic() -> Result<(), CpuError> {
-        let decoded = decode(0x00)?;
+    fn decode_undocumented_opcode() {
+        // ISC abs,X
+        let decoded = decode(0xff, CpuType::MOS6502Undocumented);
+        assert_eq!(decoded.opcode, OpCode::ISC);
+        assert_eq!(decoded.mode, AddressingMode::AbsoluteX);
+        assert_eq!(decoded.extra_bytes, 2);
+        assert_eq!(decoded.cycles, 7);
+        assert!(!decoded.page_cross_penalty);
+
+        // LAX (zp),Y
+        let decoded = decode(0xb3, CpuType::MOS6502Undocumented);
+        assert_eq!(decoded.opcode, OpCode::LAX);
+        assert_eq!(decoded.mode, AddressingMode::IndirectIndexedY);
+        assert_eq!(decoded.cycles, 5);
+        assert!(decoded.page_cross_penalty);
+
+        // documented opcodes are unchanged:
+        let decoded = decode(0x8c, CpuType::MOS6502Undocumented);
+        assert_eq!(decoded.opcode, OpCode::STY);
+    }
+
+    #[test]
+    fn decode_wdc65c02_opcode() {
+        // LDA (zp)
+        let decoded = decode(0xb2, CpuType::WDC65C02);
+        assert_eq!(decoded.opcode, OpCode::LDA);
+        assert_eq!(decoded.mode, AddressingMode::ZeroPageIndirect);
+        assert_eq!(decoded.extra_bytes, 1);
+        assert_eq!(decoded.cycles, 5);
+
+        // BBS3 zp,rel
+        let decoded = decode(0xbf, CpuType::WDC65C02);
+        assert_eq!(decoded.opcode, OpCode::BBS3);
+        assert_eq!(decoded.mode, AddressingMode::ZeroPageRelative);
+        assert_eq!(decoded.extra_bytes, 2);
+
+        // JMP (abs) takes an extra cycle:
+        let decoded = decode(0x6c, CpuType::WDC65C02);
+        assert_eq!(decoded.opcode, OpCode::JMP);
+        assert_eq!(decoded.cycles, 6);
+
+        // reserved opcodes are NOPs:
+        let decoded = decode(0x5c, CpuType::WDC65C02);
+        assert_eq!(decoded.opcode, OpCode::NOP);
+        assert_eq!(decoded.extra_bytes, 2);
+        assert_eq!(decoded.cycles, 8);
+
+        // all opcodes are defined:
+        for opcode_byte in 0..=0xFF {
+            let decoded = decode(opcode_byte, CpuType::WDC65C02);
+            assert_ne!(decoded.opcode, OpCode::ILL(opcode_byte));
+        }
+
+        // documented NMOS opcodes are unchanged:
+        let decoded = decode(0x8c, CpuType::WDC65C02);
+        assert_eq!(decoded.opcode, OpCode::STY);
+    }
+
+    #[test]
+    fn decode_tables_indexed_by_opcode() {
+        for cpu_type in [
+            CpuType::MOS6502,
+            CpuType::MOS6502Undocumented,
+            CpuType::WDC65C02,
+        ] {
+            for opcode_byte in 0..=0xFF {
+                assert_eq!(decode(opcode_byte, cpu_type).hex_opcode, opcode_byte);
+            }
+        }
+    }
+
+    #[test]
+    fn get_mnemonic() {
+        let decoded = decode(0x00, CpuType::MOS6502);
         assert_eq!(decoded.get_mnemonic(), "BRK");
 
-        let decoded = decode(0xFA)?;
+        let decoded = decode(0xFA, CpuType::MOS6502);
         assert_eq!(decoded.get_mnemonic(), "ILL(FA)");
-        Ok(())
     }
 }
@@ -60,6 +60,72 @@ pub enum OpCode {
     TXS, // Transfer Index X to Stack pointer
     TYA, // Transfer Index Y to Accumulator
 
+    // undocumented NMOS opcodes, see also https://www.masswerk.at/nowgobang/2021/6502-illegal-opcodes
+    ALR, // "AND" Memory with Accumulator, then Shift Right Accumulator
+    ANC, // "AND" Memory with Accumulator, then copy N to C
+    ANE, // (unstable) "AND" Index X and Memory with Accumulator
+    ARR, // "AND" Memory with Accumulator, then Rotate Right Accumulator
+    DCP, // Decrement Memory by One, then Compare with Accumulator
+    ISC, // Increment Memory by One, then Subtract from Accumulator with Borrow
+    JAM, // Halt the CPU
+    LAS, // "AND" Memory with Stack Pointer, transfer to Accumulator, Index X and Stack Pointer
+    LAX, // Load Accumulator and Index X with Memory
+    LXA, // (unstable) "AND" Memory with Accumulator, transfer to Accumulator and Index X
+    RLA, // Rotate One Bit Left in Memory, then "AND" with Accumulator
+    RRA, // Rotate One Bit Right in Memory, then Add to Accumulator with Carry
+    SAX, // Store Accumulator "AND" Index X in Memory
+    SBX, // "AND" Accumulator with Index X, subtract Memory without Borrow, transfer to Index X
+    SHA, // (unstable) Store Accumulator "AND" Index X "AND" High Address Byte + 1
+    SHX, // (unstable) Store Index X "AND" High Address Byte + 1
+    SHY, // (unstable) Store Index Y "AND" High Address Byte + 1
+    SLO, // Shift Left One Bit in Memory, then "OR" with Accumulator
+    SRE, // Shift Right One Bit in Memory, then "Exclusive-Or" with Accumulator
+    TAS, // (unstable) Transfer Accumulator "AND" Index X to Stack Pointer, then store like SHA
+
+    // 65C02 opcodes, see also http://www.6502.org/tutorials/65c02opcodes.html
+    BBR0, // Branch on Bit 0 Reset in Memory
+    BBR1, // Branch on Bit 1 Reset in Memory
+    BBR2, // Branch on Bit 2 Reset in Memory
+    BBR3, // Branch on Bit 3 Reset in Memory
+    BBR4, // Branch on Bit 4 Reset in Memory
+    BBR5, // Branch on Bit 5 Reset in Memory
+    BBR6, // Branch on Bit 6 Reset in Memory
+    BBR7, // Branch on Bit 7 Reset in Memory
+    BBS0, // Branch on Bit 0 Set in Memory
+    BBS1, // Branch on Bit 1 Set in Memory
+    BBS2, // Branch on Bit 2 Set in Memory
+    BBS3, // Branch on Bit 3 Set in Memory
+    BBS4, // Branch on Bit 4 Set in Memory
+    BBS5, // Branch on Bit 5 Set in Memory
+    BBS6, // Branch on Bit 6 Set in Memory
+    BBS7, // Branch on Bit 7 Set in Memory
+    BRA,  // Branch Always
+    PHX,  // Push Index X on Stack
+    PHY,  // Push Index Y on Stack
+    PLX,  // Pull Index X from Stack
+    PLY,  // Pull Index Y from Stack
+    RMB0, // Reset Bit 0 in Memory
+    RMB1, // Reset Bit 1 in Memory
+    RMB2, // Reset Bit 2 in Memory
+    RMB3, // Reset Bit 3 in Memory
+    RMB4, // Reset Bit 4 in Memory
+    RMB5, // Reset Bit 5 in Memory
+    RMB6, // Reset Bit 6 in Memory
+    RMB7, // Reset Bit 7 in Memory
+    SMB0, // Set Bit 0 in Memory
+    SMB1, // Set Bit 1 in Memory
+    SMB2, // Set Bit 2 in Memory
+    SMB3, // Set Bit 3 in Memory
+    SMB4, // Set Bit 4 in Memory
+    SMB5, // Set Bit 5 in Memory
+    SMB6, // Set Bit 6 in Memory
+    SMB7, // Set Bit 7 in Memory
+    STP,  // Stop the CPU
+    STZ,  // Store Zero in Memory
+    TRB,  // Test and Reset Bits in Memory with Accumulator
+    TSB,  // Test and Set Bits in Memory with Accumulator
+    WAI,  // Wait for Interrupt
+
     ILL(u8), // Illegal opcode
 }
 
@@ -84,6 +150,9 @@ mod tests {
         assert_eq!(OpCode::LDA.to_string(), "LDA");
         assert_eq!(OpCode::TYA.to_string(), "TYA");
 
+        assert_eq!(OpCode::LAX.to_string(), "LAX");
+        assert_eq!(OpCode::BBR7.to_string(), "BBR7");
+
         assert_eq!(OpCode::ILL(0xff).to_string(), "ILL(FF)");
     }
 }
@@ -1,5 +1,6 @@
-use crate::CpuError;
 use crate::cpu_impl::{AddressingMode, CpuImpl};
+use crate::memory::Memory;
+use crate::{CpuError, CpuType};
 
 use super::transfer::memory_write_tolerate_readonly;
 
@@ -7,11 +8,22 @@ use super::transfer::memory_write_tolerate_readonly;
 
 // ADC:    A + M + C -> A, C
 // status: NV ...ZC
-pub fn execute_adc(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_adc<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    let operand = cpu.get_effective_operand(mode)?;
+    add_to_accumulator(operand, cpu);
+    Ok(())
+}
+
+pub fn add_to_accumulator<B: Memory>(operand: u8, cpu: &mut CpuImpl<B>) {
     if cpu.status.decimal_mode() {
-        todo!("decimal mode not implemented");
+        add_decimal(cpu, operand);
+        if cpu.get_cpu_type() == CpuType::WDC65C02 {
+            // N and Z are valid, at the cost of an extra cycle
+            cpu.status.update_from(cpu.accumulator);
+            cpu.add_extra_cycles(1);
+        }
+        return;
     }
-    let operand = cpu.get_effective_operand(mode)?;
 
     let result = cpu.accumulator as u16 + operand as u16 + cpu.status.carry() as u16;
 
@@ -21,16 +33,26 @@ pub fn execute_adc(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuErr
     cpu.accumulator = acc;
     cpu.status.update_from(acc);
     cpu.status.set_carry(result > 0xFF);
-    Ok(())
 }
 
 // SBC:    A - M - C̅ -> A
 // status: NV ...ZC
-pub fn execute_sbc(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_sbc<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    let operand = cpu.get_effective_operand(mode)?;
+    subtract_from_accumulator(operand, cpu);
+    Ok(())
+}
+
+pub fn subtract_from_accumulator<B: Memory>(operand: u8, cpu: &mut CpuImpl<B>) {
     if cpu.status.decimal_mode() {
-        todo!("decimal mode not implemented");
+        if cpu.get_cpu_type() == CpuType::WDC65C02 {
+            subtract_decimal_cmos(cpu, operand);
+            cpu.add_extra_cycles(1);
+        } else {
+            subtract_decimal(cpu, operand);
+        }
+        return;
     }
-    let operand = cpu.get_effective_operand(mode)?;
 
     let (acc, carry, overflow) = subtract_with_carry(cpu.accumulator, operand, cpu.status.carry());
 
@@ -38,19 +60,86 @@ pub fn execute_sbc(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuErr
     cpu.accumulator = acc;
     cpu.status.update_from(acc);
     cpu.status.set_carry(carry);
-    Ok(())
 }
 
 pub fn subtract_with_carry(register: u8, operand: u8, carry: bool) -> (u8, bool, bool) {
     let borrow: i16 = if carry { 0 } else { 1 };
 
-    let result = register as i16 - operand as i16 + borrow;
+    let result = register as i16 - operand as i16 - borrow;
 
-    // let res_byte = result as u8;
-    let is_overflow = is_overflow(register, operand, result);
+    // A - M - C̅ is the same as A + !M + C, so check overflow as for an addition:
+    let is_overflow = is_overflow(register, !operand, result);
     (result as u8, result >= 0, is_overflow)
 }
 
+// Decimal (BCD) mode, NMOS behavior:
+// the result is a valid BCD number if both operands are valid BCD numbers;
+// see also http://www.6502.org/tutorials/decimal_mode.html, Appendix A.
+// For ADC, Z is set from the binary sum, while N and V reflect the intermediate result
+// after the low nibble adjustment, but before the high nibble adjustment.
+fn add_decimal<B: Memory>(cpu: &mut CpuImpl<B>, operand: u8) {
+    let carry = cpu.status.carry() as u16;
+    let binary_result = cpu.accumulator as u16 + operand as u16 + carry;
+
+    let mut low = (cpu.accumulator & 0x0F) as u16 + (operand & 0x0F) as u16 + carry;
+    let mut high = (cpu.accumulator & 0xF0) as u16 + (operand & 0xF0) as u16;
+    if low > 0x09 {
+        low += 0x06;
+        high += 0x10;
+    }
+    cpu.status.set_negative(high & 0x80 != 0);
+    cpu.status
+        .set_overflow(is_overflow(cpu.accumulator, operand, high as i16));
+    cpu.status.set_zero(binary_result & 0xFF == 0);
+    if high > 0x90 {
+        high += 0x60;
+    }
+    cpu.status.set_carry(high > 0xFF);
+    cpu.accumulator = (high & 0xF0) as u8 | (low & 0x0F) as u8;
+}
+
+// For SBC, N, V, Z and C are all set from the binary difference, only the accumulator is
+// adjusted to BCD.
+fn subtract_decimal<B: Memory>(cpu: &mut CpuImpl<B>, operand: u8) {
+    let (binary_result, carry, overflow) =
+        subtract_with_carry(cpu.accumulator, operand, cpu.status.carry());
+    let borrow: i16 = if cpu.status.carry() { 0 } else { 1 };
+
+    let mut low = (cpu.accumulator & 0x0F) as i16 - (operand & 0x0F) as i16 - borrow;
+    let mut high = (cpu.accumulator & 0xF0) as i16 - (operand & 0xF0) as i16;
+    if low < 0 {
+        low -= 0x06;
+        high -= 0x10;
+    }
+    if high < 0 {
+        high -= 0x60;
+    }
+    cpu.status.set_overflow(overflow);
+    cpu.status.update_from(binary_result);
+    cpu.status.set_carry(carry);
+    cpu.accumulator = (high & 0xF0) as u8 | (low & 0x0F) as u8;
+}
+
+// 65C02: the accumulator is adjusted differently for invalid BCD operands (Appendix A, Seq. 4);
+// N and Z are set from the accumulator, V and C from the binary difference.
+fn subtract_decimal_cmos<B: Memory>(cpu: &mut CpuImpl<B>, operand: u8) {
+    let (_, carry, overflow) = subtract_with_carry(cpu.accumulator, operand, cpu.status.carry());
+    let borrow: i16 = if cpu.status.carry() { 0 } else { 1 };
+
+    let low = (cpu.accumulator & 0x0F) as i16 - (operand & 0x0F) as i16 - borrow;
+    let mut result = cpu.accumulator as i16 - operand as i16 - borrow;
+    if result < 0 {
+        result -= 0x60;
+    }
+    if low < 0 {
+        result -= 0x06;
+    }
+    cpu.accumulator = result as u8;
+    cpu.status.set_overflow(overflow);
+    cpu.status.update_from(cpu.accumulator);
+    cpu.status.set_carry(carry);
+}
+
 fn is_overflow(a: u8, b: u8, result_high_bit: i16) -> bool {
     let high_bit = (result_high_bit & 0x80) as u8;
     // Overflow bit is set when the sign of the result is not the same as the sign of both operands
@@ -66,7 +155,7 @@ fn is_overflow(a: u8, b: u8, result_high_bit: i16) -> bool {
 
 // AND:    A AND M -> A
 // status: N. ...Z.
-pub fn execute_and(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_and<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     let operand = cpu.get_effective_operand(mode)?;
     cpu.accumulator &= operand;
     cpu.status.update_from(cpu.accumulator);
@@ -75,7 +164,7 @@ pub fn execute_and(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuErr
 
 // EOR:    A EOR M -> A
 // status: N. ...Z.
-pub fn execute_eor(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_eor<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     let operand = cpu.get_effective_operand(mode)?;
     cpu.accumulator ^= operand;
     cpu.status.update_from(cpu.accumulator);
@@ -84,7 +173,7 @@ pub fn execute_eor(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuErr
 
 // ORA:    A OR M -> A
 // status: N. ...Z.
-pub fn execute_ora(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_ora<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     let operand = cpu.get_effective_operand(mode)?;
     cpu.accumulator |= operand;
     cpu.status.update_from(cpu.accumulator);
@@ -96,7 +185,7 @@ pub fn execute_ora(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuErr
 // ASL:    C <- [76543210] <- 0
 // status: N. ...ZC
 // affects either accumulator or memory (read/modify/write)
-pub fn execute_asl(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_asl<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     read_modify_write(mode, cpu, |operand, _| {
         let carry = operand & 0x80 != 0;
         let result = operand << 1;
@@ -108,7 +197,7 @@ pub fn execute_asl(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuErr
 // LSR:    0 -> [76543210] <- C
 // status: N. ...ZC
 // affects either accumulator or memory (read/modify/write)
-pub fn execute_lsr(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_lsr<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     read_modify_write(mode, cpu, |operand, _| {
         let carry = operand & 0x01 != 0;
         let result = operand >> 1;
@@ -120,7 +209,7 @@ pub fn execute_lsr(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuErr
 // ROL:    C <- [76543210] <- C
 // status: N. ...ZC
 // affects either accumulator or memory (read/modify/write)
-pub fn execute_rol(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_rol<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     read_modify_write(mode, cpu, |operand, old_carry| {
         let carry_mask = if old_carry { 0x01 } else { 0x00 };
         let new_carry = operand & 0x80 != 0;
@@ -133,7 +222,7 @@ pub fn execute_rol(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuErr
 // ROR:    C -> [76543210] -> C
 // status: N. ...ZC
 // affects either accumulator or memory (read/modify/write)
-pub fn execute_ror(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_ror<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     read_modify_write(mode, cpu, |operand, old_carry| {
         let carry_mask = if old_carry { 0x80 } else { 0x00 };
         let new_carry = operand & 0x01 != 0;
@@ -148,15 +237,15 @@ pub fn execute_ror(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuErr
 // DEC: Decrement memory by one
 // M - 1 -> M
 // status: N. ...Z.
-pub fn execute_dec(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
-    read_modify_write(mode, cpu, |operand, _| (operand.wrapping_sub(1), false))?;
+pub fn execute_dec<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    read_modify_write(mode, cpu, |operand, carry| (operand.wrapping_sub(1), carry))?;
     Ok(())
 }
 
 // DEX: Decrement index X by one
 // X - 1 -> X
 // status: N. ...Z.
-pub fn execute_dex(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_dex<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     if mode != AddressingMode::Implied {
         return Err(CpuError::InvalidAddressingMode);
     }
@@ -168,7 +257,7 @@ pub fn execute_dex(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuErr
 // DEY: Decrement index Y by one
 // Y - 1 -> Y
 // status: N. ...Z.
-pub fn execute_dey(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_dey<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     if mode != AddressingMode::Implied {
         return Err(CpuError::InvalidAddressingMode);
     }
@@ -180,15 +269,15 @@ pub fn execute_dey(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuErr
 // INC: Increment memory by one
 // M + 1 -> M
 // status: N. ...Z.
-pub fn execute_inc(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
-    read_modify_write(mode, cpu, |operand, _| (operand.wrapping_add(1), false))?;
+pub fn execute_inc<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    read_modify_write(mode, cpu, |operand, carry| (operand.wrapping_add(1), carry))?;
     Ok(())
 }
 
 // INX: Increment index X by one
 // X + 1 -> X
 // status: N. ...Z.
-pub fn execute_inx(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_inx<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     if mode != AddressingMode::Implied {
         return Err(CpuError::InvalidAddressingMode);
     }
@@ -200,7 +289,7 @@ pub fn execute_inx(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuErr
 // INY: Increment index Y by one
 // Y + 1 -> Y
 // status: N. ...Z.
-pub fn execute_iny(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_iny<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     if mode != AddressingMode::Implied {
         return Err(CpuError::InvalidAddressingMode);
     }
@@ -209,12 +298,13 @@ pub fn execute_iny(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuErr
     Ok(())
 }
 
-// function to handle the divergent accumulator vs in-memory read prolog and write sequel
-fn read_modify_write(
+// function to handle the divergent accumulator vs in-memory read prolog and write sequel;
+// returns the modified value
+pub fn read_modify_write<B: Memory>(
     mode: AddressingMode,
-    cpu: &mut CpuImpl,
+    cpu: &mut CpuImpl<B>,
     f: fn(u8, bool) -> (u8, bool),
-) -> Result<(), CpuError> {
+) -> Result<u8, CpuError> {
     // determine read source for operand:
     let (operand, address) = if mode == AddressingMode::Accumulator {
         (cpu.accumulator, None)
@@ -235,12 +325,13 @@ fn read_modify_write(
         memory_write_tolerate_readonly(address.unwrap(), result, cpu)?;
     }
 
-    Ok(())
+    Ok(result)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::address_bus::AddressBus;
 
     const ZERO_PAGE_ADDR: u16 = 0x00E0;
     const NEXT_PC: u16 = 0x0200;
@@ -316,9 +407,9 @@ mod tests {
         cpu.accumulator = 4;
         execute_sbc(AddressingMode::Immediate, &mut cpu)?;
         assert_eq!(cpu.accumulator, 0xFB); // -5
-        assert!(!cpu.status.carry()); //   carry cleared -> borrow
-        assert!(cpu.status.overflow()); // overflow !!
-        assert!(cpu.status.negative()); // negative result
+        assert!(!cpu.status.carry()); //    carry cleared -> borrow
+        assert!(!cpu.status.overflow()); // no overflow, -5 is a valid signed byte
+        assert!(cpu.status.negative()); //  negative result
 
         // calculate result with overflow
         let mut cpu = setup_cpu(150, true)?;
@@ -328,6 +419,145 @@ mod tests {
         assert!(!cpu.status.carry()); //    carry set
         assert!(!cpu.status.overflow()); // no overflow
         assert!(!cpu.status.negative()); // positive result
+
+        // calculate result with signed overflow: -128 - 1 = signed 127
+        let mut cpu = setup_cpu(1, true)?;
+        cpu.accumulator = 0x80;
+        execute_sbc(AddressingMode::Immediate, &mut cpu)?;
+        assert_eq!(cpu.accumulator, 0x7F);
+        assert!(cpu.status.carry()); //     no borrow
+        assert!(cpu.status.overflow()); //  overflow !!
+        assert!(!cpu.status.negative()); // positive result
+
+        // cleared carry borrows one: 5 - 3 - 1 = 1
+        let mut cpu = setup_cpu(3, false)?;
+        cpu.accumulator = 5;
+        execute_sbc(AddressingMode::Immediate, &mut cpu)?;
+        assert_eq!(cpu.accumulator, 1);
+        assert!(cpu.status.carry());
+        Ok(())
+    }
+
+    fn setup_cpu_decimal(operand: u8, carry: bool, accumulator: u8) -> Result<CpuImpl, CpuError> {
+        let mut cpu = setup_cpu(operand, carry)?;
+        cpu.status.set_decimal_mode(true);
+        cpu.accumulator = accumulator;
+        Ok(cpu)
+    }
+
+    #[test]
+    fn add_with_carry_decimal() -> Result<(), CpuError> {
+        // 12 + 34 = 46
+        let mut cpu = setup_cpu_decimal(0x34, false, 0x12)?;
+        execute_adc(AddressingMode::Immediate, &mut cpu)?;
+        assert_eq!(cpu.accumulator, 0x46);
+        assert!(!cpu.status.carry());
+        assert!(!cpu.status.zero());
+
+        // 58 + 46 + 1 = 105
+        let mut cpu = setup_cpu_decimal(0x46, true, 0x58)?;
+        execute_adc(AddressingMode::Immediate, &mut cpu)?;
+        assert_eq!(cpu.accumulator, 0x05);
+        assert!(cpu.status.carry());
+
+        // 81 + 92 = 173, V is set from the intermediate binary result
+        let mut cpu = setup_cpu_decimal(0x92, false, 0x81)?;
+        execute_adc(AddressingMode::Immediate, &mut cpu)?;
+        assert_eq!(cpu.accumulator, 0x73);
+        assert!(cpu.status.carry());
+        assert!(cpu.status.overflow());
+        assert!(!cpu.status.negative());
+
+        // 99 + 1 = 100: NMOS quirk, Z is set from binary sum, N from intermediate result
+        let mut cpu = setup_cpu_decimal(0x01, false, 0x99)?;
+        execute_adc(AddressingMode::Immediate, &mut cpu)?;
+        assert_eq!(cpu.accumulator, 0x00);
+        assert!(cpu.status.carry());
+        assert!(!cpu.status.zero());
+        assert!(cpu.status.negative());
+
+        // 79 + 00 + 1 = 80: V is set, since the intermediate result 0x80 changed sign
+        let mut cpu = setup_cpu_decimal(0x00, true, 0x79)?;
+        execute_adc(AddressingMode::Immediate, &mut cpu)?;
+        assert_eq!(cpu.accumulator, 0x80);
+        assert!(!cpu.status.carry());
+        assert!(cpu.status.overflow());
+        assert!(cpu.status.negative());
+        Ok(())
+    }
+
+    #[test]
+    fn subtract_with_carry_decimal() -> Result<(), CpuError> {
+        // 46 - 12 = 34
+        let mut cpu = setup_cpu_decimal(0x12, true, 0x46)?;
+        execute_sbc(AddressingMode::Immediate, &mut cpu)?;
+        assert_eq!(cpu.accumulator, 0x34);
+        assert!(cpu.status.carry());
+
+        // 40 - 13 = 27
+        let mut cpu = setup_cpu_decimal(0x13, true, 0x40)?;
+        execute_sbc(AddressingMode::Immediate, &mut cpu)?;
+        assert_eq!(cpu.accumulator, 0x27);
+        assert!(cpu.status.carry());
+
+        // 32 - 2 - 1 = 29
+        let mut cpu = setup_cpu_decimal(0x02, false, 0x32)?;
+        execute_sbc(AddressingMode::Immediate, &mut cpu)?;
+        assert_eq!(cpu.accumulator, 0x29);
+        assert!(cpu.status.carry());
+
+        // 12 - 21 = -9 -> 91 with borrow
+        let mut cpu = setup_cpu_decimal(0x21, true, 0x12)?;
+        execute_sbc(AddressingMode::Immediate, &mut cpu)?;
+        assert_eq!(cpu.accumulator, 0x91);
+        assert!(!cpu.status.carry());
+        assert!(cpu.status.negative());
+
+        // 0 - 1 = 99 with borrow
+        let mut cpu = setup_cpu_decimal(0x01, true, 0x00)?;
+        execute_sbc(AddressingMode::Immediate, &mut cpu)?;
+        assert_eq!(cpu.accumulator, 0x99);
+        assert!(!cpu.status.carry());
+
+        // 21 - 21 = 0: Z and C from binary result
+        let mut cpu = setup_cpu_decimal(0x21, true, 0x21)?;
+        execute_sbc(AddressingMode::Immediate, &mut cpu)?;
+        assert_eq!(cpu.accumulator, 0x00);
+        assert!(cpu.status.carry());
+        assert!(cpu.status.zero());
+        Ok(())
+    }
+
+    fn setup_cpu_decimal_wdc65c02(
+        operand: u8,
+        carry: bool,
+        accumulator: u8,
+    ) -> Result<CpuImpl, CpuError> {
+        let mut cpu = CpuImpl::with_cpu_type(CpuType::WDC65C02);
+        cpu.memory.write(0x0000, operand)?;
+        cpu.address_bus.set_pc(0x0000)?;
+        cpu.status.set_carry(carry);
+        cpu.status.set_decimal_mode(true);
+        cpu.accumulator = accumulator;
+        Ok(cpu)
+    }
+
+    #[test]
+    fn add_subtract_decimal_wdc65c02() -> Result<(), CpuError> {
+        // 99 + 1 = 100: N and Z are valid on the 65C02
+        let mut cpu = setup_cpu_decimal_wdc65c02(0x01, false, 0x99)?;
+        execute_adc(AddressingMode::Immediate, &mut cpu)?;
+        assert_eq!(cpu.accumulator, 0x00);
+        assert!(cpu.status.carry());
+        assert!(cpu.status.zero());
+        assert!(!cpu.status.negative());
+
+        // 0 - 30 = 70 with borrow: N from BCD result, not from binary result 0xD0
+        let mut cpu = setup_cpu_decimal_wdc65c02(0x30, true, 0x00)?;
+        execute_sbc(AddressingMode::Immediate, &mut cpu)?;
+        assert_eq!(cpu.accumulator, 0x70);
+        assert!(!cpu.status.carry());
+        assert!(!cpu.status.negative());
         Ok(())
     }
 
@@ -557,6 +787,33 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn inc_dec_keep_carry() -> Result<(), CpuError> {
+        let mut cpu = setup_cpu_zero_page(0x42)?;
+        cpu.status.set_carry(true);
+        execute_inc(AddressingMode::ZeroPage, &mut cpu)?;
+        assert!(cpu.status.carry());
+        cpu.address_bus.set_pc(NEXT_PC)?;
+        execute_dec(AddressingMode::ZeroPage, &mut cpu)?;
+        assert_eq!(cpu.memory.read(ZERO_PAGE_ADDR)?, 0x42);
+        assert!(cpu.status.carry());
+
+        // INC A and DEC A on the 65C02:
+        let mut cpu = CpuImpl::with_cpu_type(CpuType::WDC65C02);
+        cpu.load_program(NEXT_PC, &[0x1a, 0x3a, 0x3a], false)?;
+        cpu.set_pc(NEXT_PC)?;
+        cpu.accumulator = 0xFF;
+        cpu.status.set_carry(true);
+        cpu.step()?;
+        assert_eq!(cpu.accumulator, 0x00);
+        assert!(cpu.status.carry());
+        cpu.step()?;
+        cpu.step()?;
+        assert_eq!(cpu.accumulator, 0xFE);
+        assert!(cpu.status.carry());
+        Ok(())
+    }
+
     #[test]
     fn dex() -> Result<(), CpuError> {
         let mut cpu = create_cpu()?;
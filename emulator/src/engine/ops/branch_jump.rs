@@ -1,83 +1,82 @@
 use crate::CpuError;
-use crate::cpu_impl::{AddressingMode, CpuImpl};
+use crate::address_bus::AddressBus;
+use crate::cpu_impl::{AddressingMode, CpuImpl, is_page_crossed};
+use crate::memory::Memory;
+use crate::stack_pointer::StackPointer;
 
 // Branch operations:
 
 // BCC:    Branch on Carry clear (C = 0)
 // status: n/c
-pub fn execute_bcc(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
-    let effective_address = cpu.get_effective_address(mode)?;
-    if !cpu.status.carry() {
-        cpu.address_bus.set_pc(effective_address)?;
-    }
-    Ok(())
+pub fn execute_bcc<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    let is_taken = !cpu.status.carry();
+    branch_if(is_taken, mode, cpu)
 }
 
 // BCS:    Branch on Carry set (C = 1)
 // status: n/c
-pub fn execute_bcs(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
-    let effective_address = cpu.get_effective_address(mode)?;
-    if cpu.status.carry() {
-        cpu.address_bus.set_pc(effective_address)?;
-    }
-    Ok(())
+pub fn execute_bcs<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    let is_taken = cpu.status.carry();
+    branch_if(is_taken, mode, cpu)
 }
 
 // BEQ:    Branch on result zero (Z = 1)
 // status: n/c
-pub fn execute_beq(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
-    let effective_address = cpu.get_effective_address(mode)?;
-    if cpu.status.zero() {
-        cpu.address_bus.set_pc(effective_address)?;
-    }
-    Ok(())
+pub fn execute_beq<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    let is_taken = cpu.status.zero();
+    branch_if(is_taken, mode, cpu)
 }
 
 // BMI:    Branch on result minus (N = 1)
 // status: n/c
-pub fn execute_bmi(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
-    let effective_address = cpu.get_effective_address(mode)?;
-    if cpu.status.negative() {
-        cpu.address_bus.set_pc(effective_address)?;
-    }
-    Ok(())
+pub fn execute_bmi<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    let is_taken = cpu.status.negative();
+    branch_if(is_taken, mode, cpu)
 }
 
 // BNE:    Branch on result non zero (Z = 0)
 // status: n/c
-pub fn execute_bne(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
-    let effective_address = cpu.get_effective_address(mode)?;
-    if !cpu.status.zero() {
-        cpu.address_bus.set_pc(effective_address)?;
-    }
-    Ok(())
+pub fn execute_bne<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    let is_taken = !cpu.status.zero();
+    branch_if(is_taken, mode, cpu)
 }
 
 // BPL:    Branch on result plus (N = 0)
 // status: n/c
-pub fn execute_bpl(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
-    let effective_address = cpu.get_effective_address(mode)?;
-    if !cpu.status.negative() {
-        cpu.address_bus.set_pc(effective_address)?;
-    }
-    Ok(())
+pub fn execute_bpl<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    let is_taken = !cpu.status.negative();
+    branch_if(is_taken, mode, cpu)
 }
 
 // BVC:    Branch on Overflow clear (V = 1)
 // status: n/c
-pub fn execute_bvc(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
-    let effective_address = cpu.get_effective_address(mode)?;
-    if !cpu.status.overflow() {
-        cpu.address_bus.set_pc(effective_address)?;
-    }
-    Ok(())
+pub fn execute_bvc<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    let is_taken = !cpu.status.overflow();
+    branch_if(is_taken, mode, cpu)
 }
 
 // BVS:    Branch on Overflow set (V = 1)
 // status: n/c
-pub fn execute_bvs(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_bvs<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    let is_taken = cpu.status.overflow();
+    branch_if(is_taken, mode, cpu)
+}
+
+// a taken branch takes an extra cycle, and another one if the branch target is on a different page
+pub fn branch_if<B: Memory>(
+    is_taken: bool,
+    mode: AddressingMode,
+    cpu: &mut CpuImpl<B>,
+) -> Result<(), CpuError> {
     let effective_address = cpu.get_effective_address(mode)?;
-    if cpu.status.overflow() {
+    if is_taken {
+        let next_pc = cpu.address_bus.get_pc();
+        let cycles = if is_page_crossed(next_pc, effective_address) {
+            2
+        } else {
+            1
+        };
+        cpu.add_extra_cycles(cycles);
         cpu.address_bus.set_pc(effective_address)?;
     }
     Ok(())
@@ -89,7 +88,7 @@ pub fn execute_bvs(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuErr
 // (PC + 1) -> PCL
 // (PC + 2) -> PCH
 // status: n/c
-pub fn execute_jmp(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_jmp<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     let effective_address = cpu.get_effective_address(mode)?;
     cpu.address_bus.set_pc(effective_address)?;
     Ok(())
@@ -100,33 +99,34 @@ pub fn execute_jmp(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuErr
 // (PC + 1) -> PCL
 // (PC + 2) -> PCH
 // status: n/c
-pub fn execute_jsr(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_jsr<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     let effective_address = cpu.get_effective_address(mode)?;
-    let return_address = cpu.address_bus.get_pc() - 1;
+    let return_address = cpu.address_bus.get_pc().wrapping_sub(1);
     // see 6502 programming manual, section 8,1 pg 106:
     // "...PC address which points to the last byte of the JSR instruction onto the stack..."
     cpu.address_bus.set_pc(effective_address)?;
-    cpu.stack.push_word(cpu.memory.as_mut(), return_address)?;
+    cpu.stack.push_word(&mut cpu.memory, return_address)?;
     Ok(())
 }
 
 // RTS:    Return from sub routine
 // pull PC, add 1, put result in PC
 // status: n/c
-pub fn execute_rts(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_rts<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     if mode != AddressingMode::Implied {
         return Err(CpuError::InvalidAddressingMode);
     }
-    let pc = cpu.stack.pop_word(cpu.memory.as_mut())?;
+    let pc = cpu.stack.pop_word(&cpu.memory)?;
     // see comment in execute_jsr:
     // now move the popped return address past the last byte of the JSR triple byte instruction
-    cpu.address_bus.set_pc(pc + 1)?;
+    cpu.address_bus.set_pc(pc.wrapping_add(1))?;
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::memory::Memory;
     use crate::status_register::StatusRegister;
 
     #[test]
@@ -205,20 +205,59 @@ mod tests {
         execute_jsr(AddressingMode::Absolute, &mut cpu)?;
         assert_eq!(cpu.address_bus.get_pc(), 0x5432);
         // JSR pushes address of last byte of 3 byte instruction to stack:
-        assert_eq!(cpu.stack.pop_word(cpu.memory.as_mut())?, 0x0124);
+        assert_eq!(cpu.stack.pop_word(&cpu.memory)?, 0x0124);
         Ok(())
     }
 
     #[test]
     fn rts() -> Result<(), CpuError> {
         let mut cpu = CpuImpl::default();
-        cpu.stack.push_word(cpu.memory.as_mut(), 0x1234)?;
+        cpu.stack.push_word(&mut cpu.memory, 0x1234)?;
         execute_rts(AddressingMode::Implied, &mut cpu)?;
         // see execute_jsr: pushed PC is one byte short of the actual return address
         assert_eq!(cpu.address_bus.get_pc(), 0x1235);
         Ok(())
     }
 
+    #[test]
+    fn branch_wraps_around_top_of_memory() -> Result<(), CpuError> {
+        // BNE +4 at 0xFFFC:
+        let mut cpu = CpuImpl::default();
+        cpu.memory.write(0xFFFD, 0x04)?;
+        cpu.address_bus.set_pc(0xFFFD)?;
+        execute_bne(AddressingMode::Relative, &mut cpu)?;
+        assert_eq!(cpu.address_bus.get_pc(), 0x0002);
+
+        // BNE -4 at 0x0000:
+        let mut cpu = CpuImpl::default();
+        cpu.memory.write(0x0001, (-4i8) as u8)?;
+        cpu.address_bus.set_pc(0x0001)?;
+        execute_bne(AddressingMode::Relative, &mut cpu)?;
+        assert_eq!(cpu.address_bus.get_pc(), 0xFFFE);
+        Ok(())
+    }
+
+    #[test]
+    fn jsr_wraps_at_top_of_memory() -> Result<(), CpuError> {
+        // JSR $0300 at 0xFFFE, its last byte is at 0x0000:
+        let mut cpu = CpuImpl::default();
+        cpu.memory.write_word(0xFFFF, 0x0300)?;
+        cpu.address_bus.set_pc(0xFFFF)?;
+        execute_jsr(AddressingMode::Absolute, &mut cpu)?;
+        assert_eq!(cpu.address_bus.get_pc(), 0x0300);
+        assert_eq!(cpu.stack.pop_word(&cpu.memory)?, 0x0000);
+        Ok(())
+    }
+
+    #[test]
+    fn rts_wraps_to_zero_page() -> Result<(), CpuError> {
+        let mut cpu = CpuImpl::default();
+        cpu.stack.push_word(&mut cpu.memory, 0xFFFF)?;
+        execute_rts(AddressingMode::Implied, &mut cpu)?;
+        assert_eq!(cpu.address_bus.get_pc(), 0x0000);
+        Ok(())
+    }
+
     const NEXT_PC: u16 = 0x0123;
 
     fn create_cpu_branch_test(relative_offset: u8) -> Result<CpuImpl, CpuError> {
@@ -1,6 +1,7 @@
-use crate::CpuError;
 use crate::cpu_impl::{AddressingMode, CpuImpl};
 use crate::engine::ops::alu::subtract_with_carry;
+use crate::memory::Memory;
+use crate::{CpuError, CpuType};
 
 // Set/clear status flag operations:
 
@@ -8,7 +9,7 @@ use crate::engine::ops::alu::subtract_with_carry;
 // 0 -> C
 //         76543210
 // status: .. ....c
-pub fn execute_clc(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_clc<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     validate_mode(mode)?;
     cpu.status.set_carry(false);
     Ok(())
@@ -18,7 +19,7 @@ pub fn execute_clc(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuErr
 // 0 -> D
 //         76543210
 // status: .. .d...
-pub fn execute_cld(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_cld<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     validate_mode(mode)?;
     cpu.status.set_decimal_mode(false);
     Ok(())
@@ -28,7 +29,7 @@ pub fn execute_cld(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuErr
 // 0 -> I
 //         76543210
 // status: .. ..i..
-pub fn execute_cli(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_cli<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     validate_mode(mode)?;
     cpu.status.set_interrupt_disable(false);
     Ok(())
@@ -38,7 +39,7 @@ pub fn execute_cli(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuErr
 // 0 -> C
 //         76543210
 // status: .v .....
-pub fn execute_clv(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_clv<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     validate_mode(mode)?;
     cpu.status.set_overflow(false);
     Ok(())
@@ -50,7 +51,7 @@ pub fn execute_clv(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuErr
 // 1 -> C
 //         76543210
 // status: .. ....C
-pub fn execute_sec(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_sec<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     validate_mode(mode)?;
     cpu.status.set_carry(true);
     Ok(())
@@ -60,7 +61,7 @@ pub fn execute_sec(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuErr
 // 1 -> D
 //         76543210
 // status: .. .D...
-pub fn execute_sed(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_sed<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     validate_mode(mode)?;
     cpu.status.set_decimal_mode(true);
     Ok(())
@@ -70,7 +71,7 @@ pub fn execute_sed(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuErr
 // 1 -> I
 //         76543210
 // status: .. ..I..
-pub fn execute_sei(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_sei<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     validate_mode(mode)?;
     cpu.status.set_interrupt_disable(true);
     Ok(())
@@ -89,11 +90,15 @@ fn validate_mode(mode: AddressingMode) -> Result<(), CpuError> {
 // A AND M, M7 -> N, M6 -> V
 //         76543210
 // status: NV ...Z.
-pub fn execute_bit(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_bit<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     let operand = cpu.get_effective_operand(mode)?;
+    cpu.status.set_zero((cpu.accumulator & operand) == 0);
+    // 65C02 BIT #imm only affects Z
+    if mode == AddressingMode::Immediate && cpu.get_cpu_type() == CpuType::WDC65C02 {
+        return Ok(());
+    }
     cpu.status.set_negative(operand & 0b1000_0000 != 0);
     cpu.status.set_overflow(operand & 0b0100_0000 != 0);
-    cpu.status.set_zero((cpu.accumulator & operand) == 0);
     Ok(())
 }
 
@@ -106,7 +111,7 @@ pub fn execute_bit(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuErr
 // A - M -> C,Z,N
 //         76543210
 // status: N. ...ZC
-pub fn execute_cmp(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_cmp<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     compare_register(cpu.accumulator, mode, cpu)?;
     Ok(())
 }
@@ -115,7 +120,7 @@ pub fn execute_cmp(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuErr
 // X - M -> C,Z,N
 //         76543210
 // status: N. ...ZC
-pub fn execute_cpx(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_cpx<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     compare_register(cpu.index_x, mode, cpu)?;
     Ok(())
 }
@@ -124,12 +129,16 @@ pub fn execute_cpx(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuErr
 // X - M -> C,Z,N
 //         76543210
 // status: N. ...ZC
-pub fn execute_cpy(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_cpy<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     compare_register(cpu.index_y, mode, cpu)?;
     Ok(())
 }
 
-fn compare_register(register: u8, mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+fn compare_register<B: Memory>(
+    register: u8,
+    mode: AddressingMode,
+    cpu: &mut CpuImpl<B>,
+) -> Result<(), CpuError> {
     let operand = cpu.get_effective_operand(mode)?;
     let (result, carry, _) = subtract_with_carry(register, operand, true);
     cpu.status.update_from(result);
@@ -139,6 +148,7 @@ fn compare_register(register: u8, mode: AddressingMode, cpu: &mut CpuImpl) -> Re
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::address_bus::AddressBus;
 
     const NEXT_PC: u16 = 0x1234;
 
@@ -346,4 +356,17 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn bit_immediate_wdc65c02() -> Result<(), CpuError> {
+        let mut cpu = CpuImpl::with_cpu_type(CpuType::WDC65C02);
+        cpu.memory.write(NEXT_PC, 0b1100_0000)?;
+        cpu.address_bus.set_pc(NEXT_PC)?;
+        cpu.accumulator = 0b0011_1111;
+        execute_bit(AddressingMode::Immediate, &mut cpu)?;
+        assert!(cpu.status.zero());
+        assert!(!cpu.status.overflow());
+        assert!(!cpu.status.negative());
+        Ok(())
+    }
 }
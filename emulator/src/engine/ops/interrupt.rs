@@ -1,36 +1,43 @@
-use crate::CpuError;
+use crate::address_bus::AddressBus;
 use crate::address_bus::SystemVector;
 use crate::cpu_impl::{AddressingMode, CpuImpl};
+use crate::memory::Memory;
+use crate::stack_pointer::StackPointer;
+use crate::{CpuError, CpuType};
 
 // BRK:    Force break
-// status: NV ...ZC
-pub fn execute_brk(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+// status: I set
+pub fn execute_brk<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     if mode != AddressingMode::Implied {
         return Err(CpuError::InvalidAddressingMode);
     }
     // see 6502 prog manual section 9.11 pg 144:
     // the pushed PC skips the byte after the BRK instruction
     let pc = cpu.address_bus.get_pc().wrapping_add(1);
-    cpu.stack.push_word(cpu.memory.as_mut(), pc)?;
+    cpu.stack.push_word(&mut cpu.memory, pc)?;
     let status = cpu.status.get_status();
     // set Break flag on pushed status value only
-    cpu.stack
-        .push_byte(cpu.memory.as_mut(), status | 0b0001_0000)?;
+    cpu.stack.push_byte(&mut cpu.memory, status | 0b0001_0000)?;
+    cpu.status.set_interrupt_disable(true);
+    if cpu.get_cpu_type() == CpuType::WDC65C02 {
+        cpu.status.set_decimal_mode(false);
+    }
 
-    // continue via IRQ vector: 0xFFFE
-    cpu.address_bus.set_pc(SystemVector::IRQ as u16)?;
+    // continue at the address read from the IRQ vector at 0xFFFE:
+    let address = cpu.memory.read_word(SystemVector::IRQ as u16)?;
+    cpu.address_bus.set_pc(address)?;
     Ok(())
 }
 
 // RTI:    Return from interrupt
 // pull PC, add 1, put result in PC
 // status: NV bD.ZC
-pub fn execute_rti(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_rti<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     if mode != AddressingMode::Implied {
         return Err(CpuError::InvalidAddressingMode);
     }
-    let status = cpu.stack.pop_byte(cpu.memory.as_mut())?;
-    let pc = cpu.stack.pop_word(cpu.memory.as_mut())?;
+    let status = cpu.stack.pop_byte(&cpu.memory)?;
+    let pc = cpu.stack.pop_word(&cpu.memory)?;
     cpu.status.set_status(status & 0xCF); // ignore Break and undefined flags
     cpu.address_bus.set_pc(pc)?;
     Ok(())
@@ -42,23 +49,39 @@ mod tests {
 
     #[test]
     fn brk() -> Result<(), CpuError> {
-        // assert_eq!(cpu.address_bus.get_pc(), 0xFFFE);
         let mut cpu = CpuImpl::default();
+        cpu.memory.write_word(SystemVector::IRQ as u16, 0x4567)?;
         cpu.status.set_carry(true);
         cpu.status.set_negative(true);
         cpu.address_bus.set_pc(0x0123)?;
 
         execute_brk(AddressingMode::Implied, &mut cpu)?;
-        assert_eq!(cpu.stack.pop_byte(cpu.memory.as_mut())?, 0b1001_0001);
-        assert_eq!(cpu.stack.pop_word(cpu.memory.as_mut())?, 0x0124);
+        assert_eq!(cpu.address_bus.get_pc(), 0x4567);
+        assert!(cpu.status.interrupt_disable());
+        // pushed status has I still cleared:
+        assert_eq!(cpu.stack.pop_byte(&cpu.memory)?, 0b1001_0001);
+        assert_eq!(cpu.stack.pop_word(&cpu.memory)?, 0x0124);
+        Ok(())
+    }
+
+    #[test]
+    fn brk_wdc65c02_clears_decimal_mode() -> Result<(), CpuError> {
+        let mut cpu = CpuImpl::with_cpu_type(CpuType::WDC65C02);
+        cpu.status.set_decimal_mode(true);
+        cpu.address_bus.set_pc(0x0123)?;
+
+        execute_brk(AddressingMode::Implied, &mut cpu)?;
+        assert!(!cpu.status.decimal_mode());
+        // pushed status still has D set:
+        assert_eq!(cpu.stack.pop_byte(&cpu.memory)?, 0b0001_1000);
         Ok(())
     }
 
     #[test]
     fn rti() -> Result<(), CpuError> {
         let mut cpu = CpuImpl::default();
-        cpu.stack.push_word(cpu.memory.as_mut(), 0x1234)?;
-        cpu.stack.push_byte(cpu.memory.as_mut(), 0b1101_1011)?;
+        cpu.stack.push_word(&mut cpu.memory, 0x1234)?;
+        cpu.stack.push_byte(&mut cpu.memory, 0b1101_1011)?;
 
         execute_rti(AddressingMode::Implied, &mut cpu)?;
 
@@ -4,14 +4,47 @@ pub mod flag_compare;
 pub mod interrupt;
 pub mod stack;
 pub mod transfer;
+pub mod undocumented;
+pub mod wdc65c02;
 
 // good overview and reference to 6502 instruction opcodes:
 // https://www.masswerk.at/6502/6502_instruction_set.html
 
-use crate::CpuError;
+use crate::address_bus::AddressBus;
 use crate::cpu_impl::{AddressingMode, CpuImpl};
+use crate::engine::decoder;
+use crate::engine::ops::interrupt::execute_brk;
+use crate::engine::ops::undocumented::execute_jam;
+use crate::memory::Memory;
+use crate::{CpuError, CpuType, IllegalOpcodePolicy};
 
 // special codes:
-pub fn execute_nop(_: AddressingMode, _: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_nop<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    // undocumented NOPs with an operand still read from memory
+    if mode != AddressingMode::Implied {
+        cpu.get_effective_operand(mode)?;
+    }
     Ok(())
 }
+
+// an opcode the CPU type does not define, executed according to the CPU's policy:
+pub fn execute_illegal<B: Memory>(opcode: u8, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    match cpu.get_illegal_opcode_policy() {
+        IllegalOpcodePolicy::Error => {
+            // leave the PC at the illegal opcode:
+            let pc = cpu.address_bus.get_pc().wrapping_sub(1);
+            cpu.address_bus.set_pc(pc)?;
+            Err(CpuError::InvalidOpcode(opcode))
+        }
+        IllegalOpcodePolicy::Nop => {
+            // the undocumented NMOS table has the length and cycles of every opcode:
+            let decoded = decoder::decode(opcode, CpuType::MOS6502Undocumented);
+            execute_nop(decoded.mode, cpu)?;
+            let page_cross_cycles = (cpu.is_page_crossed() && decoded.page_cross_penalty) as u8;
+            cpu.add_extra_cycles(decoded.cycles + page_cross_cycles);
+            Ok(())
+        }
+        IllegalOpcodePolicy::Jam => execute_jam(AddressingMode::Implied, cpu),
+        IllegalOpcodePolicy::Brk => execute_brk(AddressingMode::Implied, cpu),
+    }
+}
@@ -1,37 +1,39 @@
 use crate::CpuError;
 use crate::cpu_impl::{AddressingMode, CpuImpl};
+use crate::memory::Memory;
+use crate::stack_pointer::StackPointer;
 
 // PHA: Push accumulator
 //  A -> SP
 // status: n/c
-pub fn execute_pha(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_pha<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     if mode != AddressingMode::Implied {
         return Err(CpuError::InvalidAddressingMode);
     }
-    cpu.stack.push_byte(cpu.memory.as_mut(), cpu.accumulator)?;
+    cpu.stack.push_byte(&mut cpu.memory, cpu.accumulator)?;
     Ok(())
 }
 
 // PHP: Push status register
 //  S -> SP
 // status: n/c
-pub fn execute_php(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_php<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     if mode != AddressingMode::Implied {
         return Err(CpuError::InvalidAddressingMode);
     }
     cpu.stack
-        .push_byte(cpu.memory.as_mut(), cpu.status.get_status() & 0xCF)?;
+        .push_byte(&mut cpu.memory, cpu.status.get_status() & 0xCF)?;
     Ok(())
 }
 
 // PLA: Pull accumulator
 //  SP -> A
 // status: N. ...Z.
-pub fn execute_pla(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_pla<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     if mode != AddressingMode::Implied {
         return Err(CpuError::InvalidAddressingMode);
     }
-    cpu.accumulator = cpu.stack.pop_byte(cpu.memory.as_mut())?;
+    cpu.accumulator = cpu.stack.pop_byte(&cpu.memory)?;
     cpu.status.update_from(cpu.accumulator);
     Ok(())
 }
@@ -39,18 +41,18 @@ pub fn execute_pla(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuErr
 // PLP: Pull status register
 //  SP -> P
 // status: NV .DIZC
-pub fn execute_plp(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_plp<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     if mode != AddressingMode::Implied {
         return Err(CpuError::InvalidAddressingMode);
     }
-    let status = cpu.stack.pop_byte(cpu.memory.as_mut())?;
+    let status = cpu.stack.pop_byte(&cpu.memory)?;
     cpu.status.set_status(status & 0xCF); // ignore Break and undefined flags
     Ok(())
 }
 
 // TSX:    SP -> X
 // status: N. ...Z.
-pub fn execute_tsx(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_tsx<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     if mode != AddressingMode::Implied {
         return Err(CpuError::InvalidAddressingMode);
     }
@@ -61,7 +63,7 @@ pub fn execute_tsx(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuErr
 
 // TXS:    X -> SP
 // status: N. ...Z.
-pub fn execute_txs(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_txs<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     if mode != AddressingMode::Implied {
         return Err(CpuError::InvalidAddressingMode);
     }
@@ -73,6 +75,7 @@ pub fn execute_txs(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuErr
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::address_bus::AddressBus;
 
     const ZERO_PAGE_ADDR: u16 = 0x00E0;
     const NEXT_PC: u16 = 0x0300;
@@ -112,7 +115,7 @@ mod tests {
         cpu.accumulator = 0x42;
         let org_status = cpu.status.get_status();
         execute_pha(AddressingMode::Implied, &mut cpu).unwrap();
-        assert_eq!(cpu.stack.pop_byte(cpu.memory.as_mut())?, 0x42);
+        assert_eq!(cpu.stack.pop_byte(&cpu.memory)?, 0x42);
         assert_eq!(cpu.status.get_status(), org_status);
         Ok(())
     }
@@ -128,14 +131,14 @@ mod tests {
         let org_status = cpu.status.get_status();
         execute_php(AddressingMode::Implied, &mut cpu).unwrap();
         // break and undefined flags are ignored
-        assert_eq!(cpu.stack.pop_byte(cpu.memory.as_mut())?, org_status & 0xCF);
+        assert_eq!(cpu.stack.pop_byte(&cpu.memory)?, org_status & 0xCF);
         Ok(())
     }
 
     #[test]
     fn pla() -> Result<(), CpuError> {
         let mut cpu = create_cpu();
-        cpu.stack.push_byte(cpu.memory.as_mut(), 0x42)?;
+        cpu.stack.push_byte(&mut cpu.memory, 0x42)?;
         cpu.status.set_negative(true);
         cpu.status.set_zero(true);
         execute_pla(AddressingMode::Implied, &mut cpu).unwrap();
@@ -149,7 +152,7 @@ mod tests {
     fn plp() -> Result<(), CpuError> {
         let mut cpu = create_cpu();
         let pushed_status = 0b0100_1100;
-        cpu.stack.push_byte(cpu.memory.as_mut(), pushed_status)?;
+        cpu.stack.push_byte(&mut cpu.memory, pushed_status)?;
         cpu.status.set_negative(true);
         cpu.status.set_zero(true);
         cpu.status.set_break_command(true);
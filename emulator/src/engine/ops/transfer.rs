@@ -1,11 +1,12 @@
 use crate::CpuError;
 use crate::cpu_impl::{AddressingMode, CpuImpl};
+use crate::memory::Memory;
 
 // LDA/X/Y:
 
 // LDA:    M -> A
 // status: N. ...Z.
-pub fn execute_lda(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_lda<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     let value = cpu.get_effective_operand(mode)?;
     cpu.accumulator = value;
     cpu.status.update_from(value);
@@ -14,7 +15,7 @@ pub fn execute_lda(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuErr
 
 // LDX:    M -> X
 // status: N. ...Z.
-pub fn execute_ldx(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_ldx<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     let value = cpu.get_effective_operand(mode)?;
     cpu.index_x = value;
     cpu.status.update_from(value);
@@ -23,7 +24,7 @@ pub fn execute_ldx(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuErr
 
 // LDY:    M -> Y
 // status: N. ...Z.
-pub fn execute_ldy(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_ldy<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     let value = cpu.get_effective_operand(mode)?;
     cpu.index_y = value;
     cpu.status.update_from(value);
@@ -34,42 +35,51 @@ pub fn execute_ldy(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuErr
 
 // STA:    A -> M
 // status: N. ...Z.
-pub fn execute_sta(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_sta<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     let effective_address = cpu.get_effective_address(mode)?;
     memory_write_tolerate_readonly(effective_address, cpu.accumulator, cpu)
 }
 
 // STX:    X -> M
 // status: N. ...Z.
-pub fn execute_stx(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_stx<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     let effective_address = cpu.get_effective_address(mode)?;
     memory_write_tolerate_readonly(effective_address, cpu.index_x, cpu)
 }
 
 // STY:    Y -> M
 // status: N. ...Z.
-pub fn execute_sty(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_sty<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     let effective_address = cpu.get_effective_address(mode)?;
     memory_write_tolerate_readonly(effective_address, cpu.index_y, cpu)
 }
 
-pub fn memory_write_tolerate_readonly(
+pub fn memory_write_tolerate_readonly<B: Memory>(
     effective_address: u16,
     value: u8,
-    cpu: &mut CpuImpl,
+    cpu: &mut CpuImpl<B>,
 ) -> Result<(), CpuError> {
-    match cpu.memory.write(effective_address, value) {
-        Ok(_) => {}
-        Err(CpuError::ReadOnlyMemory) => {}
-        Err(e) => return Err(e),
-    }
+    write_tolerate_readonly(effective_address, value, cpu)?;
     cpu.status.update_from(value);
     Ok(())
 }
 
+// writes to ROM are silently ignored, like on the real hardware; status is not affected
+pub fn write_tolerate_readonly<B: Memory>(
+    effective_address: u16,
+    value: u8,
+    cpu: &mut CpuImpl<B>,
+) -> Result<(), CpuError> {
+    match cpu.memory.write(effective_address, value) {
+        Ok(_) => Ok(()),
+        Err(CpuError::ReadOnlyMemory) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
 // TAX:    A -> X
 // status: N. ...Z.
-pub fn execute_tax(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_tax<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     if mode != AddressingMode::Implied {
         return Err(CpuError::InvalidAddressingMode);
     }
@@ -80,7 +90,7 @@ pub fn execute_tax(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuErr
 
 // TAY:    A -> Y
 // status: N. ...Z.
-pub fn execute_tay(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_tay<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     if mode != AddressingMode::Implied {
         return Err(CpuError::InvalidAddressingMode);
     }
@@ -91,7 +101,7 @@ pub fn execute_tay(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuErr
 
 // TXA:    X -> A
 // status: N. ...Z.
-pub fn execute_txa(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_txa<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     if mode != AddressingMode::Implied {
         return Err(CpuError::InvalidAddressingMode);
     }
@@ -102,7 +112,7 @@ pub fn execute_txa(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuErr
 
 // TYA:    Y -> A
 // status: N. ...Z.
-pub fn execute_tya(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuError> {
+pub fn execute_tya<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
     if mode != AddressingMode::Implied {
         return Err(CpuError::InvalidAddressingMode);
     }
@@ -114,6 +124,7 @@ pub fn execute_tya(mode: AddressingMode, cpu: &mut CpuImpl) -> Result<(), CpuErr
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::address_bus::AddressBus;
 
     const ZERO_PAGE_ADDR: u16 = 0x00E0;
     const ABS_ADDR: u16 = 0xC0C0;
@@ -324,7 +335,7 @@ mod tests {
     #[test]
     fn test_sta_readonly_memory() -> Result<(), CpuError> {
         let mut cpu = setup_cpu_for_abs_store(ABS_ADDR);
-        cpu.memory.add_readonly(0xC000..0xFFFF)?;
+        cpu.memory.add_readonly(0xC000..=0xFFFE)?;
         cpu.accumulator = 0x42;
 
         assert_eq!(cpu.memory.read(ABS_ADDR)?, 0x00);
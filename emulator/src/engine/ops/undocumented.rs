@@ -0,0 +1,478 @@
+use crate::CpuError;
+use crate::address_bus::AddressBus;
+use crate::cpu_impl::{AddressingMode, CpuImpl};
+use crate::engine::ops::alu::{
+    add_to_accumulator, read_modify_write, subtract_from_accumulator, subtract_with_carry,
+};
+use crate::engine::ops::transfer::write_tolerate_readonly;
+use crate::memory::Memory;
+use crate::stack_pointer::StackPointer;
+
+// Undocumented NMOS 6502 operations; behavior and naming follows:
+// https://www.masswerk.at/nowgobang/2021/6502-illegal-opcodes
+// The "unstable" opcodes (ANE, LXA, SHA, SHX, SHY, TAS) are emulated with their most common behavior.
+
+// Combined read/modify/write operations:
+
+// SLO:    M = C <- [76543210] <- 0, A OR M -> A
+// status: N. ...ZC
+pub fn execute_slo<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    let value = read_modify_write(mode, cpu, |operand, _| (operand << 1, operand & 0x80 != 0))?;
+    cpu.accumulator |= value;
+    cpu.status.update_from(cpu.accumulator);
+    Ok(())
+}
+
+// RLA:    M = C <- [76543210] <- C, A AND M -> A
+// status: N. ...ZC
+pub fn execute_rla<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    let value = read_modify_write(mode, cpu, |operand, carry| {
+        ((operand << 1) | carry as u8, operand & 0x80 != 0)
+    })?;
+    cpu.accumulator &= value;
+    cpu.status.update_from(cpu.accumulator);
+    Ok(())
+}
+
+// SRE:    M = 0 -> [76543210] -> C, A EOR M -> A
+// status: N. ...ZC
+pub fn execute_sre<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    let value = read_modify_write(mode, cpu, |operand, _| (operand >> 1, operand & 0x01 != 0))?;
+    cpu.accumulator ^= value;
+    cpu.status.update_from(cpu.accumulator);
+    Ok(())
+}
+
+// RRA:    M = C -> [76543210] -> C, A + M + C -> A, C
+// status: NV ...ZC
+pub fn execute_rra<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    let value = read_modify_write(mode, cpu, |operand, carry| {
+        ((operand >> 1) | ((carry as u8) << 7), operand & 0x01 != 0)
+    })?;
+    add_to_accumulator(value, cpu);
+    Ok(())
+}
+
+// DCP:    M - 1 -> M, A - M
+// status: N. ...ZC
+pub fn execute_dcp<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    let value = read_modify_write(mode, cpu, |operand, carry| (operand.wrapping_sub(1), carry))?;
+    let (result, carry, _) = subtract_with_carry(cpu.accumulator, value, true);
+    cpu.status.update_from(result);
+    cpu.status.set_carry(carry);
+    Ok(())
+}
+
+// ISC:    M + 1 -> M, A - M - C̅ -> A
+// status: NV ...ZC
+pub fn execute_isc<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    let value = read_modify_write(mode, cpu, |operand, carry| (operand.wrapping_add(1), carry))?;
+    subtract_from_accumulator(value, cpu);
+    Ok(())
+}
+
+// Load and store operations:
+
+// LAX:    M -> A -> X
+// status: N. ...Z.
+pub fn execute_lax<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    let value = cpu.get_effective_operand(mode)?;
+    cpu.accumulator = value;
+    cpu.index_x = value;
+    cpu.status.update_from(value);
+    Ok(())
+}
+
+// LAS:    M AND SP -> A, X, SP
+// status: N. ...Z.
+pub fn execute_las<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    let operand = cpu.get_effective_operand(mode)?;
+    let value = operand & cpu.stack.get_sp()? as u8;
+    cpu.accumulator = value;
+    cpu.index_x = value;
+    cpu.stack.set_sp(0x0100 | value as u16)?;
+    cpu.status.update_from(value);
+    Ok(())
+}
+
+// SAX:    A AND X -> M
+// status: n/c
+pub fn execute_sax<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    let effective_address = cpu.get_effective_address(mode)?;
+    write_tolerate_readonly(effective_address, cpu.accumulator & cpu.index_x, cpu)
+}
+
+// SHA:    A AND X AND (H + 1) -> M
+// status: n/c
+pub fn execute_sha<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    let value = cpu.accumulator & cpu.index_x;
+    store_and_high_byte(mode, value, cpu)
+}
+
+// SHX:    X AND (H + 1) -> M
+// status: n/c
+pub fn execute_shx<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    store_and_high_byte(mode, cpu.index_x, cpu)
+}
+
+// SHY:    Y AND (H + 1) -> M
+// status: n/c
+pub fn execute_shy<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    store_and_high_byte(mode, cpu.index_y, cpu)
+}
+
+// TAS:    A AND X -> SP, SP AND (H + 1) -> M
+// status: n/c
+pub fn execute_tas<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    let value = cpu.accumulator & cpu.index_x;
+    cpu.stack.set_sp(0x0100 | value as u16)?;
+    store_and_high_byte(mode, value, cpu)
+}
+
+// the stored value is AND'ed with the high byte of the base address plus one;
+// if the indexed address crosses a page, the stored value also replaces the target's high byte
+fn store_and_high_byte<B: Memory>(
+    mode: AddressingMode,
+    value: u8,
+    cpu: &mut CpuImpl<B>,
+) -> Result<(), CpuError> {
+    let effective_address = cpu.get_effective_address(mode)?;
+    let page_crossed = cpu.is_page_crossed();
+    let base_high = ((effective_address >> 8) as u8).wrapping_sub(page_crossed as u8);
+    let value = value & base_high.wrapping_add(1);
+    let address = if page_crossed {
+        (value as u16) << 8 | (effective_address & 0x00FF)
+    } else {
+        effective_address
+    };
+    write_tolerate_readonly(address, value, cpu)
+}
+
+// Immediate operations:
+
+// ANC:    A AND M -> A, N -> C
+// status: N. ...ZC
+pub fn execute_anc<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    let operand = cpu.get_effective_operand(mode)?;
+    cpu.accumulator &= operand;
+    cpu.status.update_from(cpu.accumulator);
+    cpu.status.set_carry(cpu.status.negative());
+    Ok(())
+}
+
+// ALR:    A AND M -> A, 0 -> [76543210] -> C
+// status: N. ...ZC
+pub fn execute_alr<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    let value = cpu.accumulator & cpu.get_effective_operand(mode)?;
+    cpu.accumulator = value >> 1;
+    cpu.status.update_from(cpu.accumulator);
+    cpu.status.set_carry(value & 0x01 != 0);
+    Ok(())
+}
+
+// ARR:    A AND M -> A, C -> [76543210] -> C
+// status: NV ...ZC
+// C is bit 6 and V is bit 6 EOR bit 5 of the result; decimal mode fixes up the BCD nibbles
+pub fn execute_arr<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    let value = cpu.accumulator & cpu.get_effective_operand(mode)?;
+    let carry = cpu.status.carry() as u8;
+    let mut result = (value >> 1) | (carry << 7);
+    cpu.status.update_from(result);
+
+    if cpu.status.decimal_mode() {
+        cpu.status.set_overflow((value ^ result) & 0x40 != 0);
+        if (value & 0x0F) + (value & 0x01) > 0x05 {
+            result = (result & 0xF0) | (result.wrapping_add(0x06) & 0x0F);
+        }
+        let is_high_adjusted = (value & 0xF0) as u16 + (value & 0x10) as u16 > 0x50;
+        if is_high_adjusted {
+            result = result.wrapping_add(0x60);
+        }
+        cpu.status.set_carry(is_high_adjusted);
+    } else {
+        cpu.status.set_carry(result & 0x40 != 0);
+        cpu.status
+            .set_overflow(((result >> 6) ^ (result >> 5)) & 0x01 != 0);
+    }
+    cpu.accumulator = result;
+    Ok(())
+}
+
+// SBX:    (A AND X) - M -> X
+// status: N. ...ZC
+pub fn execute_sbx<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    let operand = cpu.get_effective_operand(mode)?;
+    let (result, carry, _) = subtract_with_carry(cpu.accumulator & cpu.index_x, operand, true);
+    cpu.index_x = result;
+    cpu.status.update_from(result);
+    cpu.status.set_carry(carry);
+    Ok(())
+}
+
+// "magic" constant of the unstable ANE and LXA opcodes, differs between chips
+const UNSTABLE_MAGIC: u8 = 0xEE;
+
+// ANE:    (A OR magic) AND X AND M -> A
+// status: N. ...Z.
+pub fn execute_ane<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    let operand = cpu.get_effective_operand(mode)?;
+    cpu.accumulator = (cpu.accumulator | UNSTABLE_MAGIC) & cpu.index_x & operand;
+    cpu.status.update_from(cpu.accumulator);
+    Ok(())
+}
+
+// LXA:    (A OR magic) AND M -> A -> X
+// status: N. ...Z.
+pub fn execute_lxa<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    let operand = cpu.get_effective_operand(mode)?;
+    cpu.accumulator = (cpu.accumulator | UNSTABLE_MAGIC) & operand;
+    cpu.index_x = cpu.accumulator;
+    cpu.status.update_from(cpu.accumulator);
+    Ok(())
+}
+
+// JAM:    halts the CPU, only a reset recovers
+// status: n/c
+pub fn execute_jam<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    if mode != AddressingMode::Implied {
+        return Err(CpuError::InvalidAddressingMode);
+    }
+    // the CPU is stuck on the JAM instruction:
+    let pc = cpu.address_bus.get_pc().wrapping_sub(1);
+    cpu.address_bus.set_pc(pc)?;
+    cpu.halt();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::Memory;
+
+    const ZERO_PAGE_ADDR: u16 = 0x00E0;
+    const NEXT_PC: u16 = 0x0200;
+
+    // operand is written to zero page address, or used as immediate value
+    fn setup_cpu(operand: u8) -> Result<CpuImpl, CpuError> {
+        let mut cpu = CpuImpl::default();
+        cpu.memory.write(ZERO_PAGE_ADDR, operand)?;
+        cpu.memory.write(NEXT_PC, ZERO_PAGE_ADDR as u8)?;
+        cpu.address_bus.set_pc(NEXT_PC)?;
+        Ok(cpu)
+    }
+
+    fn setup_cpu_immediate(operand: u8) -> Result<CpuImpl, CpuError> {
+        let mut cpu = CpuImpl::default();
+        cpu.memory.write(NEXT_PC, operand)?;
+        cpu.address_bus.set_pc(NEXT_PC)?;
+        Ok(cpu)
+    }
+
+    #[test]
+    fn slo() -> Result<(), CpuError> {
+        let mut cpu = setup_cpu(0b1010_0001)?;
+        cpu.accumulator = 0b0000_0100;
+        execute_slo(AddressingMode::ZeroPage, &mut cpu)?;
+        assert_eq!(cpu.memory.read(ZERO_PAGE_ADDR)?, 0b0100_0010);
+        assert_eq!(cpu.accumulator, 0b0100_0110);
+        assert!(cpu.status.carry());
+        assert!(!cpu.status.negative());
+        Ok(())
+    }
+
+    #[test]
+    fn rla() -> Result<(), CpuError> {
+        let mut cpu = setup_cpu(0b0100_0001)?;
+        cpu.status.set_carry(true);
+        cpu.accumulator = 0b1000_0011;
+        execute_rla(AddressingMode::ZeroPage, &mut cpu)?;
+        assert_eq!(cpu.memory.read(ZERO_PAGE_ADDR)?, 0b1000_0011);
+        assert_eq!(cpu.accumulator, 0b1000_0011);
+        assert!(!cpu.status.carry());
+        assert!(cpu.status.negative());
+        Ok(())
+    }
+
+    #[test]
+    fn sre() -> Result<(), CpuError> {
+        let mut cpu = setup_cpu(0b0000_0011)?;
+        cpu.accumulator = 0b0000_0001;
+        execute_sre(AddressingMode::ZeroPage, &mut cpu)?;
+        assert_eq!(cpu.memory.read(ZERO_PAGE_ADDR)?, 0b0000_0001);
+        assert_eq!(cpu.accumulator, 0);
+        assert!(cpu.status.carry());
+        assert!(cpu.status.zero());
+        Ok(())
+    }
+
+    #[test]
+    fn rra() -> Result<(), CpuError> {
+        let mut cpu = setup_cpu(0x21)?;
+        cpu.accumulator = 0x10;
+        execute_rra(AddressingMode::ZeroPage, &mut cpu)?;
+        assert_eq!(cpu.memory.read(ZERO_PAGE_ADDR)?, 0x10);
+        // the carry from ROR is added:
+        assert_eq!(cpu.accumulator, 0x21);
+        assert!(!cpu.status.carry());
+        Ok(())
+    }
+
+    #[test]
+    fn dcp() -> Result<(), CpuError> {
+        let mut cpu = setup_cpu(0x43)?;
+        cpu.accumulator = 0x42;
+        execute_dcp(AddressingMode::ZeroPage, &mut cpu)?;
+        assert_eq!(cpu.memory.read(ZERO_PAGE_ADDR)?, 0x42);
+        assert_eq!(cpu.accumulator, 0x42);
+        assert!(cpu.status.zero());
+        assert!(cpu.status.carry());
+        Ok(())
+    }
+
+    #[test]
+    fn isc() -> Result<(), CpuError> {
+        let mut cpu = setup_cpu(0x0F)?;
+        cpu.status.set_carry(true);
+        cpu.accumulator = 0x52;
+        execute_isc(AddressingMode::ZeroPage, &mut cpu)?;
+        assert_eq!(cpu.memory.read(ZERO_PAGE_ADDR)?, 0x10);
+        assert_eq!(cpu.accumulator, 0x42);
+        assert!(cpu.status.carry());
+        Ok(())
+    }
+
+    #[test]
+    fn lax() -> Result<(), CpuError> {
+        let mut cpu = setup_cpu(0x84)?;
+        execute_lax(AddressingMode::ZeroPage, &mut cpu)?;
+        assert_eq!(cpu.accumulator, 0x84);
+        assert_eq!(cpu.index_x, 0x84);
+        assert!(cpu.status.negative());
+        Ok(())
+    }
+
+    #[test]
+    fn las() -> Result<(), CpuError> {
+        let mut cpu = setup_cpu(0x3C)?;
+        cpu.stack.set_sp(0x01F0)?;
+        execute_las(AddressingMode::ZeroPage, &mut cpu)?;
+        assert_eq!(cpu.accumulator, 0x30);
+        assert_eq!(cpu.index_x, 0x30);
+        assert_eq!(cpu.stack.get_sp()?, 0x0130);
+        Ok(())
+    }
+
+    #[test]
+    fn sax() -> Result<(), CpuError> {
+        let mut cpu = setup_cpu(0x00)?;
+        cpu.accumulator = 0b1100_1100;
+        cpu.index_x = 0b1010_1010;
+        let org_status = cpu.status.get_status();
+        execute_sax(AddressingMode::ZeroPage, &mut cpu)?;
+        assert_eq!(cpu.memory.read(ZERO_PAGE_ADDR)?, 0b1000_1000);
+        assert_eq!(cpu.status.get_status(), org_status);
+        Ok(())
+    }
+
+    #[test]
+    fn shx() -> Result<(), CpuError> {
+        // SHX $11F0,Y: no page crossing
+        let mut cpu = CpuImpl::default();
+        cpu.load_program(NEXT_PC, &[0xF0, 0x11], false)?;
+        cpu.address_bus.set_pc(NEXT_PC)?;
+        cpu.index_x = 0xFF;
+        cpu.index_y = 0x01;
+        execute_shx(AddressingMode::AbsoluteY, &mut cpu)?;
+        assert_eq!(cpu.memory.read(0x11F1)?, 0x12);
+
+        // SHX $11F0,Y: page crossing replaces high byte of target address
+        let mut cpu = CpuImpl::default();
+        cpu.load_program(NEXT_PC, &[0xF0, 0x11], false)?;
+        cpu.address_bus.set_pc(NEXT_PC)?;
+        cpu.index_x = 0x0F;
+        cpu.index_y = 0x20;
+        execute_shx(AddressingMode::AbsoluteY, &mut cpu)?;
+        assert_eq!(cpu.memory.read(0x0210)?, 0x02);
+        Ok(())
+    }
+
+    #[test]
+    fn anc() -> Result<(), CpuError> {
+        let mut cpu = setup_cpu_immediate(0xF0)?;
+        cpu.accumulator = 0x8F;
+        execute_anc(AddressingMode::Immediate, &mut cpu)?;
+        assert_eq!(cpu.accumulator, 0x80);
+        assert!(cpu.status.negative());
+        assert!(cpu.status.carry());
+        Ok(())
+    }
+
+    #[test]
+    fn alr() -> Result<(), CpuError> {
+        let mut cpu = setup_cpu_immediate(0x0F)?;
+        cpu.accumulator = 0xFF;
+        execute_alr(AddressingMode::Immediate, &mut cpu)?;
+        assert_eq!(cpu.accumulator, 0x07);
+        assert!(cpu.status.carry());
+        Ok(())
+    }
+
+    #[test]
+    fn arr() -> Result<(), CpuError> {
+        let mut cpu = setup_cpu_immediate(0xFF)?;
+        cpu.accumulator = 0xC0;
+        cpu.status.set_carry(true);
+        execute_arr(AddressingMode::Immediate, &mut cpu)?;
+        assert_eq!(cpu.accumulator, 0xE0);
+        assert!(cpu.status.carry()); //     bit 6
+        assert!(!cpu.status.overflow()); // bit 6 EOR bit 5
+        assert!(cpu.status.negative());
+
+        let mut cpu = setup_cpu_immediate(0xFF)?;
+        cpu.accumulator = 0x40;
+        execute_arr(AddressingMode::Immediate, &mut cpu)?;
+        assert_eq!(cpu.accumulator, 0x20);
+        assert!(!cpu.status.carry());
+        assert!(cpu.status.overflow());
+        Ok(())
+    }
+
+    #[test]
+    fn arr_decimal() -> Result<(), CpuError> {
+        let mut cpu = setup_cpu_immediate(0xFF)?;
+        cpu.status.set_decimal_mode(true);
+        cpu.accumulator = 0x66;
+        execute_arr(AddressingMode::Immediate, &mut cpu)?;
+        // 0x33 with both nibbles adjusted:
+        assert_eq!(cpu.accumulator, 0x99);
+        assert!(cpu.status.carry());
+        Ok(())
+    }
+
+    #[test]
+    fn sbx() -> Result<(), CpuError> {
+        let mut cpu = setup_cpu_immediate(0x02)?;
+        cpu.accumulator = 0x0F;
+        cpu.index_x = 0xFC;
+        execute_sbx(AddressingMode::Immediate, &mut cpu)?;
+        assert_eq!(cpu.index_x, 0x0A);
+        assert_eq!(cpu.accumulator, 0x0F);
+        assert!(cpu.status.carry());
+        Ok(())
+    }
+
+    #[test]
+    fn jam() -> Result<(), CpuError> {
+        let mut cpu = CpuImpl::with_cpu_type(crate::CpuType::MOS6502Undocumented);
+        cpu.load_program(NEXT_PC, &[0xA9, 0x42, 0x02, 0xA9, 0x43], false)?;
+        cpu.address_bus.set_pc(NEXT_PC)?;
+        assert!(!cpu.step()?);
+        assert!(cpu.step()?);
+        assert_eq!(cpu.address_bus.get_pc(), NEXT_PC + 2);
+        // CPU stays halted:
+        assert!(cpu.step()?);
+        assert_eq!(cpu.address_bus.get_pc(), NEXT_PC + 2);
+        assert_eq!(cpu.accumulator, 0x42);
+        Ok(())
+    }
+}
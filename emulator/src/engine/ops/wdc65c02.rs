@@ -0,0 +1,303 @@
+use crate::CpuError;
+use crate::cpu_impl::{AddressingMode, CpuImpl};
+use crate::engine::ops::branch_jump::branch_if;
+use crate::engine::ops::transfer::write_tolerate_readonly;
+use crate::memory::Memory;
+use crate::stack_pointer::StackPointer;
+
+// Operations added by the CMOS 65C02, see also:
+// http://www.6502.org/tutorials/65c02opcodes.html
+
+// BRA:    Branch always
+// status: n/c
+pub fn execute_bra<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    branch_if(true, mode, cpu)
+}
+
+// PHX:    X -> SP
+// status: n/c
+pub fn execute_phx<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    if mode != AddressingMode::Implied {
+        return Err(CpuError::InvalidAddressingMode);
+    }
+    cpu.stack.push_byte(&mut cpu.memory, cpu.index_x)?;
+    Ok(())
+}
+
+// PHY:    Y -> SP
+// status: n/c
+pub fn execute_phy<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    if mode != AddressingMode::Implied {
+        return Err(CpuError::InvalidAddressingMode);
+    }
+    cpu.stack.push_byte(&mut cpu.memory, cpu.index_y)?;
+    Ok(())
+}
+
+// PLX:    SP -> X
+// status: N. ...Z.
+pub fn execute_plx<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    if mode != AddressingMode::Implied {
+        return Err(CpuError::InvalidAddressingMode);
+    }
+    cpu.index_x = cpu.stack.pop_byte(&cpu.memory)?;
+    cpu.status.update_from(cpu.index_x);
+    Ok(())
+}
+
+// PLY:    SP -> Y
+// status: N. ...Z.
+pub fn execute_ply<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    if mode != AddressingMode::Implied {
+        return Err(CpuError::InvalidAddressingMode);
+    }
+    cpu.index_y = cpu.stack.pop_byte(&cpu.memory)?;
+    cpu.status.update_from(cpu.index_y);
+    Ok(())
+}
+
+// STZ:    0 -> M
+// status: n/c
+pub fn execute_stz<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    let effective_address = cpu.get_effective_address(mode)?;
+    write_tolerate_readonly(effective_address, 0, cpu)
+}
+
+// TRB:    A AND M -> Z, NOT A AND M -> M
+// status: .. ...Z.
+pub fn execute_trb<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    let effective_address = cpu.get_effective_address(mode)?;
+    let operand = cpu.memory.read(effective_address)?;
+    cpu.status.set_zero(cpu.accumulator & operand == 0);
+    write_tolerate_readonly(effective_address, operand & !cpu.accumulator, cpu)
+}
+
+// TSB:    A AND M -> Z, A OR M -> M
+// status: .. ...Z.
+pub fn execute_tsb<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    let effective_address = cpu.get_effective_address(mode)?;
+    let operand = cpu.memory.read(effective_address)?;
+    cpu.status.set_zero(cpu.accumulator & operand == 0);
+    write_tolerate_readonly(effective_address, operand | cpu.accumulator, cpu)
+}
+
+// WAI:    Wait for interrupt
+// status: n/c
+pub fn execute_wai<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    if mode != AddressingMode::Implied {
+        return Err(CpuError::InvalidAddressingMode);
+    }
+    cpu.wait_for_interrupt();
+    Ok(())
+}
+
+// STP:    Stop the CPU, only a reset recovers
+// status: n/c
+pub fn execute_stp<B: Memory>(mode: AddressingMode, cpu: &mut CpuImpl<B>) -> Result<(), CpuError> {
+    if mode != AddressingMode::Implied {
+        return Err(CpuError::InvalidAddressingMode);
+    }
+    cpu.halt();
+    Ok(())
+}
+
+// Bit operations, one opcode per bit number:
+
+// BBR0-7: Branch if bit b of M is reset
+// BBS0-7: Branch if bit b of M is set
+// status: n/c
+macro_rules! branch_on_bit {
+    ($($name:ident: $bit:literal, $is_set:literal;)*) => {
+        $(
+            pub fn $name<B: Memory>(
+                mode: AddressingMode,
+                cpu: &mut CpuImpl<B>,
+            ) -> Result<(), CpuError> {
+                branch_on_bit($bit, $is_set, mode, cpu)
+            }
+        )*
+    };
+}
+
+branch_on_bit! {
+    execute_bbr0: 0, false; execute_bbr1: 1, false; execute_bbr2: 2, false; execute_bbr3: 3, false;
+    execute_bbr4: 4, false; execute_bbr5: 5, false; execute_bbr6: 6, false; execute_bbr7: 7, false;
+    execute_bbs0: 0, true; execute_bbs1: 1, true; execute_bbs2: 2, true; execute_bbs3: 3, true;
+    execute_bbs4: 4, true; execute_bbs5: 5, true; execute_bbs6: 6, true; execute_bbs7: 7, true;
+}
+
+fn branch_on_bit<B: Memory>(
+    bit: u8,
+    is_set: bool,
+    mode: AddressingMode,
+    cpu: &mut CpuImpl<B>,
+) -> Result<(), CpuError> {
+    if mode != AddressingMode::ZeroPageRelative {
+        return Err(CpuError::InvalidAddressingMode);
+    }
+    // first operand byte is the zero page address, second is the branch offset
+    let operand = cpu.get_effective_operand(AddressingMode::ZeroPage)?;
+    let is_taken = (operand & (1 << bit) != 0) == is_set;
+    branch_if(is_taken, AddressingMode::Relative, cpu)
+}
+
+// RMB0-7: 0 -> bit b of M
+// SMB0-7: 1 -> bit b of M
+// status: n/c
+macro_rules! modify_bit {
+    ($($name:ident: $bit:literal, $is_set:literal;)*) => {
+        $(
+            pub fn $name<B: Memory>(
+                mode: AddressingMode,
+                cpu: &mut CpuImpl<B>,
+            ) -> Result<(), CpuError> {
+                modify_bit($bit, $is_set, mode, cpu)
+            }
+        )*
+    };
+}
+
+modify_bit! {
+    execute_rmb0: 0, false; execute_rmb1: 1, false; execute_rmb2: 2, false; execute_rmb3: 3, false;
+    execute_rmb4: 4, false; execute_rmb5: 5, false; execute_rmb6: 6, false; execute_rmb7: 7, false;
+    execute_smb0: 0, true; execute_smb1: 1, true; execute_smb2: 2, true; execute_smb3: 3, true;
+    execute_smb4: 4, true; execute_smb5: 5, true; execute_smb6: 6, true; execute_smb7: 7, true;
+}
+
+fn modify_bit<B: Memory>(
+    bit: u8,
+    is_set: bool,
+    mode: AddressingMode,
+    cpu: &mut CpuImpl<B>,
+) -> Result<(), CpuError> {
+    let effective_address = cpu.get_effective_address(mode)?;
+    let operand = cpu.memory.read(effective_address)?;
+    let value = if is_set {
+        operand | (1 << bit)
+    } else {
+        operand & !(1 << bit)
+    };
+    write_tolerate_readonly(effective_address, value, cpu)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CpuType;
+    use crate::address_bus::AddressBus;
+
+    const ZERO_PAGE_ADDR: u16 = 0x00E0;
+    const NEXT_PC: u16 = 0x0300;
+
+    // operand is written to zero page address
+    fn setup_cpu(operand: u8) -> Result<CpuImpl, CpuError> {
+        let mut cpu = CpuImpl::with_cpu_type(CpuType::WDC65C02);
+        cpu.memory.write(ZERO_PAGE_ADDR, operand)?;
+        cpu.memory.write(NEXT_PC, ZERO_PAGE_ADDR as u8)?;
+        cpu.address_bus.set_pc(NEXT_PC)?;
+        Ok(cpu)
+    }
+
+    #[test]
+    fn bra() -> Result<(), CpuError> {
+        let mut cpu = setup_cpu(0)?;
+        cpu.memory.write(NEXT_PC, 0x10)?;
+        execute_bra(AddressingMode::Relative, &mut cpu)?;
+        assert_eq!(cpu.address_bus.get_pc(), NEXT_PC + 1 + 0x10);
+        Ok(())
+    }
+
+    #[test]
+    fn phx_ply() -> Result<(), CpuError> {
+        let mut cpu = setup_cpu(0)?;
+        cpu.index_x = 0x84;
+        execute_phx(AddressingMode::Implied, &mut cpu)?;
+        execute_ply(AddressingMode::Implied, &mut cpu)?;
+        assert_eq!(cpu.index_y, 0x84);
+        assert!(cpu.status.negative());
+
+        cpu.index_y = 0x00;
+        execute_phy(AddressingMode::Implied, &mut cpu)?;
+        execute_plx(AddressingMode::Implied, &mut cpu)?;
+        assert_eq!(cpu.index_x, 0x00);
+        assert!(cpu.status.zero());
+        Ok(())
+    }
+
+    #[test]
+    fn stz() -> Result<(), CpuError> {
+        let mut cpu = setup_cpu(0x42)?;
+        execute_stz(AddressingMode::ZeroPage, &mut cpu)?;
+        assert_eq!(cpu.memory.read(ZERO_PAGE_ADDR)?, 0x00);
+        assert!(!cpu.status.zero());
+        Ok(())
+    }
+
+    #[test]
+    fn trb() -> Result<(), CpuError> {
+        let mut cpu = setup_cpu(0b1100_1100)?;
+        cpu.accumulator = 0b1010_1010;
+        execute_trb(AddressingMode::ZeroPage, &mut cpu)?;
+        assert_eq!(cpu.memory.read(ZERO_PAGE_ADDR)?, 0b0100_0100);
+        assert!(!cpu.status.zero());
+        Ok(())
+    }
+
+    #[test]
+    fn tsb() -> Result<(), CpuError> {
+        let mut cpu = setup_cpu(0b1100_0000)?;
+        cpu.accumulator = 0b0000_0011;
+        execute_tsb(AddressingMode::ZeroPage, &mut cpu)?;
+        assert_eq!(cpu.memory.read(ZERO_PAGE_ADDR)?, 0b1100_0011);
+        assert!(cpu.status.zero());
+        Ok(())
+    }
+
+    #[test]
+    fn bbr_bbs() -> Result<(), CpuError> {
+        let mut cpu = setup_cpu(0b0000_1000)?;
+        cpu.memory.write(NEXT_PC + 1, 0x10)?;
+        execute_bbr3(AddressingMode::ZeroPageRelative, &mut cpu)?;
+        assert_eq!(cpu.address_bus.get_pc(), NEXT_PC + 2);
+
+        cpu.address_bus.set_pc(NEXT_PC)?;
+        execute_bbs3(AddressingMode::ZeroPageRelative, &mut cpu)?;
+        assert_eq!(cpu.address_bus.get_pc(), NEXT_PC + 2 + 0x10);
+        Ok(())
+    }
+
+    #[test]
+    fn rmb_smb() -> Result<(), CpuError> {
+        let mut cpu = setup_cpu(0xFF)?;
+        execute_rmb7(AddressingMode::ZeroPage, &mut cpu)?;
+        assert_eq!(cpu.memory.read(ZERO_PAGE_ADDR)?, 0x7F);
+
+        cpu.address_bus.set_pc(NEXT_PC)?;
+        execute_smb0(AddressingMode::ZeroPage, &mut cpu)?;
+        assert_eq!(cpu.memory.read(ZERO_PAGE_ADDR)?, 0x7F);
+
+        cpu.address_bus.set_pc(NEXT_PC)?;
+        execute_rmb0(AddressingMode::ZeroPage, &mut cpu)?;
+        assert_eq!(cpu.memory.read(ZERO_PAGE_ADDR)?, 0x7E);
+        Ok(())
+    }
+
+    #[test]
+    fn wai_stp() -> Result<(), CpuError> {
+        let mut cpu = CpuImpl::with_cpu_type(CpuType::WDC65C02);
+        cpu.load_program(NEXT_PC, &[0xCB, 0xDB], false)?;
+        cpu.address_bus.set_pc(NEXT_PC)?;
+        assert!(cpu.step()?);
+        assert_eq!(cpu.address_bus.get_pc(), NEXT_PC + 1);
+        // steps are ignored while waiting:
+        assert!(cpu.step()?);
+        assert_eq!(cpu.address_bus.get_pc(), NEXT_PC + 1);
+
+        cpu.reset()?;
+        cpu.address_bus.set_pc(NEXT_PC + 1)?;
+        assert!(cpu.step()?);
+        assert!(cpu.step()?);
+        assert_eq!(cpu.address_bus.get_pc(), NEXT_PC + 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,226 @@
+use std::cell::RefCell;
+use std::ops;
+
+use crate::CpuError;
+use crate::cpu_traps::{AccessKind, MemoryAccess};
+use crate::memory::Memory;
+
+/// Memory wrapper that records the previous value of every written byte,
+/// so that the writes of an instruction can be undone.
+/// While watching, it also records all reads and writes for watchpoints.
+/// Nothing is recorded outside of a journal, i.e. between clear and take.
+#[derive(Debug)]
+pub struct JournaledMemory<B: Memory> {
+    memory: B,
+    writes: Vec<(u16, u8)>,
+    journaling: bool,
+    // all writes so far, e.g. to tell a loop that changes memory from an endless one:
+    write_count: u64,
+    watching: bool,
+    // reads take &self, hence the RefCell:
+    accesses: RefCell<Vec<MemoryAccess>>,
+}
+
+impl<B: Memory> JournaledMemory<B> {
+    pub fn new(memory: B) -> JournaledMemory<B> {
+        JournaledMemory {
+            memory,
+            writes: vec![],
+            journaling: false,
+            write_count: 0,
+            watching: false,
+            accesses: RefCell::new(vec![]),
+        }
+    }
+
+    /// Starts a new journal; accesses are also recorded if watching is set.
+    pub fn clear_journal(&mut self, watching: bool) {
+        self.writes.clear();
+        self.accesses.get_mut().clear();
+        self.journaling = true;
+        self.watching = watching;
+    }
+
+    /// Returns the reads and writes since the journal was cleared, in order, and stops watching.
+    pub fn take_accesses(&mut self) -> Vec<MemoryAccess> {
+        self.watching = false;
+        std::mem::take(self.accesses.get_mut())
+    }
+
+    fn record_access(&self, address: u16, value: u8, kind: AccessKind) {
+        if self.watching {
+            self.accesses.borrow_mut().push(MemoryAccess {
+                address,
+                value,
+                kind,
+            });
+        }
+    }
+
+    pub fn write_count(&self) -> u64 {
+        self.write_count
+    }
+
+    /// Returns the overwritten (address, previous value) pairs since the journal was cleared,
+    /// and stops recording writes.
+    pub fn take_journal(&mut self) -> Vec<(u16, u8)> {
+        self.journaling = false;
+        std::mem::take(&mut self.writes)
+    }
+
+    /// Restores the previous values, latest write first.
+    pub fn undo(&mut self, writes: &[(u16, u8)]) -> Result<(), CpuError> {
+        for (address, value) in writes.iter().rev() {
+            self.memory.poke(*address, *value)?;
+        }
+        Ok(())
+    }
+}
+
+impl<B: Memory> Memory for JournaledMemory<B> {
+    fn read(&self, address: u16) -> Result<u8, CpuError> {
+        let value = self.memory.read(address)?;
+        self.record_access(address, value, AccessKind::Read);
+        Ok(value)
+    }
+
+    fn write(&mut self, address: u16, value: u8) -> Result<(), CpuError> {
+        if self.journaling {
+            let previous = self.memory.peek(address)?;
+            self.memory.write(address, value)?;
+            self.writes.push((address, previous));
+        } else {
+            self.memory.write(address, value)?;
+        }
+        self.write_count += 1;
+        self.record_access(address, value, AccessKind::Write);
+        Ok(())
+    }
+
+    fn get_size(&self) -> usize {
+        self.memory.get_size()
+    }
+
+    fn load_program(&mut self, start_addr: u16, program: &[u8]) -> Result<(), CpuError> {
+        self.memory.load_program(start_addr, program)
+    }
+
+    fn add_readonly(&mut self, range: ops::RangeInclusive<u16>) -> Result<(), CpuError> {
+        self.memory.add_readonly(range)
+    }
+
+    fn clear_readonly_ranges(&mut self) {
+        self.memory.clear_readonly_ranges();
+    }
+
+    fn get_readonly_ranges(&self) -> Vec<ops::RangeInclusive<u16>> {
+        self.memory.get_readonly_ranges()
+    }
+
+    fn peek(&self, address: u16) -> Result<u8, CpuError> {
+        self.memory.peek(address)
+    }
+
+    fn poke(&mut self, address: u16, value: u8) -> Result<(), CpuError> {
+        self.memory.poke(address, value)
+    }
+}
+
+/// Undo record of a single step: the registers before the step and the overwritten memory.
+#[derive(Debug, Clone)]
+pub struct UndoRecord {
+    pub accumulator: u8,
+    pub index_x: u8,
+    pub index_y: u8,
+    pub stack_pointer: u16,
+    pub program_counter: u16,
+    pub status: u8,
+    pub halted: bool,
+    pub waiting: bool,
+    pub nmi_pending: bool,
+    pub accumulated_cycles: u64,
+    pub accumulated_instructions: u64,
+    pub writes: Vec<(u16, u8)>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::MemoryImpl;
+
+    #[test]
+    fn journal_records_previous_values() -> Result<(), CpuError> {
+        let mut mem = JournaledMemory::new(MemoryImpl::default());
+        mem.write(0x0200, 0x11)?;
+        mem.clear_journal(false);
+
+        mem.write(0x0200, 0x22)?;
+        mem.write_word(0x01FE, 0x3344)?;
+        mem.write(0x0200, 0x55)?;
+        let writes = mem.take_journal();
+        assert_eq!(
+            writes,
+            vec![(0x0200, 0x11), (0x01FE, 0), (0x01FF, 0), (0x0200, 0x22)]
+        );
+        assert!(mem.take_journal().is_empty());
+
+        mem.undo(&writes)?;
+        assert_eq!(mem.read(0x0200)?, 0x11);
+        assert_eq!(mem.read_word(0x01FE)?, 0);
+        assert!(mem.take_accesses().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn watching_records_accesses() -> Result<(), CpuError> {
+        let mut mem = JournaledMemory::new(MemoryImpl::default());
+        mem.clear_journal(true);
+        mem.write(0x0200, 0x11)?;
+        mem.read(0x0200)?;
+        mem.read_zero_page_word(0x10)?;
+        let access = |address, value, kind| MemoryAccess {
+            address,
+            value,
+            kind,
+        };
+        assert_eq!(
+            mem.take_accesses(),
+            vec![
+                access(0x0200, 0x11, AccessKind::Write),
+                access(0x0200, 0x11, AccessKind::Read),
+                access(0x0010, 0, AccessKind::Read),
+                access(0x0011, 0, AccessKind::Read),
+            ]
+        );
+
+        mem.clear_journal(false);
+        mem.read(0x0200)?;
+        assert!(mem.take_accesses().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn writes_outside_journal_are_not_recorded() -> Result<(), CpuError> {
+        let mut mem = JournaledMemory::new(MemoryImpl::default());
+        mem.write(0x0200, 0x11)?;
+        mem.clear_journal(false);
+        mem.write(0x0200, 0x22)?;
+        assert_eq!(mem.take_journal(), vec![(0x0200, 0x11)]);
+
+        mem.write(0x0200, 0x33)?;
+        assert!(mem.take_journal().is_empty());
+        assert_eq!(mem.write_count(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn failed_write_is_not_recorded() -> Result<(), CpuError> {
+        let mut mem = JournaledMemory::new(MemoryImpl::default());
+        mem.load_program(0xE000, &[0x12])?;
+        mem.add_readonly(0xE000..=0xE000)?;
+        mem.clear_journal(false);
+        assert_eq!(mem.write(0xE000, 0x34), Err(CpuError::ReadOnlyMemory));
+        assert!(mem.take_journal().is_empty());
+        Ok(())
+    }
+}
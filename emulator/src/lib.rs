@@ -1,18 +1,34 @@
 use cpu::CpuControllerImpl;
+use std::io;
 use std::time;
 use thiserror::Error;
 
+pub use crate::condition::{BinaryOp, Expr, FlagName, RegisterName, UnaryOp};
 use crate::cpu_impl::CpuImpl;
+pub use crate::cpu_traps::{
+    AccessKind, Breakpoint, MemoryAccess, TrapContext, TrapHandler, TrapResult, WatchHit,
+    WatchKind, Watchpoint,
+};
+use crate::memory::MemoryImpl;
+pub use crate::memory_bus::{Device, MemoryBus};
+pub use crate::trace::Tracer;
 
 mod address_bus;
+mod condition;
 mod cpu;
 mod cpu_impl;
 mod cpu_traps;
 mod disassembler;
 mod engine;
+mod journal;
 mod memory;
+mod memory_bus;
+#[cfg(test)]
+mod single_step_tests;
+mod snapshot;
 mod stack_pointer;
 mod status_register;
+mod trace;
 
 #[derive(Debug, PartialEq, Error)]
 pub enum CpuError {
@@ -23,16 +39,78 @@ pub enum CpuError {
     #[error("addressing mode is not supported")]
     InvalidAddressingMode,
     #[error("illegal op code instruction {0}")]
-    InvalidOpcode(u8), // TODO: also capture PC
+    InvalidOpcode(u8),
     #[error("op code instruction expects an operand, but none was found")]
     MissingOperand,
-    #[error("op code instruction expects an operand, but none was found")]
+    #[error("stack overflow")]
     StackOverflow,
     #[error("memory range is read-only")]
     ReadOnlyMemory,
+    #[error("no return address on the stack for the trap handler")]
+    MissingReturnAddress,
+    #[error("invalid machine state: {0}")]
+    InvalidState(String),
+    #[error("writing trace failed: {0}")]
+    TraceOutput(String),
+    #[error("{0}")]
+    Execution(Box<ExecutionError>),
+}
+
+/// An error raised while executing an instruction, with where it happened.
+#[derive(Debug, PartialEq, Error)]
+#[error("{cause} at {pc:04X} (bytes: {})", format_bytes(.opcode_bytes))]
+pub struct ExecutionError {
+    pub cause: CpuError,
+    // address and bytes of the faulting instruction:
+    pub pc: u16,
+    pub opcode_bytes: Vec<u8>,
+    // registers after the failed instruction:
+    pub snapshot: CpuRegisterSnapshot,
+}
+
+fn format_bytes(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Limits for a run, measured from the start of the run; a run stops at the first limit reached.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RunLimits {
+    pub max_cycles: Option<u64>,
+    pub max_instructions: Option<u64>,
+    pub deadline: Option<time::Instant>,
+    // stops at an endless loop like JMP *, or a few instructions repeating without memory writes;
+    // polling a memory mapped device can look like one, hence opt-in
+    pub stop_on_loop: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    Stopped, // by a trap like BRK, or a halted or waiting CPU
+    CycleLimit,
+    InstructionLimit,
+    Deadline,
+    Watchpoint(WatchHit),
+    Loop(u16), // endless loop, at the PC where it was detected
+}
+
+impl std::fmt::Display for StopReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StopReason::Stopped => write!(f, "stopped"),
+            StopReason::CycleLimit => write!(f, "cycle limit reached"),
+            StopReason::InstructionLimit => write!(f, "instruction limit reached"),
+            StopReason::Deadline => write!(f, "timeout reached"),
+            StopReason::Watchpoint(hit) => write!(f, "watchpoint hit: {}", hit),
+            StopReason::Loop(pc) => write!(f, "endless loop at {:04X}", pc),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct CpuRegisterSnapshot {
     pub accumulator: u8,
     pub x_register: u8,
@@ -59,19 +137,143 @@ pub trait Cpu {
     // debugger API:
     fn set_pc(&mut self, addr: u16) -> Result<(), CpuError>;
     fn get_pc(&self) -> u16;
-    // TODO: run/step return a Result with a CpuError AND a CpuRegisterSnapshot to convey where the error occurred
+    // execution errors are returned as CpuError::Execution, with the faulting instruction and registers
     fn run(&mut self, start_addr: Option<u16>) -> Result<CpuRegisterSnapshot, CpuError>;
+    // continues at the current PC until stopped or a limit is reached
+    fn run_limited(
+        &mut self,
+        limits: RunLimits,
+    ) -> Result<(StopReason, CpuRegisterSnapshot), CpuError>;
+    fn run_for_cycles(
+        &mut self,
+        cycles: u64,
+    ) -> Result<(StopReason, CpuRegisterSnapshot), CpuError> {
+        self.run_limited(RunLimits {
+            max_cycles: Some(cycles),
+            ..Default::default()
+        })
+    }
+    fn run_for_instructions(
+        &mut self,
+        instructions: u64,
+    ) -> Result<(StopReason, CpuRegisterSnapshot), CpuError> {
+        self.run_limited(RunLimits {
+            max_instructions: Some(instructions),
+            ..Default::default()
+        })
+    }
+    fn run_until(
+        &mut self,
+        deadline: time::Instant,
+    ) -> Result<(StopReason, CpuRegisterSnapshot), CpuError> {
+        self.run_limited(RunLimits {
+            deadline: Some(deadline),
+            ..Default::default()
+        })
+    }
     fn step(&mut self) -> Result<CpuRegisterSnapshot, CpuError>;
+    // rewinds recorded steps; None if there is no history left to step back
+    fn step_back(&mut self) -> Result<Option<CpuRegisterSnapshot>, CpuError>;
+    // steps back to the previous address trap, or to the start of the recorded history
+    fn reverse_continue(&mut self) -> Result<Option<CpuRegisterSnapshot>, CpuError>;
+    // number of steps that can be undone; 0, the default, disables recording
+    fn set_history_limit(&mut self, steps: usize);
+    // throttles runs to a target clock frequency in Hz; None runs as fast as the host allows
+    fn set_clock_speed(&mut self, hz: Option<f64>);
+    // writes a line per executed instruction, e.g. for diffing with other emulators; None stops tracing
+    fn set_tracer(&mut self, tracer: Option<Tracer>);
     fn get_register_snapshot(&self) -> CpuRegisterSnapshot;
     fn disassemble(&self, start_addr: u16, lines: usize) -> Result<(Vec<String>, u16), CpuError>;
+    // bus access, with the side effects of memory mapped devices:
     fn get_byte_at(&self, address: u16) -> Result<u8, CpuError>;
     fn set_byte_at(&mut self, address: u16, value: u8) -> Result<(), CpuError>;
+    // side-effect-free access for debuggers and tracing; poke also modifies read-only memory:
+    fn peek(&self, address: u16) -> Result<u8, CpuError>;
+    fn poke(&mut self, address: u16, value: u8) -> Result<(), CpuError>;
+
+    // interrupt lines, serviced between instructions:
+    fn assert_irq(&mut self);
+    fn release_irq(&mut self);
+    fn trigger_nmi(&mut self);
+
+    // breakpoints stop before the instruction at an address; continuing executes it
+    fn add_breakpoint(&mut self, address: u16) {
+        self.add_conditional_breakpoint(address, None, 0);
+    }
+    // stops only if the condition holds, and not for the first ignore_count hits;
+    // replaces a previous breakpoint at the address
+    fn add_conditional_breakpoint(
+        &mut self,
+        address: u16,
+        condition: Option<Expr>,
+        ignore_count: u32,
+    );
+    fn remove_breakpoint(&mut self, address: u16) -> bool;
+    // with their conditions and remaining ignore counts
+    fn get_breakpoints(&self) -> Vec<Breakpoint>;
+    // stops runs on data reads or writes of an address range
+    fn add_watchpoint(&mut self, watchpoint: Watchpoint);
+    fn remove_watchpoint(&mut self, watchpoint: &Watchpoint) -> bool;
+    fn get_watchpoints(&self) -> Vec<Watchpoint>;
+    // a BRK stops runs by default; disabled, BRK continues via the IRQ vector at 0xFFFE,
+    // e.g. for monitors and OS calls with a BRK handler
+    fn set_brk_trap(&mut self, enabled: bool);
+
+    // emulates the routine at address in Rust; the CPU returns from it with an implicit RTS,
+    // so a return address must be on the stack
+    fn add_trap_handler(&mut self, address: u16, handler: Box<TrapHandler>);
+
+    // machine state snapshots: registers, memory, counters and traps
+    fn save_state(&self, writer: &mut dyn io::Write) -> Result<(), CpuError>;
+    fn load_state(&mut self, reader: &mut dyn io::Read) -> Result<(), CpuError>;
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum CpuType {
     MOS6502,
+    MOS6502Undocumented, // NMOS 6502 including the undocumented ("illegal") opcodes
+    WDC65C02,            // CMOS 65C02 including the Rockwell/WDC bit instructions, WAI and STP
+}
+
+/// How the CPU executes an opcode its CPU type does not define, e.g. after a jump into data.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum IllegalOpcodePolicy {
+    /// fails with CpuError::InvalidOpcode, at the PC of the opcode
+    Error,
+    /// skips the opcode, with the length and cycles it has on the NMOS 6502
+    Nop,
+    /// halts the CPU like the JAM (KIL) opcodes, until the next reset
+    Jam,
+    /// executes a BRK, i.e. continues via the IRQ vector
+    #[default]
+    Brk,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CpuOptions {
+    pub illegal_opcodes: IllegalOpcodePolicy,
 }
 
 pub fn create_cpu(kind: CpuType) -> Result<Box<dyn Cpu>, CpuError> {
-    CpuControllerImpl::create(kind)
+    create_cpu_with_options(kind, CpuOptions::default())
+}
+
+pub fn create_cpu_with_options(
+    kind: CpuType,
+    options: CpuOptions,
+) -> Result<Box<dyn Cpu>, CpuError> {
+    CpuControllerImpl::create(kind, MemoryImpl::default(), options)
+}
+
+/// Creates a CPU that runs on a bus of attached devices instead of plain RAM.
+pub fn create_cpu_with_bus(kind: CpuType, bus: MemoryBus) -> Result<Box<dyn Cpu>, CpuError> {
+    create_cpu_with_bus_and_options(kind, bus, CpuOptions::default())
+}
+
+pub fn create_cpu_with_bus_and_options(
+    kind: CpuType,
+    bus: MemoryBus,
+    options: CpuOptions,
+) -> Result<Box<dyn Cpu>, CpuError> {
+    CpuControllerImpl::create(kind, bus, options)
 }
@@ -2,25 +2,56 @@ use std::ops;
 
 use crate::CpuError;
 
-// write_zero_page_word & clear_readonly_ranges are not used yet
-#[allow(dead_code)]
 pub trait Memory {
     fn read(&self, address: u16) -> Result<u8, CpuError>;
-    fn read_word(&self, address: u16) -> Result<u16, CpuError>;
-    fn read_zero_page_word(&self, address: u8) -> Result<u16, CpuError>;
     fn write(&mut self, address: u16, value: u8) -> Result<(), CpuError>;
-    fn write_word(&mut self, address: u16, value: u16) -> Result<(), CpuError>;
-    fn write_zero_page_word(&mut self, address: u8, value: u16) -> Result<(), CpuError>;
+
+    // words are little endian, so the low byte is at the lower address;
+    // the high byte of a word at 0xFFFF is at 0x0000, like on the 6502
+    fn read_word(&self, address: u16) -> Result<u16, CpuError> {
+        let lo = self.read(address)? as u16;
+        let hi = self.read(address.wrapping_add(1))? as u16;
+        Ok((hi << 8) | lo)
+    }
+    // the high byte of a zero page word at 0xFF is at 0x00, the 6502 does not leave the zero page
+    fn read_zero_page_word(&self, address: u8) -> Result<u16, CpuError> {
+        let lo = self.read(address as u16)? as u16;
+        let hi = self.read(address.wrapping_add(1) as u16)? as u16;
+        Ok((hi << 8) | lo)
+    }
+    fn write_word(&mut self, address: u16, value: u16) -> Result<(), CpuError> {
+        self.write(address, value as u8)?;
+        self.write(address.wrapping_add(1), (value >> 8) as u8)
+    }
+    // write_zero_page_word is not used yet
+    #[allow(dead_code)]
+    fn write_zero_page_word(&mut self, address: u8, value: u16) -> Result<(), CpuError> {
+        self.write(address as u16, value as u8)?;
+        self.write(address.wrapping_add(1) as u16, (value >> 8) as u8)
+    }
+
     fn get_size(&self) -> usize;
     fn load_program(&mut self, start_addr: u16, program: &[u8]) -> Result<(), CpuError>;
-    fn add_readonly(&mut self, range: ops::Range<u16>) -> Result<(), CpuError>;
+    fn add_readonly(&mut self, range: ops::RangeInclusive<u16>) -> Result<(), CpuError>;
     fn clear_readonly_ranges(&mut self);
+    fn get_readonly_ranges(&self) -> Vec<ops::RangeInclusive<u16>> {
+        vec![]
+    }
+
+    // side-effect-free access for debuggers, disassembly and tracing;
+    // poke also modifies read-only memory
+    fn peek(&self, address: u16) -> Result<u8, CpuError> {
+        self.read(address)
+    }
+    fn poke(&mut self, address: u16, value: u8) -> Result<(), CpuError> {
+        self.write(address, value)
+    }
 }
 
 #[derive(Clone)]
 pub struct MemoryImpl {
     memory: Vec<u8>,
-    ranges: Vec<ops::Range<u16>>,
+    ranges: Vec<ops::RangeInclusive<u16>>,
 }
 
 impl MemoryImpl {
@@ -74,45 +105,27 @@ impl Memory for MemoryImpl {
         Ok(self.memory[address as usize])
     }
 
-    fn read_word(&self, address: u16) -> Result<u16, CpuError> {
-        // little endian, so low byte is read first:
-        let lo = self.read(address)? as u16;
-        let hi = self.read(address + 1)? as u16;
-        Ok((hi << 8) | lo)
-    }
-
-    fn read_zero_page_word(&self, address: u8) -> Result<u16, CpuError> {
-        self.read_word(address as u16)
-    }
-
     fn write(&mut self, address: u16, value: u8) -> Result<(), CpuError> {
         self.write_byte(address, value, false)
     }
 
-    fn write_word(&mut self, address: u16, value: u16) -> Result<(), CpuError> {
-        // little endian, so low byte is written to lower byte address:
-        self.write(address, value as u8)?;
-        self.write(address + 1, (value >> 8) as u8)?;
-        Ok(())
-    }
-
-    fn write_zero_page_word(&mut self, address: u8, value: u16) -> Result<(), CpuError> {
-        self.write_word(address as u16, value)
-    }
-
     fn get_size(&self) -> usize {
         self.memory.len()
     }
 
     fn load_program(&mut self, start_addr: u16, program: &[u8]) -> Result<(), CpuError> {
+        // a program past 0xFFFF wraps around to the zero page, but must not overlap itself:
+        if program.len() > 0x10000 {
+            return Err(CpuError::InvalidAddress);
+        }
         for (i, byte) in program.iter().enumerate() {
             // allow writing to readonly memory here:
-            self.write_byte(start_addr + i as u16, *byte, true)?;
+            self.write_byte(start_addr.wrapping_add(i as u16), *byte, true)?;
         }
         Ok(())
     }
 
-    fn add_readonly(&mut self, range: ops::Range<u16>) -> Result<(), CpuError> {
+    fn add_readonly(&mut self, range: ops::RangeInclusive<u16>) -> Result<(), CpuError> {
         self.ranges.push(range);
         Ok(())
     }
@@ -120,6 +133,14 @@ impl Memory for MemoryImpl {
     fn clear_readonly_ranges(&mut self) {
         self.ranges.clear();
     }
+
+    fn get_readonly_ranges(&self) -> Vec<ops::RangeInclusive<u16>> {
+        self.ranges.clone()
+    }
+
+    fn poke(&mut self, address: u16, value: u8) -> Result<(), CpuError> {
+        self.write_byte(address, value, true)
+    }
 }
 
 #[cfg(test)]
@@ -180,6 +201,37 @@ pub mod tests {
         Ok(())
     }
 
+    #[test]
+    fn load_program_wraps_to_zero_page() -> Result<(), CpuError> {
+        let mut mem = MemoryImpl::default();
+        mem.load_program(0xFFFE, &[0x12, 0x34, 0x56])?;
+        assert_eq!(0x34, mem.read(0xFFFF)?);
+        assert_eq!(0x56, mem.read(0x0000)?);
+
+        // a program larger than the address space would overwrite itself:
+        assert_eq!(
+            mem.load_program(0x0000, &vec![0; 0x10001]),
+            Err(CpuError::InvalidAddress)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn words_wrap_around() -> Result<(), CpuError> {
+        let mut mem = MemoryImpl::default();
+        mem.write_word(0xFFFF, 0x1234)?;
+        assert_eq!(0x34, mem.read(0xFFFF)?);
+        assert_eq!(0x12, mem.read(0x0000)?);
+        assert_eq!(0x1234, mem.read_word(0xFFFF)?);
+
+        // zero page words stay in the zero page:
+        mem.write_zero_page_word(0xFF, 0x5678)?;
+        assert_eq!(0x56, mem.read(0x0000)?);
+        assert_eq!(0x00, mem.read(0x0100)?);
+        assert_eq!(0x5678, mem.read_zero_page_word(0xFF)?);
+        Ok(())
+    }
+
     #[test]
     fn zero_page_read_write() -> Result<(), CpuError> {
         let mut mem = MemoryImpl::new(10);
@@ -193,7 +245,7 @@ pub mod tests {
     #[test]
     fn write_to_readonly_rejected() -> Result<(), CpuError> {
         let mut mem = MemoryImpl::new(0x0200);
-        mem.add_readonly(0x0100..0x0200)?;
+        mem.add_readonly(0x0100..=0x01FF)?;
 
         // load_program to readonly area is still allowed:
         mem.load_program(0x0180, &[0x12, 0x34, 0x56])?;
@@ -206,7 +258,13 @@ pub mod tests {
         mem.write(0x00AB, 0x55)?;
         assert_eq!(0x55, mem.read(0x00AB)?);
 
+        // poke ignores readonly ranges:
+        mem.poke(0x0181, 0xBB)?;
+        assert_eq!(0xBB, mem.peek(0x0181)?);
+
+        assert_eq!(mem.get_readonly_ranges()[0], 0x0100..=0x01FF);
         mem.clear_readonly_ranges();
+        assert!(mem.get_readonly_ranges().is_empty());
         mem.write(0x0180, 0xAA)?;
         assert_eq!(0xAA, mem.read(0x0180)?);
         Ok(())
@@ -0,0 +1,249 @@
+use std::cell::RefCell;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+
+use crate::CpuError;
+use crate::memory::{Memory, MemoryImpl};
+
+/// A memory mapped I/O device, e.g. an ACIA, VIA or video chip.
+/// The offset is relative to the start of the address range the device is attached to.
+pub trait Device {
+    /// Reads a device register; reads may have side effects, like clearing a status flag.
+    fn read(&mut self, offset: u16) -> u8;
+    fn write(&mut self, offset: u16, value: u8);
+
+    /// Returns a register's value without any side effects, used by debuggers and tracing.
+    fn peek(&self, offset: u16) -> u8;
+    /// Sets a register's value from a debugger; defaults to a regular write.
+    fn poke(&mut self, offset: u16, value: u8) {
+        self.write(offset, value);
+    }
+}
+
+struct AttachedDevice {
+    range: RangeInclusive<u16>,
+    device: Rc<RefCell<dyn Device>>,
+}
+
+/// Address bus with devices attached to address ranges;
+/// all other addresses fall through to RAM/ROM.
+pub struct MemoryBus {
+    memory: MemoryImpl,
+    devices: Vec<AttachedDevice>,
+}
+
+impl MemoryBus {
+    pub fn new() -> MemoryBus {
+        MemoryBus {
+            memory: MemoryImpl::default(),
+            devices: vec![],
+        }
+    }
+
+    /// Attaches a device to an address range; keep a clone of the device to inspect it
+    /// while the CPU is running. Address ranges of devices must not overlap.
+    pub fn attach(
+        &mut self,
+        range: RangeInclusive<u16>,
+        device: Rc<RefCell<dyn Device>>,
+    ) -> Result<(), CpuError> {
+        if range.is_empty()
+            || self
+                .devices
+                .iter()
+                .any(|d| d.range.start() <= range.end() && range.start() <= d.range.end())
+        {
+            return Err(CpuError::InvalidAddress);
+        }
+        self.devices.push(AttachedDevice { range, device });
+        Ok(())
+    }
+
+    fn find_device(&self, address: u16) -> Option<&AttachedDevice> {
+        self.devices.iter().find(|d| d.range.contains(&address))
+    }
+}
+
+impl Default for MemoryBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for MemoryBus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "MemoryBus {{ size: {}, devices: {} }}",
+            self.get_size(),
+            self.devices.len()
+        )
+    }
+}
+
+impl Memory for MemoryBus {
+    fn read(&self, address: u16) -> Result<u8, CpuError> {
+        match self.find_device(address) {
+            Some(d) => Ok(d.device.borrow_mut().read(address - d.range.start())),
+            None => self.memory.read(address),
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) -> Result<(), CpuError> {
+        match self.find_device(address) {
+            Some(d) => {
+                d.device
+                    .borrow_mut()
+                    .write(address - d.range.start(), value);
+                Ok(())
+            }
+            None => self.memory.write(address, value),
+        }
+    }
+
+    fn get_size(&self) -> usize {
+        self.memory.get_size()
+    }
+
+    // programs and ROM images are loaded into the memory underneath any devices
+    fn load_program(&mut self, start_addr: u16, program: &[u8]) -> Result<(), CpuError> {
+        self.memory.load_program(start_addr, program)
+    }
+
+    fn add_readonly(&mut self, range: RangeInclusive<u16>) -> Result<(), CpuError> {
+        self.memory.add_readonly(range)
+    }
+
+    fn clear_readonly_ranges(&mut self) {
+        self.memory.clear_readonly_ranges();
+    }
+
+    fn get_readonly_ranges(&self) -> Vec<RangeInclusive<u16>> {
+        self.memory.get_readonly_ranges()
+    }
+
+    fn peek(&self, address: u16) -> Result<u8, CpuError> {
+        match self.find_device(address) {
+            Some(d) => Ok(d.device.borrow().peek(address - d.range.start())),
+            None => self.memory.peek(address),
+        }
+    }
+
+    fn poke(&mut self, address: u16, value: u8) -> Result<(), CpuError> {
+        match self.find_device(address) {
+            Some(d) => {
+                d.device.borrow_mut().poke(address - d.range.start(), value);
+                Ok(())
+            }
+            None => self.memory.poke(address, value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // records the last write, and counts reads of its status register at offset 1
+    #[derive(Default)]
+    struct Latch {
+        value: u8,
+        reads: u8,
+    }
+
+    impl Device for Latch {
+        fn read(&mut self, offset: u16) -> u8 {
+            match offset {
+                0 => self.value,
+                _ => {
+                    self.reads += 1;
+                    self.reads
+                }
+            }
+        }
+
+        fn write(&mut self, _offset: u16, value: u8) {
+            self.value = value;
+        }
+
+        fn peek(&self, offset: u16) -> u8 {
+            match offset {
+                0 => self.value,
+                _ => self.reads,
+            }
+        }
+    }
+
+    #[test]
+    fn device_handles_its_address_range() -> Result<(), CpuError> {
+        let latch = Rc::new(RefCell::new(Latch::default()));
+        let mut bus = MemoryBus::new();
+        bus.attach(0xD000..=0xD001, latch.clone())?;
+
+        bus.write(0xD000, 0x42)?;
+        assert_eq!(latch.borrow().value, 0x42);
+        assert_eq!(bus.read(0xD000)?, 0x42);
+        // reads have side effects:
+        assert_eq!(bus.read(0xD001)?, 1);
+        assert_eq!(bus.read(0xD001)?, 2);
+
+        // other addresses fall through to memory:
+        bus.write(0xD002, 0x55)?;
+        assert_eq!(bus.read(0xD002)?, 0x55);
+        assert_eq!(latch.borrow().value, 0x42);
+        Ok(())
+    }
+
+    #[test]
+    fn peek_has_no_side_effects() -> Result<(), CpuError> {
+        let latch = Rc::new(RefCell::new(Latch::default()));
+        let mut bus = MemoryBus::new();
+        bus.attach(0xD000..=0xD001, latch.clone())?;
+        bus.load_program(0xE000, &[0x12])?;
+        bus.add_readonly(0xE000..=0xE000)?;
+
+        assert_eq!(bus.read(0xD001)?, 1);
+        assert_eq!(bus.peek(0xD001)?, 1);
+        assert_eq!(bus.peek(0xD001)?, 1);
+        assert_eq!(latch.borrow().reads, 1);
+
+        bus.poke(0xD000, 0x42)?;
+        assert_eq!(bus.peek(0xD000)?, 0x42);
+        bus.poke(0xE000, 0x34)?;
+        assert_eq!(bus.peek(0xE000)?, 0x34);
+        Ok(())
+    }
+
+    #[test]
+    fn word_access_spans_device_and_memory() -> Result<(), CpuError> {
+        let latch = Rc::new(RefCell::new(Latch::default()));
+        let mut bus = MemoryBus::new();
+        bus.attach(0xD000..=0xD000, latch.clone())?;
+        bus.write_word(0xCFFF, 0x1234)?;
+        assert_eq!(latch.borrow().value, 0x12);
+        assert_eq!(bus.read_word(0xCFFF)?, 0x1234);
+        Ok(())
+    }
+
+    #[test]
+    fn overlapping_devices_rejected() -> Result<(), CpuError> {
+        let mut bus = MemoryBus::new();
+        bus.attach(0xD000..=0xD00F, Rc::new(RefCell::new(Latch::default())))?;
+        assert_eq!(
+            bus.attach(0xD00F..=0xD01F, Rc::new(RefCell::new(Latch::default()))),
+            Err(CpuError::InvalidAddress)
+        );
+        bus.attach(0xD010..=0xD01F, Rc::new(RefCell::new(Latch::default())))?;
+        Ok(())
+    }
+
+    #[test]
+    fn readonly_memory_underneath() -> Result<(), CpuError> {
+        let mut bus = MemoryBus::new();
+        bus.load_program(0xE000, &[0x12, 0x34])?;
+        bus.add_readonly(0xE000..=0xE001)?;
+        assert_eq!(bus.write(0xE000, 0x55), Err(CpuError::ReadOnlyMemory));
+        assert_eq!(bus.read(0xE000)?, 0x12);
+        Ok(())
+    }
+}
@@ -0,0 +1,305 @@
+// Conformance tests against per-instruction JSON test vectors in the SingleStepTests format,
+// see https://github.com/SingleStepTests/65x02: one file per opcode, e.g. a9.json, with an array
+// of cases that each hold the initial and final machine state, and optionally the bus cycles.
+// The core executes whole instructions without the dummy reads and writes of the real bus,
+// so of the bus cycles only their number is compared, not the address, value and direction
+// of each cycle.
+//
+// The cases in tests/single_step are hand-written in that format for a few opcodes, they are
+// not taken from the suite. The full suite is an ignored test, run by CI, that needs the
+// environment variable SINGLE_STEP_TESTS_DIR pointing at a local copy of e.g. 65x02/6502/v1,
+// and fails unless it covers all 151 documented opcodes:
+//   SINGLE_STEP_TESTS_DIR=~/SingleStepTests/65x02/6502/v1 cargo test single_step -- --ignored --nocapture
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::cpu_impl::CpuImpl;
+use crate::engine::decoder;
+use crate::engine::opcodes::OpCode;
+use crate::memory::Memory;
+use crate::stack_pointer::StackPointer;
+use crate::{CpuError, CpuType};
+
+const VECTORS_DIR_VAR: &str = "SINGLE_STEP_TESTS_DIR";
+// the B flag and the unused bit 5 only exist on the stack:
+const STATUS_MASK: u8 = 0b1100_1111;
+// mismatches listed per opcode, the remaining ones are only counted
+const REPORTED_MISMATCHES: usize = 3;
+
+#[derive(Debug, Deserialize)]
+struct TestCase {
+    name: String,
+    initial: CaseState,
+    #[serde(rename = "final")]
+    expected: CaseState,
+    // address, value and "read" or "write" for each cycle:
+    #[serde(default)]
+    cycles: Option<Vec<(u16, u8, String)>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CaseState {
+    pc: u16,
+    s: u8,
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    ram: Vec<(u16, u8)>,
+}
+
+#[derive(Debug)]
+struct OpcodeReport {
+    opcode: u8,
+    cases: usize,
+    failed: usize,
+    mismatches: Vec<String>,
+}
+
+impl std::fmt::Display for OpcodeReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.failed == 0 {
+            return write!(f, "{:02x}: {} cases passed", self.opcode, self.cases);
+        }
+        write!(
+            f,
+            "{:02x}: {} of {} cases failed, e.g.",
+            self.opcode, self.failed, self.cases
+        )?;
+        for mismatch in &self.mismatches {
+            write!(f, "\n    {}", mismatch)?;
+        }
+        Ok(())
+    }
+}
+
+// runs the cases of all opcode files in dir that the CPU type documents
+fn run_vectors(dir: &Path, cpu_type: CpuType) -> Vec<OpcodeReport> {
+    let mut files: Vec<(u8, PathBuf)> = fs::read_dir(dir)
+        .unwrap_or_else(|e| panic!("cannot read test vectors in {}: {}", dir.display(), e))
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            if path.extension()? != "json" {
+                return None;
+            }
+            let opcode = u8::from_str_radix(path.file_stem()?.to_str()?, 16).ok()?;
+            Some((opcode, path))
+        })
+        .filter(|(opcode, _)| is_documented(*opcode, cpu_type))
+        .collect();
+    files.sort();
+
+    let mut cpu = CpuImpl::with_cpu_type(cpu_type);
+    files
+        .iter()
+        .map(|(opcode, path)| {
+            let json = fs::read_to_string(path)
+                .unwrap_or_else(|e| panic!("cannot read {}: {}", path.display(), e));
+            let cases: Vec<TestCase> = serde_json::from_str(&json)
+                .unwrap_or_else(|e| panic!("cannot parse {}: {}", path.display(), e));
+            let mut report = OpcodeReport {
+                opcode: *opcode,
+                cases: cases.len(),
+                failed: 0,
+                mismatches: vec![],
+            };
+            for case in &cases {
+                let mismatches = run_case(&mut cpu, case);
+                if !mismatches.is_empty() {
+                    report.failed += 1;
+                    if report.mismatches.len() < REPORTED_MISMATCHES {
+                        report
+                            .mismatches
+                            .push(format!("{}: {}", case.name, mismatches.join(", ")));
+                    }
+                }
+            }
+            report
+        })
+        .collect()
+}
+
+fn is_documented(opcode: u8, cpu_type: CpuType) -> bool {
+    !matches!(decoder::decode(opcode, cpu_type).opcode, OpCode::ILL(_))
+}
+
+fn documented_opcodes(cpu_type: CpuType) -> Vec<u8> {
+    (0..=0xFF)
+        .filter(|opcode| is_documented(*opcode, cpu_type))
+        .collect()
+}
+
+// documented opcodes without test vectors
+fn missing_opcodes(reports: &[OpcodeReport], cpu_type: CpuType) -> Vec<u8> {
+    documented_opcodes(cpu_type)
+        .into_iter()
+        .filter(|opcode| !reports.iter().any(|r| r.opcode == *opcode && r.cases > 0))
+        .collect()
+}
+
+// executes one instruction from the initial state; returns the differences to the final state
+fn run_case(cpu: &mut CpuImpl, case: &TestCase) -> Vec<String> {
+    let initial = &case.initial;
+    if let Err(e) = set_state(cpu, initial) {
+        return vec![format!("cannot set initial state: {}", e)];
+    }
+    let start_cycles = cpu.get_register_snapshot().accumulated_cycles;
+
+    let mut mismatches = vec![];
+    if let Err(e) = cpu.step() {
+        mismatches.push(format!("step failed: {}", e));
+    }
+    let expected = &case.expected;
+    let mut compare = |name: &str, expected: u16, actual: u16| {
+        if expected != actual {
+            mismatches.push(format!(
+                "{} expected {:02X}, got {:02X}",
+                name, expected, actual
+            ));
+        }
+    };
+    compare("pc", expected.pc, cpu.get_pc());
+    compare(
+        "s",
+        expected.s as u16,
+        cpu.stack.get_sp().unwrap_or(0) & 0xFF,
+    );
+    compare("a", expected.a as u16, cpu.accumulator as u16);
+    compare("x", expected.x as u16, cpu.index_x as u16);
+    compare("y", expected.y as u16, cpu.index_y as u16);
+    compare(
+        "p",
+        (expected.p & STATUS_MASK) as u16,
+        (cpu.status.get_status() & STATUS_MASK) as u16,
+    );
+    for (address, value) in &expected.ram {
+        let actual = cpu.peek(*address).unwrap_or(0);
+        compare(
+            format!("ram[{:04X}]", address).as_str(),
+            *value as u16,
+            actual as u16,
+        );
+    }
+    // only the number of bus cycles, see above:
+    if let Some(cycles) = &case.cycles {
+        let actual = cpu.get_register_snapshot().accumulated_cycles - start_cycles;
+        compare("cycles", cycles.len() as u16, actual as u16);
+    }
+
+    // clear the memory of this case, so that it cannot leak into the next one:
+    for (address, _) in initial.ram.iter().chain(&expected.ram) {
+        let _ = cpu.memory.write(*address, 0);
+    }
+    mismatches
+}
+
+fn set_state(cpu: &mut CpuImpl, state: &CaseState) -> Result<(), CpuError> {
+    cpu.reset()?;
+    cpu.accumulator = state.a;
+    cpu.index_x = state.x;
+    cpu.index_y = state.y;
+    cpu.stack.set_sp(0x0100 | state.s as u16)?;
+    cpu.status.set_status(state.p & STATUS_MASK);
+    cpu.set_pc(state.pc)?;
+    for (address, value) in &state.ram {
+        cpu.memory.write(*address, *value)?;
+    }
+    Ok(())
+}
+
+// with all_opcodes, fails unless every documented opcode has test vectors
+fn assert_conformance(dir: &Path, cpu_type: CpuType, all_opcodes: bool) {
+    let reports = run_vectors(dir, cpu_type);
+    assert!(!reports.is_empty(), "no test vectors in {}", dir.display());
+    for report in &reports {
+        println!("{}", report);
+    }
+    let missing = missing_opcodes(&reports, cpu_type);
+    let cases: usize = reports.iter().map(|report| report.cases).sum();
+    println!(
+        "{} of {} documented opcodes covered by {} cases",
+        documented_opcodes(cpu_type).len() - missing.len(),
+        documented_opcodes(cpu_type).len(),
+        cases
+    );
+    if all_opcodes {
+        let missing: Vec<String> = missing.iter().map(|op| format!("{:02x}", op)).collect();
+        assert!(
+            missing.is_empty(),
+            "no test vectors for {} documented opcodes: {}",
+            missing.len(),
+            missing.join(" ")
+        );
+    }
+    let failed: Vec<String> = reports
+        .iter()
+        .filter(|report| report.failed > 0)
+        .map(|report| format!("{:02x}", report.opcode))
+        .collect();
+    assert!(
+        failed.is_empty(),
+        "{} of {} opcodes failed: {}",
+        failed.len(),
+        reports.len(),
+        failed.join(" ")
+    );
+}
+
+#[test]
+fn single_step_checked_in_vectors() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/single_step/6502");
+    assert_conformance(&dir, CpuType::MOS6502, false);
+}
+
+#[test]
+#[ignore = "needs the full suite in SINGLE_STEP_TESTS_DIR"]
+fn single_step_vectors_from_env() {
+    let dir = std::env::var(VECTORS_DIR_VAR)
+        .unwrap_or_else(|_| panic!("{} must point at the test vectors", VECTORS_DIR_VAR));
+    assert_conformance(Path::new(&dir), CpuType::MOS6502, true);
+}
+
+#[test]
+fn single_step_coverage_of_documented_opcodes() {
+    assert_eq!(documented_opcodes(CpuType::MOS6502).len(), 151);
+    let reports: Vec<OpcodeReport> = [0xA9, 0xE8]
+        .iter()
+        .map(|opcode| OpcodeReport {
+            opcode: *opcode,
+            cases: 1,
+            failed: 0,
+            mismatches: vec![],
+        })
+        .collect();
+    let missing = missing_opcodes(&reports, CpuType::MOS6502);
+    assert_eq!(missing.len(), 149);
+    assert!(!missing.contains(&0xA9));
+    // undocumented opcodes don't count:
+    assert!(!missing.contains(&0x02));
+}
+
+#[test]
+fn single_step_reports_mismatches() {
+    let json = r#"{
+        "name": "a9 42",
+        "initial": { "pc": 512, "s": 253, "a": 0, "x": 0, "y": 0, "p": 36,
+                     "ram": [[512, 169], [513, 66]] },
+        "final":   { "pc": 514, "s": 253, "a": 67, "x": 0, "y": 0, "p": 38,
+                     "ram": [[512, 169], [513, 66]] },
+        "cycles": [[512, 169, "read"]]
+    }"#;
+    let case: TestCase = serde_json::from_str(json).unwrap();
+    let mut cpu = CpuImpl::new();
+    assert_eq!(
+        run_case(&mut cpu, &case),
+        vec![
+            "a expected 43, got 42",
+            "p expected 06, got 04",
+            "cycles expected 01, got 02"
+        ]
+    );
+    // the memory of the case is cleared:
+    assert_eq!(cpu.peek(512).unwrap(), 0);
+}
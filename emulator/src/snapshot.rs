@@ -0,0 +1,470 @@
+use std::io::{Read, Write};
+use std::ops;
+
+use crate::condition::{BinaryOp, Expr, FlagName, RegisterName, UnaryOp};
+use crate::cpu_traps::{CpuTrap, Trap, TrapOutcomeStatus};
+use crate::{CpuError, CpuType};
+
+const MAGIC: &[u8; 4] = b"6502";
+const VERSION: u16 = 1;
+// nesting limit of a condition read from a file
+const MAX_EXPR_DEPTH: usize = 64;
+
+// bits of the machine state flags byte:
+const FLAG_HALTED: u8 = 0x01;
+const FLAG_WAITING: u8 = 0x02;
+const FLAG_IRQ_ASSERTED: u8 = 0x04;
+const FLAG_NMI_PENDING: u8 = 0x08;
+
+/// Complete machine state, as written to and read from a snapshot file.
+///
+/// The file format is little endian: a magic "6502" and a u16 version, followed by the
+/// CPU type, registers, flags, counters, memory, read-only ranges and traps with their conditions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MachineState {
+    pub cpu_type: CpuType,
+    pub accumulator: u8,
+    pub index_x: u8,
+    pub index_y: u8,
+    pub stack_pointer: u8,
+    pub program_counter: u16,
+    pub status: u8,
+    pub halted: bool,
+    pub waiting: bool,
+    pub irq_asserted: bool,
+    pub nmi_pending: bool,
+    pub accumulated_cycles: u64,
+    pub accumulated_instructions: u64,
+    pub memory: Vec<u8>,
+    pub readonly_ranges: Vec<ops::RangeInclusive<u16>>,
+    pub traps: Vec<Trap>,
+}
+
+impl MachineState {
+    pub fn write_to(&self, writer: &mut dyn Write) -> Result<(), CpuError> {
+        let mut buf: Vec<u8> = Vec::with_capacity(self.memory.len() + 64);
+        buf.extend_from_slice(MAGIC);
+        buf.extend_from_slice(&VERSION.to_le_bytes());
+
+        buf.push(encode_cpu_type(self.cpu_type));
+        buf.extend_from_slice(&[
+            self.accumulator,
+            self.index_x,
+            self.index_y,
+            self.stack_pointer,
+        ]);
+        buf.extend_from_slice(&self.program_counter.to_le_bytes());
+        buf.push(self.status);
+        let mut flags = 0;
+        for (set, bit) in [
+            (self.halted, FLAG_HALTED),
+            (self.waiting, FLAG_WAITING),
+            (self.irq_asserted, FLAG_IRQ_ASSERTED),
+            (self.nmi_pending, FLAG_NMI_PENDING),
+        ] {
+            if set {
+                flags |= bit;
+            }
+        }
+        buf.push(flags);
+        buf.extend_from_slice(&self.accumulated_cycles.to_le_bytes());
+        buf.extend_from_slice(&self.accumulated_instructions.to_le_bytes());
+
+        buf.extend_from_slice(&(self.memory.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&self.memory);
+
+        buf.extend_from_slice(&(self.readonly_ranges.len() as u16).to_le_bytes());
+        for range in &self.readonly_ranges {
+            buf.extend_from_slice(&range.start().to_le_bytes());
+            buf.extend_from_slice(&range.end().to_le_bytes());
+        }
+
+        buf.extend_from_slice(&(self.traps.len() as u16).to_le_bytes());
+        for trap in &self.traps {
+            let (kind, target) = match trap.cpu_trap {
+                CpuTrap::ByAddress(address) => (0, address),
+                CpuTrap::ByInstruction(op_code) => (1, op_code as u16),
+            };
+            buf.push(kind);
+            buf.extend_from_slice(&target.to_le_bytes());
+            buf.push(encode_outcome(trap.requested_outcome));
+            buf.extend_from_slice(&trap.ignore_count.to_le_bytes());
+            match &trap.condition {
+                Some(condition) => {
+                    buf.push(1);
+                    write_expr(&mut buf, condition);
+                }
+                None => buf.push(0),
+            }
+        }
+
+        writer.write_all(&buf).map_err(io_error)
+    }
+
+    pub fn read_from(reader: &mut dyn Read) -> Result<MachineState, CpuError> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic).map_err(io_error)?;
+        if &magic != MAGIC {
+            return Err(CpuError::InvalidState("not a 6502 state file".to_string()));
+        }
+        let version = read_u16(reader)?;
+        if version != VERSION {
+            return Err(CpuError::InvalidState(format!(
+                "unsupported state file version {}",
+                version
+            )));
+        }
+
+        let cpu_type = decode_cpu_type(read_u8(reader)?)?;
+        let accumulator = read_u8(reader)?;
+        let index_x = read_u8(reader)?;
+        let index_y = read_u8(reader)?;
+        let stack_pointer = read_u8(reader)?;
+        let program_counter = read_u16(reader)?;
+        let status = read_u8(reader)?;
+        let flags = read_u8(reader)?;
+        let accumulated_cycles = read_u64(reader)?;
+        let accumulated_instructions = read_u64(reader)?;
+
+        let mut size = [0u8; 4];
+        reader.read_exact(&mut size).map_err(io_error)?;
+        let size = u32::from_le_bytes(size) as usize;
+        if size > 0x10000 {
+            return Err(CpuError::InvalidState(format!(
+                "memory size {} exceeds 64K",
+                size
+            )));
+        }
+        let mut memory = vec![0u8; size];
+        reader.read_exact(&mut memory).map_err(io_error)?;
+
+        let mut readonly_ranges = vec![];
+        for _ in 0..read_u16(reader)? {
+            let start = read_u16(reader)?;
+            let end = read_u16(reader)?;
+            readonly_ranges.push(start..=end);
+        }
+
+        let mut traps = vec![];
+        for _ in 0..read_u16(reader)? {
+            let kind = read_u8(reader)?;
+            let target = read_u16(reader)?;
+            let cpu_trap = match kind {
+                0 => CpuTrap::ByAddress(target),
+                1 => CpuTrap::ByInstruction(target as u8),
+                _ => {
+                    return Err(CpuError::InvalidState(format!(
+                        "unknown trap kind {}",
+                        kind
+                    )));
+                }
+            };
+            let requested_outcome = decode_outcome(read_u8(reader)?)?;
+            let ignore_count = read_u32(reader)?;
+            let condition = match read_u8(reader)? {
+                0 => None,
+                _ => Some(read_expr(reader, 0)?),
+            };
+            traps.push(Trap {
+                cpu_trap,
+                requested_outcome,
+                condition,
+                ignore_count,
+            });
+        }
+
+        Ok(MachineState {
+            cpu_type,
+            accumulator,
+            index_x,
+            index_y,
+            stack_pointer,
+            program_counter,
+            status,
+            halted: flags & FLAG_HALTED != 0,
+            waiting: flags & FLAG_WAITING != 0,
+            irq_asserted: flags & FLAG_IRQ_ASSERTED != 0,
+            nmi_pending: flags & FLAG_NMI_PENDING != 0,
+            accumulated_cycles,
+            accumulated_instructions,
+            memory,
+            readonly_ranges,
+            traps,
+        })
+    }
+}
+
+fn io_error(err: std::io::Error) -> CpuError {
+    CpuError::InvalidState(err.to_string())
+}
+
+fn read_u8(reader: &mut dyn Read) -> Result<u8, CpuError> {
+    let mut buf = [0u8; 1];
+    reader.read_exact(&mut buf).map_err(io_error)?;
+    Ok(buf[0])
+}
+
+fn read_u16(reader: &mut dyn Read) -> Result<u16, CpuError> {
+    let mut buf = [0u8; 2];
+    reader.read_exact(&mut buf).map_err(io_error)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+fn read_u32(reader: &mut dyn Read) -> Result<u32, CpuError> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf).map_err(io_error)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(reader: &mut dyn Read) -> Result<u64, CpuError> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf).map_err(io_error)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn encode_cpu_type(cpu_type: CpuType) -> u8 {
+    match cpu_type {
+        CpuType::MOS6502 => 0,
+        CpuType::MOS6502Undocumented => 1,
+        CpuType::WDC65C02 => 2,
+    }
+}
+
+fn decode_cpu_type(value: u8) -> Result<CpuType, CpuError> {
+    match value {
+        0 => Ok(CpuType::MOS6502),
+        1 => Ok(CpuType::MOS6502Undocumented),
+        2 => Ok(CpuType::WDC65C02),
+        _ => Err(CpuError::InvalidState(format!(
+            "unknown CPU type {}",
+            value
+        ))),
+    }
+}
+
+// prefix notation: a tag byte, followed by the operator or value and the operands;
+// names and operators are encoded by their declaration order
+fn write_expr(buf: &mut Vec<u8>, expr: &Expr) {
+    match expr {
+        Expr::Number(value) => {
+            buf.push(0);
+            buf.extend_from_slice(&value.to_le_bytes());
+        }
+        Expr::Register(name) => buf.extend_from_slice(&[1, *name as u8]),
+        Expr::Flag(name) => buf.extend_from_slice(&[2, *name as u8]),
+        Expr::Byte(address) => {
+            buf.push(3);
+            write_expr(buf, address);
+        }
+        Expr::Word(address) => {
+            buf.push(4);
+            write_expr(buf, address);
+        }
+        Expr::Unary(op, operand) => {
+            buf.extend_from_slice(&[5, *op as u8]);
+            write_expr(buf, operand);
+        }
+        Expr::Binary(op, left, right) => {
+            buf.extend_from_slice(&[6, *op as u8]);
+            write_expr(buf, left);
+            write_expr(buf, right);
+        }
+    }
+}
+
+fn read_expr(reader: &mut dyn Read, depth: usize) -> Result<Expr, CpuError> {
+    if depth > MAX_EXPR_DEPTH {
+        return Err(CpuError::InvalidState(
+            "trap condition is nested too deeply".to_string(),
+        ));
+    }
+    let invalid =
+        |what: &str, value: u8| CpuError::InvalidState(format!("unknown {} {}", what, value));
+    let tag = read_u8(reader)?;
+    let expr = match tag {
+        0 => Expr::Number(read_u16(reader)?),
+        1 => {
+            let value = read_u8(reader)?;
+            let name = match value {
+                0 => RegisterName::Accumulator,
+                1 => RegisterName::IndexX,
+                2 => RegisterName::IndexY,
+                3 => RegisterName::StackPointer,
+                4 => RegisterName::ProgramCounter,
+                _ => return Err(invalid("register", value)),
+            };
+            Expr::Register(name)
+        }
+        2 => {
+            let value = read_u8(reader)?;
+            let name = match value {
+                0 => FlagName::Negative,
+                1 => FlagName::Overflow,
+                2 => FlagName::Decimal,
+                3 => FlagName::InterruptDisable,
+                4 => FlagName::Zero,
+                5 => FlagName::Carry,
+                _ => return Err(invalid("flag", value)),
+            };
+            Expr::Flag(name)
+        }
+        3 => Expr::Byte(Box::new(read_expr(reader, depth + 1)?)),
+        4 => Expr::Word(Box::new(read_expr(reader, depth + 1)?)),
+        5 => {
+            let value = read_u8(reader)?;
+            let op = match value {
+                0 => UnaryOp::Not,
+                _ => return Err(invalid("operator", value)),
+            };
+            Expr::Unary(op, Box::new(read_expr(reader, depth + 1)?))
+        }
+        6 => {
+            let value = read_u8(reader)?;
+            let op = match value {
+                0 => BinaryOp::Or,
+                1 => BinaryOp::And,
+                2 => BinaryOp::Eq,
+                3 => BinaryOp::Ne,
+                4 => BinaryOp::Lt,
+                5 => BinaryOp::Le,
+                6 => BinaryOp::Gt,
+                7 => BinaryOp::Ge,
+                8 => BinaryOp::Add,
+                9 => BinaryOp::Sub,
+                10 => BinaryOp::BitAnd,
+                11 => BinaryOp::BitOr,
+                _ => return Err(invalid("operator", value)),
+            };
+            let left = read_expr(reader, depth + 1)?;
+            let right = read_expr(reader, depth + 1)?;
+            Expr::Binary(op, Box::new(left), Box::new(right))
+        }
+        _ => return Err(invalid("condition element", tag)),
+    };
+    Ok(expr)
+}
+
+fn encode_outcome(outcome: TrapOutcomeStatus) -> u8 {
+    match outcome {
+        TrapOutcomeStatus::Continue => 0,
+        TrapOutcomeStatus::Handled => 1,
+        TrapOutcomeStatus::Stop => 2,
+        TrapOutcomeStatus::StopAfter => 3,
+    }
+}
+
+fn decode_outcome(value: u8) -> Result<TrapOutcomeStatus, CpuError> {
+    match value {
+        0 => Ok(TrapOutcomeStatus::Continue),
+        1 => Ok(TrapOutcomeStatus::Handled),
+        2 => Ok(TrapOutcomeStatus::Stop),
+        3 => Ok(TrapOutcomeStatus::StopAfter),
+        _ => Err(CpuError::InvalidState(format!(
+            "unknown trap outcome {}",
+            value
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_state() -> MachineState {
+        let mut memory = vec![0u8; 0x10000];
+        memory[0x0200] = 0xA9;
+        memory[0xFFFC] = 0x00;
+        memory[0xFFFD] = 0x02;
+        MachineState {
+            cpu_type: CpuType::WDC65C02,
+            accumulator: 0x12,
+            index_x: 0x34,
+            index_y: 0x56,
+            stack_pointer: 0xFD,
+            program_counter: 0x0200,
+            status: 0b1010_0101,
+            halted: false,
+            waiting: true,
+            irq_asserted: true,
+            nmi_pending: false,
+            accumulated_cycles: 1_000_000_007,
+            accumulated_instructions: 314_159,
+            memory,
+            readonly_ranges: vec![0xC000..=0xCFFF, 0xE000..=0xFFFF],
+            traps: vec![
+                Trap {
+                    cpu_trap: CpuTrap::ByAddress(0x0400),
+                    requested_outcome: TrapOutcomeStatus::Stop,
+                    // !Z && mem[$40 + X] >= 3
+                    condition: Some(Expr::Binary(
+                        BinaryOp::And,
+                        Box::new(Expr::Unary(
+                            UnaryOp::Not,
+                            Box::new(Expr::Flag(FlagName::Zero)),
+                        )),
+                        Box::new(Expr::Binary(
+                            BinaryOp::Ge,
+                            Box::new(Expr::Byte(Box::new(Expr::Binary(
+                                BinaryOp::Add,
+                                Box::new(Expr::Number(0x40)),
+                                Box::new(Expr::Register(RegisterName::IndexX)),
+                            )))),
+                            Box::new(Expr::Number(3)),
+                        )),
+                    )),
+                    ignore_count: 100,
+                },
+                Trap {
+                    cpu_trap: CpuTrap::ByInstruction(0x00),
+                    requested_outcome: TrapOutcomeStatus::StopAfter,
+                    condition: None,
+                    ignore_count: 0,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn round_trip() -> Result<(), CpuError> {
+        let state = sample_state();
+        let mut buf: Vec<u8> = vec![];
+        state.write_to(&mut buf)?;
+        assert_eq!(&buf[0..6], b"6502\x01\x00");
+
+        let restored = MachineState::read_from(&mut buf.as_slice())?;
+        assert_eq!(restored, state);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_foreign_file() {
+        let mut input: &[u8] = b"PK\x03\x04 not a state file";
+        assert_eq!(
+            MachineState::read_from(&mut input),
+            Err(CpuError::InvalidState("not a 6502 state file".to_string()))
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_version() {
+        let mut input: &[u8] = b"6502\x02\x00";
+        assert_eq!(
+            MachineState::read_from(&mut input),
+            Err(CpuError::InvalidState(
+                "unsupported state file version 2".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_file() -> Result<(), CpuError> {
+        let mut buf: Vec<u8> = vec![];
+        sample_state().write_to(&mut buf)?;
+        buf.truncate(100);
+        assert!(matches!(
+            MachineState::read_from(&mut buf.as_slice()),
+            Err(CpuError::InvalidState(_))
+        ));
+        Ok(())
+    }
+}
@@ -4,11 +4,10 @@ use crate::memory::Memory;
 pub trait StackPointer {
     fn get_sp(&self) -> Result<u16, CpuError>;
     fn set_sp(&mut self, value: u16) -> Result<(), CpuError>;
-    fn push_byte(&mut self, mem: &mut dyn Memory, value: u8) -> Result<(), CpuError>;
-    fn pop_byte(&mut self, mem: &dyn Memory) -> Result<u8, CpuError>;
-    fn push_word(&mut self, mem: &mut dyn Memory, value: u16) -> Result<(), CpuError>;
-    fn pop_word(&mut self, mem: &dyn Memory) -> Result<u16, CpuError>;
-    fn reset(&mut self) -> Result<(), CpuError>;
+    fn push_byte<M: Memory + ?Sized>(&mut self, mem: &mut M, value: u8) -> Result<(), CpuError>;
+    fn pop_byte<M: Memory + ?Sized>(&mut self, mem: &M) -> Result<u8, CpuError>;
+    fn push_word<M: Memory + ?Sized>(&mut self, mem: &mut M, value: u16) -> Result<(), CpuError>;
+    fn pop_word<M: Memory + ?Sized>(&mut self, mem: &M) -> Result<u16, CpuError>;
 }
 
 #[derive(Clone)]
@@ -39,7 +38,7 @@ impl StackPointer for StackPointerImpl {
         Ok(())
     }
 
-    fn push_byte(&mut self, mem: &mut dyn Memory, value: u8) -> Result<(), CpuError> {
+    fn push_byte<M: Memory + ?Sized>(&mut self, mem: &mut M, value: u8) -> Result<(), CpuError> {
         if self.sp == 0x00 {
             return Err(CpuError::StackOverflow);
         }
@@ -48,7 +47,7 @@ impl StackPointer for StackPointerImpl {
         Ok(())
     }
 
-    fn pop_byte(&mut self, mem: &dyn Memory) -> Result<u8, CpuError> {
+    fn pop_byte<M: Memory + ?Sized>(&mut self, mem: &M) -> Result<u8, CpuError> {
         if self.sp == 0xFF {
             return Err(CpuError::StackOverflow);
         }
@@ -57,7 +56,7 @@ impl StackPointer for StackPointerImpl {
         Ok(value)
     }
 
-    fn push_word(&mut self, mem: &mut dyn Memory, value: u16) -> Result<(), CpuError> {
+    fn push_word<M: Memory + ?Sized>(&mut self, mem: &mut M, value: u16) -> Result<(), CpuError> {
         if self.sp <= 0x01 {
             return Err(CpuError::StackOverflow);
         }
@@ -67,7 +66,7 @@ impl StackPointer for StackPointerImpl {
         Ok(())
     }
 
-    fn pop_word(&mut self, mem: &dyn Memory) -> Result<u16, CpuError> {
+    fn pop_word<M: Memory + ?Sized>(&mut self, mem: &M) -> Result<u16, CpuError> {
         if self.sp >= 0xFE {
             return Err(CpuError::StackOverflow);
         }
@@ -78,26 +77,11 @@ impl StackPointer for StackPointerImpl {
         self.sp = self.sp.wrapping_add(1);
         Ok(value)
     }
-
-    fn reset(&mut self) -> Result<(), CpuError> {
-        self.sp = 0xFF;
-        Ok(())
-    }
-}
-
-impl std::fmt::Debug for dyn StackPointer {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "StackPointer {{ sp: 0x01{:02X} }}",
-            (self.get_sp().unwrap() & 0xFF) as u8
-        )
-    }
 }
 
 impl std::fmt::Debug for StackPointerImpl {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "StackPointer {{ sp: 0x{:04X} }}", self.sp)
+        write!(f, "StackPointer {{ sp: 0x{:04X} }}", self.sp_as_u16())
     }
 }
 
@@ -119,7 +103,7 @@ mod tests {
             fn get_size(&self) -> usize;
             fn load_program(&mut self, start_addr: u16, program: &[u8]) -> Result<(), CpuError>;
             fn write_zero_page_word(&mut self, address: u8, value: u16) -> Result<(), CpuError>;
-            fn add_readonly(&mut self, range: ops::Range<u16>) -> Result<(), CpuError>;
+            fn add_readonly(&mut self, range: ops::RangeInclusive<u16>) -> Result<(), CpuError>;
             fn clear_readonly_ranges(&mut self);
         }
     }
@@ -187,15 +171,6 @@ mod tests {
         Ok(())
     }
 
-    #[test]
-    fn reset() -> Result<(), CpuError> {
-        let mut sp = StackPointerImpl::new();
-        sp.sp = 0xFF;
-        sp.reset()?;
-        assert_eq!(0x01FF, sp.get_sp()?);
-        Ok(())
-    }
-
     #[test]
     fn push_byte() -> Result<(), CpuError> {
         let mut sp = StackPointerImpl::new();
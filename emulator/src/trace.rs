@@ -0,0 +1,95 @@
+use std::fmt;
+use std::io::Write;
+use std::ops::RangeInclusive;
+
+use crate::CpuError;
+use crate::condition::Registers;
+
+/// Writes a line for each executed instruction, in the column layout of nestest.log:
+/// PC, instruction bytes, disassembly, registers and the cycle count before the instruction.
+pub struct Tracer {
+    writer: Box<dyn Write>,
+    // only instructions at these addresses are traced, all if empty:
+    ranges: Vec<RangeInclusive<u16>>,
+}
+
+impl Tracer {
+    pub fn new(writer: Box<dyn Write>, ranges: Vec<RangeInclusive<u16>>) -> Tracer {
+        Tracer { writer, ranges }
+    }
+
+    pub fn is_traced(&self, pc: u16) -> bool {
+        self.ranges.is_empty() || self.ranges.iter().any(|range| range.contains(&pc))
+    }
+
+    pub fn write_line(&mut self, line: &str) -> Result<(), CpuError> {
+        writeln!(self.writer, "{}", line).map_err(|e| CpuError::TraceOutput(e.to_string()))
+    }
+}
+
+impl fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Tracer {{ ranges: {:?} }}", self.ranges)
+    }
+}
+
+// e.g. "C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD CYC:7"
+pub fn format_line(registers: &Registers, bytes: &[u8], instruction: &str, cycles: u64) -> String {
+    let bytes = bytes
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<String>>()
+        .join(" ");
+    format!(
+        "{:04X}  {:<8}  {:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+        registers.program_counter,
+        bytes,
+        instruction,
+        registers.accumulator,
+        registers.index_x,
+        registers.index_y,
+        registers.status,
+        registers.stack_pointer,
+        cycles
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_line_in_nestest_columns() {
+        let registers = Registers {
+            accumulator: 0x00,
+            index_x: 0x01,
+            index_y: 0xFF,
+            stack_pointer: 0xFD,
+            program_counter: 0xC000,
+            status: 0x24,
+        };
+        assert_eq!(
+            format_line(&registers, &[0x4C, 0xF5, 0xC5], "JMP $C5F5", 7),
+            "C000  4C F5 C5  JMP $C5F5                       A:00 X:01 Y:FF P:24 SP:FD CYC:7"
+        );
+        assert_eq!(
+            format_line(&registers, &[0xEA], "NOP", 1234),
+            "C000  EA        NOP                             A:00 X:01 Y:FF P:24 SP:FD CYC:1234"
+        );
+    }
+
+    #[test]
+    fn is_traced_in_ranges() {
+        let tracer = Tracer::new(Box::new(std::io::sink()), vec![]);
+        assert!(tracer.is_traced(0xFFFF));
+        let tracer = Tracer::new(
+            Box::new(std::io::sink()),
+            vec![0x0200..=0x02FF, 0x0400..=0x0400],
+        );
+        assert!(tracer.is_traced(0x0200));
+        assert!(tracer.is_traced(0x02FF));
+        assert!(tracer.is_traced(0x0400));
+        assert!(!tracer.is_traced(0x0300));
+        assert!(!tracer.is_traced(0xC000));
+    }
+}
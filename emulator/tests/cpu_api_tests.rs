@@ -1,4 +1,11 @@
-use mos6502_emulator::{CpuError, CpuType, create_cpu};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use mos6502_emulator::{
+    CpuError, CpuOptions, CpuType, Device, ExecutionError, IllegalOpcodePolicy, MemoryBus,
+    StopReason, TrapContext, WatchKind, Watchpoint, create_cpu, create_cpu_with_bus,
+    create_cpu_with_bus_and_options,
+};
 
 #[test]
 fn create_default_cpu() -> Result<(), CpuError> {
@@ -34,11 +41,229 @@ fn run_simple_program() -> Result<(), CpuError> {
 fn run_empty_program_stops_at_irq() -> Result<(), CpuError> {
     let mut cpu = create_cpu(CpuType::MOS6502).unwrap();
     let reg_snapshot = cpu.run(None)?;
-    // PC should point at IRQ vector:
-    assert_eq!(reg_snapshot.program_counter, 0xFFFE);
+    // PC should point at the IRQ handler, read from the zeroed IRQ vector:
+    assert_eq!(reg_snapshot.program_counter, 0x0000);
     // executed a single BRK since all memory is zeroed out:
     assert_eq!(reg_snapshot.accumulated_instructions, 1);
     // BRK takes 7 cycles:
     assert_eq!(reg_snapshot.accumulated_cycles, 7);
     Ok(())
 }
+
+#[test]
+fn brk_without_trap_runs_irq_handler() -> Result<(), CpuError> {
+    let mut cpu = create_cpu(CpuType::MOS6502)?;
+    cpu.set_brk_trap(false);
+    // BRK with a signature byte, then LDX #$07
+    cpu.load_program(0x0600, &[0x00, 0xFF, 0xA2, 0x07], false)?;
+    // handler: LDA #$42, RTI
+    cpu.load_program(0x0700, &[0xA9, 0x42, 0x40], false)?;
+    cpu.load_program(0xFFFE, &[0x00, 0x07], false)?;
+    cpu.set_pc(0x0600)?;
+
+    let (reason, snapshot) = cpu.run_for_instructions(4)?;
+    assert_eq!(reason, StopReason::InstructionLimit);
+    assert_eq!(snapshot.program_counter, 0x0604);
+    assert_eq!(snapshot.accumulator, 0x42);
+    assert_eq!(snapshot.x_register, 0x07);
+    Ok(())
+}
+
+#[test]
+fn bounded_runs_of_endless_loop() -> Result<(), CpuError> {
+    let mut cpu = create_cpu(CpuType::MOS6502)?;
+    cpu.load_program(0x0600, &[0x4C, 0x00, 0x06], true)?; // JMP $0600
+    cpu.set_pc(0x0600)?;
+
+    let (reason, snapshot) = cpu.run_for_instructions(100)?;
+    assert_eq!(reason, StopReason::InstructionLimit);
+    assert_eq!(snapshot.accumulated_instructions, 100);
+
+    let (reason, snapshot) = cpu.run_for_cycles(30)?;
+    assert_eq!(reason, StopReason::CycleLimit);
+    assert_eq!(snapshot.accumulated_cycles, 330);
+
+    let deadline = std::time::Instant::now() + std::time::Duration::from_millis(5);
+    let (reason, _) = cpu.run_until(deadline)?;
+    assert_eq!(reason, StopReason::Deadline);
+    assert!(std::time::Instant::now() >= deadline);
+    Ok(())
+}
+
+#[test]
+fn watchpoint_stops_before_stack_write() -> Result<(), CpuError> {
+    let mut cpu = create_cpu(CpuType::MOS6502)?;
+    // LDA #$42, PHA, BRK
+    cpu.load_program(0x0600, &[0xA9, 0x42, 0x48, 0x00], true)?;
+    cpu.set_pc(0x0600)?;
+    cpu.add_watchpoint(Watchpoint {
+        range: 0x0100..=0x01FF,
+        kind: WatchKind::Write,
+        value: None,
+        stop_before: true,
+    });
+
+    let (reason, snapshot) = cpu.run_limited(Default::default())?;
+    let StopReason::Watchpoint(hit) = reason else {
+        panic!("unexpected stop: {}", reason);
+    };
+    assert_eq!(hit.pc, 0x0602);
+    assert_eq!(hit.access.address, 0x01FD);
+    assert_eq!(snapshot.program_counter, 0x0602);
+    assert_eq!(snapshot.stack_pointer, 0x01FD);
+    assert_eq!(cpu.peek(0x01FD)?, 0x00);
+
+    // the PHA is executed when resuming, then BRK's pushes stop again:
+    let (reason, snapshot) = cpu.run_limited(Default::default())?;
+    assert!(matches!(reason, StopReason::Watchpoint(hit) if hit.pc == 0x0603));
+    assert_eq!(snapshot.stack_pointer, 0x01FC);
+    assert_eq!(cpu.peek(0x01FD)?, 0x42);
+    Ok(())
+}
+
+#[test]
+fn execution_error_reports_faulting_instruction() -> Result<(), CpuError> {
+    let mut cpu = create_cpu(CpuType::MOS6502)?;
+    cpu.load_program(
+        0x0600,
+        &[
+            0xA2, 0xFF, // LDX #$FF
+            0x9A, // TXS
+            0x68, // PLA from an empty stack
+        ],
+        true,
+    )?;
+    let err = cpu.run(Some(0x0600)).unwrap_err();
+    let CpuError::Execution(exec) = err else {
+        panic!("expected an execution error, got {:?}", err);
+    };
+    let ExecutionError {
+        cause,
+        pc,
+        opcode_bytes,
+        snapshot,
+    } = *exec;
+    assert_eq!(cause, CpuError::StackOverflow);
+    assert_eq!(pc, 0x0603);
+    assert_eq!(opcode_bytes, vec![0x68]);
+    assert_eq!(snapshot.x_register, 0xFF);
+    assert_eq!(snapshot.accumulated_instructions, 2);
+    Ok(())
+}
+
+#[test]
+fn step_back_after_run() -> Result<(), CpuError> {
+    let mut cpu = create_cpu(CpuType::MOS6502)?;
+    cpu.set_history_limit(100);
+    cpu.load_program(
+        0x0600,
+        &[
+            0xA9, 0x42, // LDA #$42
+            0x85, 0x0F, // STA $0F
+            0x00, // BRK
+        ],
+        true,
+    )?;
+    cpu.run(Some(0x0600))?;
+    // undo BRK and STA:
+    cpu.step_back()?;
+    let snapshot = cpu.step_back()?.unwrap();
+    assert_eq!(snapshot.program_counter, 0x0602);
+    assert_eq!(snapshot.accumulator, 0x42);
+    assert_eq!(cpu.get_byte_at(0x000F)?, 0x00);
+    Ok(())
+}
+
+#[test]
+fn trap_handler_emulates_rom_routine() -> Result<(), CpuError> {
+    const CHROUT: u16 = 0xFFD2;
+    let output = Rc::new(RefCell::new(String::new()));
+    let mut cpu = create_cpu(CpuType::MOS6502)?;
+    let chrout_output = output.clone();
+    cpu.add_trap_handler(
+        CHROUT,
+        Box::new(move |ctx: &mut TrapContext| {
+            chrout_output.borrow_mut().push(ctx.accumulator as char);
+            // clear carry to signal success:
+            ctx.status &= !0x01;
+            Ok(ctx.result())
+        }),
+    );
+    cpu.load_program(
+        0x0600,
+        &[
+            0x38, // SEC
+            0xA9, 0x48, // LDA #'H'
+            0x20, 0xD2, 0xFF, // JSR CHROUT
+            0xA9, 0x49, // LDA #'I'
+            0x20, 0xD2, 0xFF, // JSR CHROUT
+            0x00, // BRK
+        ],
+        true,
+    )?;
+    let snapshot = cpu.run(Some(0x0600))?;
+    assert_eq!(output.borrow().as_str(), "HI");
+    assert_eq!(snapshot.status & 0x01, 0);
+    // SEC, 2x LDA, JSR and handler, BRK:
+    assert_eq!(snapshot.accumulated_instructions, 8);
+    assert_eq!(snapshot.stack_pointer, 0x01FD - 3);
+    Ok(())
+}
+
+// collects all bytes written to its single register
+struct Output {
+    written: Vec<u8>,
+}
+
+impl Device for Output {
+    fn read(&mut self, _offset: u16) -> u8 {
+        0
+    }
+
+    fn write(&mut self, _offset: u16, value: u8) {
+        self.written.push(value);
+    }
+
+    fn peek(&self, _offset: u16) -> u8 {
+        0
+    }
+}
+
+#[test]
+fn run_program_with_device() -> Result<(), CpuError> {
+    let output = Rc::new(RefCell::new(Output { written: vec![] }));
+    let mut bus = MemoryBus::new();
+    bus.attach(0xD000..=0xD000, output.clone())?;
+
+    let mut cpu = create_cpu_with_bus(CpuType::MOS6502, bus)?;
+    cpu.load_program(
+        0x0600,
+        &[
+            0xA9, 0x48, // LDA #'H'
+            0x8D, 0x00, 0xD0, // STA $D000
+            0xA9, 0x69, // LDA #'i'
+            0x8D, 0x00, 0xD0, // STA $D000
+            0x00, // BRK
+        ],
+        true,
+    )?;
+    cpu.run(Some(0x0600))?;
+    assert_eq!(output.borrow().written, b"Hi");
+    Ok(())
+}
+
+#[test]
+fn illegal_opcode_policy_with_device_bus() -> Result<(), CpuError> {
+    let options = CpuOptions {
+        illegal_opcodes: IllegalOpcodePolicy::Error,
+    };
+    let mut cpu = create_cpu_with_bus_and_options(CpuType::MOS6502, MemoryBus::new(), options)?;
+    // LDA #$42, then data:
+    cpu.load_program(0x0600, &[0xA9, 0x42, 0x02], true)?;
+    let Err(CpuError::Execution(exec)) = cpu.run(Some(0x0600)) else {
+        panic!("expected an execution error");
+    };
+    assert_eq!(exec.cause, CpuError::InvalidOpcode(0x02));
+    assert_eq!(exec.pc, 0x0602);
+    Ok(())
+}
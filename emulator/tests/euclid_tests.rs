@@ -30,13 +30,15 @@ fn run_gcd_euclid() -> Result<(), CpuError> {
     cpu.set_byte_at(0x0041, 49)?; // VAR_B
 
     let snapshot = cpu.run(Some(0x0200))?;
-    assert_eq!(snapshot.program_counter, 0xFFFE);
+    // stopped after BRK, at the handler address in the zeroed IRQ vector:
+    assert_eq!(snapshot.program_counter, 0x0000);
     // assert loop termination due to a == b:
     assert_eq!(cpu.get_byte_at(0x0040)?, cpu.get_byte_at(0x0041)?);
     // read back transferred byte from zero page:
     assert_eq!(cpu.get_byte_at(0x0040)?, 7);
     assert_eq!(snapshot.accumulated_instructions, 72);
-    assert_eq!(snapshot.accumulated_cycles, 191);
+    // 191 base cycles, plus 4 taken branches (3x BMI swap, 1x BEQ done) within the same page:
+    assert_eq!(snapshot.accumulated_cycles, 195);
     // clock speed is returned in Hz, so even a slow machine should be faster than 1kHz:
     assert!(snapshot.approximate_clock_speed > 1000.0);
     println!(